@@ -18,7 +18,7 @@
     defs::*,
     node::{Node, NodeRef},
     process::Process,
-    DArc,
+    trace, DArc,
 };
 
 #[derive(Default)]
@@ -35,6 +35,8 @@ pub(crate) struct AllocationInfo {
     pub(crate) oneway_node: Option<DArc<Node>>,
     /// Zero the data in the buffer on free.
     pub(crate) clear_on_free: bool,
+    /// Checksum of the contents of the buffer, when checksums are enabled for the process.
+    pub(crate) checksum: Option<u64>,
     /// List of files embedded in this transaction.
     file_list: FileList,
 }
@@ -124,6 +126,51 @@ pub(crate) fn fill_zero(&self) -> Result {
         unsafe { self.process.pages.fill_zero(self.offset, self.size) }
     }
 
+    /// Computes a checksum of the contents of the buffer. This reads the whole buffer, so it is
+    /// only used for debugging.
+    fn compute_checksum(&self) -> Result<u64> {
+        const WORD: usize = size_of::<u64>();
+        // FNV-1a, over words and then the remaining bytes.
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut offset = 0;
+        while offset + WORD <= self.size {
+            hash = (hash ^ self.read::<u64>(offset)?).wrapping_mul(0x0100_0000_01b3);
+            offset += WORD;
+        }
+        while offset < self.size {
+            hash = (hash ^ self.read::<u8>(offset)? as u64).wrapping_mul(0x0100_0000_01b3);
+            offset += 1;
+        }
+        Ok(hash)
+    }
+
+    /// Stores a checksum of the contents of the buffer, to be verified when it is freed.
+    pub(crate) fn set_info_checksum(&mut self) -> Result {
+        let checksum = self.compute_checksum()?;
+        self.get_or_init_info().checksum = Some(checksum);
+        Ok(())
+    }
+
+    /// Warns if the buffer no longer matches its stored checksum.
+    fn verify_checksum(&self, expected: Option<u64>) {
+        let expected = match expected {
+            Some(expected) => expected,
+            None => return,
+        };
+        match self.compute_checksum() {
+            Ok(actual) if actual != expected => pr_warn!(
+                "{}: buffer at offset {} of size {} was modified, checksum {:#x} != {:#x}\n",
+                self.process.task.pid(),
+                self.offset,
+                self.size,
+                actual,
+                expected
+            ),
+            Ok(_) => {}
+            Err(err) => pr_warn!("Failed to verify buffer checksum: {:?}\n", err),
+        }
+    }
+
     pub(crate) fn keep_alive(mut self) {
         self.process
             .buffer_make_freeable(self.offset, self.allocation_info.take());
@@ -146,6 +193,14 @@ pub(crate) fn set_info_oneway_node(&mut self, oneway_node: DArc<Node>) {
         self.get_or_init_info().oneway_node = Some(oneway_node);
     }
 
+    /// Forget the node of a oneway transaction, so that freeing this buffer does not start the
+    /// next oneway transaction queued on it.
+    pub(crate) fn clear_info_oneway_node(&mut self) {
+        if let Some(info) = self.allocation_info.as_mut() {
+            info.oneway_node = None;
+        }
+    }
+
     pub(crate) fn set_info_clear_on_drop(&mut self) {
         self.get_or_init_info().clear_on_free = true;
     }
@@ -211,6 +266,13 @@ pub(crate) fn translate_fds(&mut self) -> Result<TranslatedFds> {
             }
         }
 
+        // Writing the fds modified the buffer, so check it first and then update the checksum.
+        let checksum = self.allocation_info.as_ref().and_then(|info| info.checksum);
+        if checksum.is_some() && !reservations.is_empty() {
+            self.verify_checksum(checksum);
+            self.set_info_checksum()?;
+        }
+
         Ok(TranslatedFds {
             reservations,
             close_on_free: FdsCloseOnFree(close_on_free),
@@ -235,6 +297,8 @@ fn drop(&mut self) {
         }
 
         if let Some(mut info) = self.allocation_info.take() {
+            self.verify_checksum(info.checksum);
+
             if let Some(oneway_node) = info.oneway_node.as_ref() {
                 oneway_node.pending_oneway_finished();
             }
@@ -272,6 +336,7 @@ fn drop(&mut self) {
             }
         }
 
+        trace::trace_buffer_release(self.process.task.pid(), self.offset, self.size);
         self.process.buffer_raw_free(self.ptr);
     }
 }
@@ -11,7 +11,9 @@
     task::Kuid,
 };
 
-use crate::{error::BinderError, node::NodeRef, process::Process};
+use crate::{defs::BINDER_UNDERFLOW_WARN, error::BinderError, node::NodeRef, process::Process};
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 
 // This module defines the global variable containing the list of contexts. Since the
 // `kernel::sync` bindings currently don't support mutexes in globals, we use a temporary
@@ -115,6 +117,37 @@ pub(crate) struct Context {
     pub(crate) name: CString,
     #[pin]
     links: ListLinks,
+    /// Allocating a buffer larger than this many bytes prints a warning. Zero disables the warning.
+    pub(crate) large_txn_warn_size: AtomicUsize,
+    /// A process reaching this many handles prints a warning. Zero disables the warning.
+    pub(crate) handle_warn_count: AtomicU32,
+    /// The maximum number of processes that may use this context. Zero means unlimited.
+    pub(crate) max_procs: AtomicU32,
+    /// The initial `max_threads` of processes using this context.
+    pub(crate) default_max_threads: AtomicU32,
+    /// The `flat_binder_object` flags that may be set on binder objects sent in transactions.
+    pub(crate) allowed_node_flags: AtomicU32,
+    /// Whether `mmap` fails for processes that have not set `max_threads`.
+    pub(crate) mmap_requires_max_threads: AtomicBool,
+    /// Whether oneway transactions are processed at no more than the priority of their sender.
+    pub(crate) cap_async_priority: AtomicBool,
+    /// The maximum number of synchronous transactions in a chain. Zero means unlimited.
+    pub(crate) max_chain_len: AtomicU32,
+    /// What happens when the count of outstanding transactions of a process underflows. One of
+    /// the `BINDER_UNDERFLOW_*` constants.
+    pub(crate) underflow_policy: AtomicU32,
+    /// The maximum nesting depth of `BINDER_TYPE_PTR` objects in a transaction, counting a buffer
+    /// without a parent as depth one. Zero means unlimited.
+    pub(crate) max_buffer_depth: AtomicU32,
+    /// The maximum total size of the buffer mappings of the processes using this context. Zero
+    /// means unlimited.
+    pub(crate) max_mapped_bytes: AtomicUsize,
+    /// The total size of the buffer mappings of the processes using this context.
+    mapped_bytes: AtomicUsize,
+    /// The number of transactions submitted in this context, not counting replies, and the
+    /// number of bytes of data and offsets that they carried.
+    pub(crate) txn_count: AtomicU64,
+    pub(crate) txn_bytes: AtomicU64,
 }
 
 kernel::list::impl_has_list_links! {
@@ -135,6 +168,20 @@ pub(crate) fn new(name: &CStr) -> Result<Arc<Self>> {
         let list_ctx = ListArc::pin_init(pin_init!(Context {
             name,
             links <- ListLinks::new(),
+            large_txn_warn_size: AtomicUsize::new(0),
+            handle_warn_count: AtomicU32::new(0),
+            max_procs: AtomicU32::new(0),
+            default_max_threads: AtomicU32::new(0),
+            allowed_node_flags: AtomicU32::new(u32::MAX),
+            mmap_requires_max_threads: AtomicBool::new(false),
+            cap_async_priority: AtomicBool::new(false),
+            max_chain_len: AtomicU32::new(0),
+            underflow_policy: AtomicU32::new(BINDER_UNDERFLOW_WARN),
+            max_buffer_depth: AtomicU32::new(0),
+            max_mapped_bytes: AtomicUsize::new(0),
+            mapped_bytes: AtomicUsize::new(0),
+            txn_count: AtomicU64::new(0),
+            txn_bytes: AtomicU64::new(0),
             manager <- kernel::new_mutex!(Manager {
                 all_procs: List::new(),
                 node: None,
@@ -157,12 +204,18 @@ pub(crate) fn deregister(&self) {
         unsafe { CONTEXTS.lock().list.remove(self) };
     }
 
-    pub(crate) fn register_process(self: &Arc<Self>, proc: ListArc<Process>) {
+    pub(crate) fn register_process(self: &Arc<Self>, proc: ListArc<Process>) -> Result {
         if !Arc::ptr_eq(self, &proc.ctx) {
             pr_err!("Context::register_process called on the wrong context.");
-            return;
+            return Err(EINVAL);
+        }
+        let mut manager = self.manager.lock();
+        let max_procs = self.max_procs.load(Ordering::Relaxed) as usize;
+        if max_procs != 0 && manager.all_procs.iter().count() >= max_procs {
+            return Err(EBUSY);
         }
-        self.manager.lock().all_procs.push_back(proc);
+        manager.all_procs.push_back(proc);
+        Ok(())
     }
 
     pub(crate) fn deregister_process(self: &Arc<Self>, proc: &Process) {
@@ -210,6 +263,28 @@ pub(crate) fn get_manager_node(&self, strong: bool) -> Result<NodeRef, BinderErr
             .map_err(BinderError::from)
     }
 
+    /// Accounts for a new buffer mapping of `size` bytes, failing with `ENOMEM` if it would exceed
+    /// `max_mapped_bytes`.
+    pub(crate) fn reserve_mapped_bytes(&self, size: usize) -> Result {
+        let max = self.max_mapped_bytes.load(Ordering::Relaxed);
+        self.mapped_bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |total| {
+                let new_total = total.checked_add(size)?;
+                if max != 0 && new_total > max {
+                    None
+                } else {
+                    Some(new_total)
+                }
+            })
+            .map(|_| ())
+            .map_err(|_| ENOMEM)
+    }
+
+    /// Releases bytes previously accounted for with `reserve_mapped_bytes`.
+    pub(crate) fn release_mapped_bytes(&self, size: usize) {
+        self.mapped_bytes.fetch_sub(size, Ordering::Relaxed);
+    }
+
     pub(crate) fn for_each_proc<F>(&self, mut func: F)
     where
         F: FnMut(&Process),
@@ -2,11 +2,13 @@
 
 // Copyright (C) 2024 Google LLC.
 
-use core::mem::MaybeUninit;
+use core::mem::{size_of, MaybeUninit};
 use core::ops::{Deref, DerefMut};
 use kernel::{
     bindings::{self, *},
+    prelude::*,
     types::{AsBytes, FromBytes},
+    uaccess::UserSlice,
 };
 
 macro_rules! pub_no_prefix {
@@ -73,6 +75,19 @@ macro_rules! pub_no_prefix {
     FLAT_BINDER_FLAG_TXN_SECURITY_CTX
 );
 
+/// Driver-specific `flat_binder_object` flag. When set on a node, a oneway transaction to the node
+/// that does not fit in the target's buffer space drops the oldest oneway transaction that is still
+/// queued on the node, instead of failing.
+pub(crate) const FLAT_BINDER_FLAG_ONEWAY_DROP_OLDEST: u32 = 0x0100_0000;
+
+/// Driver-specific `flat_binder_object` flag. When set on a node, oneway transactions to the node
+/// are rejected.
+pub(crate) const FLAT_BINDER_FLAG_SYNC_ONLY: u32 = 0x0200_0000;
+
+/// Driver-specific `flat_binder_object` flag. When set on a node, transactions to the node that
+/// are queued on the process are delivered before the other queued work of the process.
+pub(crate) const FLAT_BINDER_FLAG_HIGH_PRIORITY: u32 = 0x0400_0000;
+
 pub_no_prefix!(
     transaction_flags_,
     TF_ONE_WAY,
@@ -81,6 +96,20 @@ macro_rules! pub_no_prefix {
     TF_UPDATE_TXN
 );
 
+/// Driver-specific transaction flag. The receiving thread does not inherit the priority of the
+/// sender. Like for oneway transactions, it runs at the priority of the node instead.
+pub(crate) const TF_NO_PRIO_INHERIT: u32 = 0x0100_0000;
+
+/// Driver-specific transaction flag. The receiving thread keeps its scheduler policy. It only
+/// inherits the priority level when its policy is of the same class (real-time or fair) as the
+/// one it would otherwise inherit, and keeps its current priority otherwise.
+pub(crate) const TF_KEEP_SCHED_POLICY: u32 = 0x0400_0000;
+
+/// Driver-specific transaction flag for oneway transactions. If the target process has no buffer
+/// space for the transaction, it is silently dropped and the sender gets `BR_TRANSACTION_COMPLETE`
+/// as if it had been delivered.
+pub(crate) const TF_DROP_IF_FULL: u32 = 0x0200_0000;
+
 pub(crate) use bindings::{
     BINDER_TYPE_BINDER, BINDER_TYPE_FD, BINDER_TYPE_FDA, BINDER_TYPE_HANDLE, BINDER_TYPE_PTR,
     BINDER_TYPE_WEAK_BINDER, BINDER_TYPE_WEAK_HANDLE,
@@ -183,3 +212,885 @@ pub(crate) fn new(id: u32, command: u32, param: i32) -> Self {
         }))
     }
 }
+
+// Driver-specific ioctls.
+//
+// These commands are not part of the upstream binder uapi, so they are defined here instead of
+// being generated from the uapi header. They use command numbers well above the range used by the
+// upstream protocol to avoid clashing with future additions to it.
+const BINDER_IOC_MAGIC: u32 = b'b' as u32;
+
+macro_rules! decl_driver_struct {
+    (
+        $(#[$meta:meta])*
+        $newname:ident {
+            $($(#[$fmeta:meta])* $field:ident: $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Copy, Clone, Default)]
+        #[repr(C)]
+        pub(crate) struct $newname {
+            $($(#[$fmeta])* pub(crate) $field: $ty,)*
+        }
+
+        // SAFETY: This macro is only used with structs whose fields are integers (or arrays of
+        // integers) laid out so that there is no padding.
+        unsafe impl FromBytes for $newname {}
+        unsafe impl AsBytes for $newname {}
+    };
+}
+
+/// Sets the buffer usage threshold (in percent of the mapping) above which `poll` reports
+/// `POLLPRI`. Zero disables the notification.
+pub(crate) const BINDER_SET_BUFFER_PRESSURE_THRESHOLD: u32 =
+    kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 64);
+
+/// The maximum length of a context name reported by `BINDER_GET_PID_PRESENCE` and
+/// `BINDER_GET_CONTEXT_NAME`, including the nul terminator. Longer names are truncated.
+pub(crate) const BINDER_CONTEXT_NAME_MAX: usize = 32;
+/// The maximum number of context names reported by `BINDER_GET_PID_PRESENCE`.
+pub(crate) const BINDER_PID_PRESENCE_MAX_CONTEXTS: usize = 8;
+
+decl_driver_struct!(
+    /// Argument of `BINDER_GET_PID_PRESENCE`.
+    BinderPidPresence {
+        /// The pid to look up. Set by userspace.
+        pid: i32,
+        /// The number of contexts that the pid has opened. Zero means that the pid does not use
+        /// binder. If this is larger than `BINDER_PID_PRESENCE_MAX_CONTEXTS`, only the first names
+        /// are reported.
+        num_contexts: u32,
+        /// The nul-terminated names of the contexts.
+        names: [[u8; BINDER_CONTEXT_NAME_MAX]; BINDER_PID_PRESENCE_MAX_CONTEXTS],
+    }
+);
+
+/// Reports whether a pid has opened any binder context, and which ones. Requires
+/// `CAP_SYS_ADMIN`.
+pub(crate) const BINDER_GET_PID_PRESENCE: u32 =
+    kernel::ioctl::_IOWR::<BinderPidPresence>(BINDER_IOC_MAGIC, 65);
+
+decl_driver_struct!(
+    /// Argument of `BINDER_GET_MEMORY_SUMMARY`. Totals over all processes in all contexts.
+    BinderMemorySummary {
+        /// The total size of the buffer mappings.
+        mapped_bytes: u64,
+        /// The total number of bytes in reserved or allocated buffers.
+        allocated_bytes: u64,
+        /// The total number of pages backing reserved or allocated buffers.
+        pages_in_use: u64,
+    }
+);
+
+/// Reports binder-wide memory usage. Requires `CAP_SYS_ADMIN`.
+pub(crate) const BINDER_GET_MEMORY_SUMMARY: u32 =
+    kernel::ioctl::_IOWR::<BinderMemorySummary>(BINDER_IOC_MAGIC, 66);
+
+/// Sets the buffer size (in bytes) above which allocating a transaction buffer in this context
+/// prints a warning. Zero disables the warning. Requires `CAP_SYS_ADMIN` or being the context
+/// manager.
+pub(crate) const BINDER_SET_LARGE_TRANSACTION_WARN_SIZE: u32 =
+    kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 67);
+
+/// Enables or disables the collection of optional statistics for the calling process. They are
+/// reported in the `stats` debugfs file.
+pub(crate) const BINDER_ENABLE_STATS: u32 = kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 68);
+
+/// The maximum number of entries returned by a single call to an ioctl using `BinderArray`.
+pub(crate) const BINDER_ARRAY_MAX_ENTRIES: u32 = 256;
+
+decl_driver_struct!(
+    /// Header of ioctls that return a bounded array of entries.
+    ///
+    /// The entries are sorted by a key, and only entries whose key is at least `start` are
+    /// returned. At most `capacity` entries are written to `buffer`, and `count` is set to the
+    /// number of entries written. When `count` is equal to `capacity`, there may be more entries,
+    /// which can be retrieved by calling the ioctl again with `start` set to one more than the key
+    /// of the last entry.
+    BinderArray {
+        buffer: u64,
+        start: u64,
+        capacity: u32,
+        count: u32,
+    }
+);
+
+impl BinderArray {
+    /// The maximum number of entries to write to `buffer`.
+    pub(crate) fn max_entries(&self) -> usize {
+        u32::min(self.capacity, BINDER_ARRAY_MAX_ENTRIES) as usize
+    }
+
+    /// Writes the entries to `buffer`, and sets `count` accordingly.
+    pub(crate) fn write_entries<T: AsBytes>(&mut self, entries: &[T]) -> Result {
+        let len = entries.len() * size_of::<T>();
+        let mut writer = UserSlice::new(self.buffer as _, len).writer();
+        for entry in entries {
+            writer.write(entry)?;
+        }
+        self.count = entries.len() as u32;
+        Ok(())
+    }
+}
+
+decl_driver_struct!(
+    /// Entry of `BINDER_GET_NODE_DEBUG_IDS`. The key is `ptr`.
+    BinderNodeDebugId {
+        ptr: u64,
+        debug_id: u64,
+    }
+);
+
+/// Lists the debug ids of the nodes of the calling process, using the `BinderArray` convention.
+pub(crate) const BINDER_GET_NODE_DEBUG_IDS: u32 =
+    kernel::ioctl::_IOWR::<BinderArray>(BINDER_IOC_MAGIC, 69);
+
+/// Sets the number of handles at which a process in this context prints a warning, to notice
+/// handle leaks before the handle space runs out. Zero disables the warning. Requires
+/// `CAP_SYS_ADMIN` or being the context manager.
+pub(crate) const BINDER_SET_HANDLE_WARN_COUNT: u32 =
+    kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 70);
+
+decl_driver_struct!(
+    /// Entry of `BINDER_GET_DEATH_NOTIFICATIONS`. The key is `handle`.
+    BinderDeathNotificationInfo {
+        handle: u32,
+        reserved: u32,
+        cookie: u64,
+    }
+);
+
+/// Lists the handles of the calling process that have a death notification registered, using the
+/// `BinderArray` convention.
+pub(crate) const BINDER_GET_DEATH_NOTIFICATIONS: u32 =
+    kernel::ioctl::_IOWR::<BinderArray>(BINDER_IOC_MAGIC, 71);
+
+/// Enables or disables logging of every ioctl made on this binder fd. Requires `CAP_SYS_ADMIN`.
+pub(crate) const BINDER_ENABLE_IOCTL_LOG: u32 = kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 72);
+
+/// When enabled, transactions from the calling process to handle zero fail with `EINVAL` unless
+/// the process holds a reference to the context manager, to catch uninitialized handles.
+pub(crate) const BINDER_SET_STRICT_HANDLES: u32 = kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 73);
+
+/// The thread is not blocked in the driver.
+pub(crate) const BINDER_THREAD_STATE_RUNNING: u32 = 0;
+/// The thread is waiting for work from the process queue.
+pub(crate) const BINDER_THREAD_STATE_IDLE: u32 = 1;
+/// The thread is waiting for work on its own queue.
+pub(crate) const BINDER_THREAD_STATE_WAITING: u32 = 2;
+/// The thread uses `poll` to wait for work, and is not in a transaction.
+pub(crate) const BINDER_THREAD_STATE_POLL: u32 = 3;
+/// The thread is handling an incoming transaction.
+pub(crate) const BINDER_THREAD_STATE_SERVICING: u32 = 4;
+/// The thread sent a transaction and is waiting for the reply.
+pub(crate) const BINDER_THREAD_STATE_AWAITING_REPLY: u32 = 5;
+/// The number of `BINDER_THREAD_STATE_*` constants.
+pub(crate) const BINDER_THREAD_STATE_COUNT: usize = 6;
+
+decl_driver_struct!(
+    /// Entry of `BINDER_GET_THREAD_STATES`. The key is `tid`.
+    BinderThreadState {
+        tid: u32,
+        /// One of the `BINDER_THREAD_STATE_*` constants.
+        state: u32,
+    }
+);
+
+/// Lists the threads of the calling process and what they are doing, using the `BinderArray`
+/// convention.
+pub(crate) const BINDER_GET_THREAD_STATES: u32 =
+    kernel::ioctl::_IOWR::<BinderArray>(BINDER_IOC_MAGIC, 74);
+
+/// Sets the lowest handle given out by the calling process for nodes other than the context
+/// manager. Defaults to one, and can only be changed while the process has no handles.
+pub(crate) const BINDER_SET_HANDLE_BASE: u32 = kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 75);
+
+decl_driver_struct!(
+    /// Argument of `BINDER_GET_LAST_ERROR`.
+    BinderLastError {
+        /// Incremented for every error. Zero if there has not been any error.
+        seq: u64,
+        /// When the error happened, in nanoseconds of `CLOCK_MONOTONIC`.
+        time_ns: u64,
+        /// The `BR_*` code returned to the sender.
+        command: u32,
+        /// The errno that caused the error, or zero if there is none.
+        param: i32,
+        /// The target handle of the failed transaction. Not meaningful for replies.
+        handle: u32,
+        /// The thread that sent the failed transaction.
+        tid: i32,
+    }
+);
+
+/// Returns the last error encountered when sending a transaction or reply from any thread of the
+/// calling process.
+pub(crate) const BINDER_GET_LAST_ERROR: u32 =
+    kernel::ioctl::_IOWR::<BinderLastError>(BINDER_IOC_MAGIC, 76);
+
+/// Sets the maximum number of processes, including the context manager, that may open this
+/// context. Further opens fail with `EBUSY`. Zero means unlimited, which is the default. Requires
+/// `CAP_SYS_ADMIN` or being the context manager.
+pub(crate) const BINDER_SET_MAX_PROCS: u32 = kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 77);
+
+/// The number of buckets in `BinderSizeHistogram`.
+pub(crate) const BINDER_SIZE_HISTOGRAM_BUCKETS: usize = 32;
+
+decl_driver_struct!(
+    /// Argument of `BINDER_GET_SIZE_HISTOGRAM`.
+    BinderSizeHistogram {
+        /// Input. If non-zero, the histogram is cleared after being read.
+        reset: u32,
+        reserved: u32,
+        /// Output. Bucket `i` counts the buffers of at least `2^i` and less than `2^(i+1)` bytes.
+        /// Bucket zero also counts empty buffers.
+        buckets: [u64; BINDER_SIZE_HISTOGRAM_BUCKETS],
+    }
+);
+
+/// Returns the sizes of the buffers allocated for transactions received by the calling process
+/// since statistics were enabled with `BINDER_ENABLE_STATS`. Fails with `EINVAL` while statistics
+/// are disabled.
+pub(crate) const BINDER_GET_SIZE_HISTOGRAM: u32 =
+    kernel::ioctl::_IOWR::<BinderSizeHistogram>(BINDER_IOC_MAGIC, 78);
+
+/// Freezes every process of the given pid, but only if none of them has pending transactions.
+/// Unlike `BINDER_FREEZE`, this never waits, and fails with `EAGAIN` leaving the processes
+/// unfrozen if any of them is busy. Like `BINDER_FREEZE`, it is subject to
+/// `BINDER_SET_STRICT_FREEZE`.
+pub(crate) const BINDER_FREEZE_IF_IDLE: u32 = kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 79);
+
+/// When enabled, `BINDER_FREEZE` and `BINDER_FREEZE_IF_IDLE` fail with `EPERM` for a pid other
+/// than the caller's unless the caller opened the binder device with `CAP_SYS_ADMIN`. Disabled by
+/// default, since the freezer doesn't normally hold `CAP_SYS_ADMIN`. This is a global setting and
+/// requires `CAP_SYS_ADMIN`.
+pub(crate) const BINDER_SET_STRICT_FREEZE: u32 = kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 80);
+
+/// Sets how many idle looper threads the calling process wants to keep around. A new looper is
+/// requested with `BR_SPAWN_LOOPER` once no more than this many threads are idle. Defaults to zero,
+/// which only requests a new looper when no thread is idle.
+pub(crate) const BINDER_SET_SPARE_THREADS: u32 = kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 81);
+
+decl_driver_struct!(
+    /// Argument of `BINDER_GET_OUTSTANDING_TXNS`.
+    BinderOutstandingTxns {
+        /// Input. The pid to query.
+        pid: u32,
+        /// Output. The number of outstanding transactions, summed over all contexts.
+        count: u32,
+    }
+);
+
+/// Returns the number of transactions that the processes of the given pid have received but not
+/// yet finished. Fails with `EINVAL` if the pid doesn't use binder. Requires `CAP_SYS_ADMIN`.
+pub(crate) const BINDER_GET_OUTSTANDING_TXNS: u32 =
+    kernel::ioctl::_IOWR::<BinderOutstandingTxns>(BINDER_IOC_MAGIC, 82);
+
+/// Sets the `max_threads` that processes opening this context start out with, until they call
+/// `BINDER_SET_MAX_THREADS`. Defaults to zero. Requires `CAP_SYS_ADMIN` or being the context
+/// manager.
+pub(crate) const BINDER_SET_DEFAULT_MAX_THREADS: u32 =
+    kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 83);
+
+decl_driver_struct!(
+    /// Argument of `BINDER_GET_HANDLE_STATUS`.
+    BinderHandleStatus {
+        /// Input. The handle to query.
+        handle: u32,
+        /// Output. Non-zero if the process owning the node has died.
+        dead: u32,
+    }
+);
+
+/// Returns whether the node behind a handle of the calling process belongs to a dead process. This
+/// is a one-shot alternative to `BC_REQUEST_DEATH_NOTIFICATION`. Fails with `ENOENT` if the handle
+/// doesn't exist.
+pub(crate) const BINDER_GET_HANDLE_STATUS: u32 =
+    kernel::ioctl::_IOWR::<BinderHandleStatus>(BINDER_IOC_MAGIC, 84);
+
+/// The layout version of `BinderProcessState`. Incremented whenever the layout changes.
+pub(crate) const BINDER_PROCESS_STATE_VERSION: u32 = 1;
+
+decl_driver_struct!(
+    /// Argument of `BINDER_EXPORT_STATE`. A fixed-size snapshot of a process, suitable for
+    /// storing in a crash dump.
+    BinderProcessState {
+        /// Always `BINDER_PROCESS_STATE_VERSION`.
+        version: u32,
+        pid: u32,
+        threads: u32,
+        requested_threads: u32,
+        started_threads: u32,
+        max_threads: u32,
+        /// The number of threads in each of the `BINDER_THREAD_STATE_*` states.
+        thread_states: [u32; BINDER_THREAD_STATE_COUNT],
+        nodes: u32,
+        refs: u32,
+        outstanding_txns: u32,
+        /// The number of work items queued on the process.
+        pending_work: u32,
+        buffer_allocated: u64,
+        buffer_size: u64,
+    }
+);
+
+/// Returns a `BinderProcessState` snapshot of the calling process.
+pub(crate) const BINDER_EXPORT_STATE: u32 =
+    kernel::ioctl::_IOWR::<BinderProcessState>(BINDER_IOC_MAGIC, 85);
+
+/// Removes the uid filter of a node.
+pub(crate) const BINDER_UID_FILTER_NONE: u32 = 0;
+/// Only the listed uids may send transactions to the node.
+pub(crate) const BINDER_UID_FILTER_ALLOW: u32 = 1;
+/// The listed uids may not send transactions to the node.
+pub(crate) const BINDER_UID_FILTER_DENY: u32 = 2;
+/// The maximum number of uids in a node's uid filter.
+pub(crate) const BINDER_UID_FILTER_MAX_UIDS: usize = 64;
+
+decl_driver_struct!(
+    /// Argument of `BINDER_SET_NODE_UID_FILTER`.
+    BinderNodeUidFilter {
+        /// The node to configure, identified like in `flat_binder_object`.
+        ptr: u64,
+        cookie: u64,
+        /// One of the `BINDER_UID_FILTER_*` constants.
+        mode: u32,
+        /// The number of entries in `uids`.
+        count: u32,
+        /// Userspace pointer to an array of `count` uids.
+        uids: u64,
+    }
+);
+
+/// Restricts which effective uids may send transactions to a node owned by the calling process.
+/// Transactions from other uids fail with `EPERM`. Uids are interpreted in the initial user
+/// namespace.
+pub(crate) const BINDER_SET_NODE_UID_FILTER: u32 =
+    kernel::ioctl::_IOW::<BinderNodeUidFilter>(BINDER_IOC_MAGIC, 86);
+
+/// Set in `BinderRefNodeInfo::flags` if the handle does not refer to a node with a strong
+/// reference.
+pub(crate) const BINDER_REF_NODE_INFO_NOT_FOUND: u32 = 1;
+
+decl_driver_struct!(
+    /// Entry of `BINDER_GET_NODE_INFO_FOR_REFS`.
+    BinderRefNodeInfo {
+        /// Input. The handle to query.
+        handle: u32,
+        /// Output. Must be zero on input.
+        strong_count: u32,
+        /// Output. Must be zero on input.
+        weak_count: u32,
+        /// Output. `BINDER_REF_NODE_INFO_*` flags. Must be zero on input.
+        flags: u32,
+    }
+);
+
+decl_driver_struct!(
+    /// Argument of `BINDER_GET_NODE_INFO_FOR_REFS`.
+    BinderRefNodeInfoArray {
+        /// Userspace pointer to `count` entries of type `BinderRefNodeInfo`, which are updated in
+        /// place.
+        buffer: u64,
+        /// At most `BINDER_ARRAY_MAX_ENTRIES`.
+        count: u32,
+        reserved: u32,
+    }
+);
+
+/// Batch version of `BINDER_GET_NODE_INFO_FOR_REF`. Only the context manager may use this ioctl.
+pub(crate) const BINDER_GET_NODE_INFO_FOR_REFS: u32 =
+    kernel::ioctl::_IOWR::<BinderRefNodeInfoArray>(BINDER_IOC_MAGIC, 87);
+
+/// Sets the maximum number of synchronous transactions that the calling process may be handling
+/// at the same time. Further synchronous transactions fail with `EAGAIN` until one of them is
+/// replied to. Zero means unlimited, which is the default.
+pub(crate) const BINDER_SET_MAX_SYNC_TXNS: u32 = kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 88);
+
+/// Pauses (non-zero) or resumes (zero) the delivery of work queued on the calling process. While
+/// paused, incoming work is queued without waking up any thread, and threads don't take work from
+/// the process queue. Work already delivered to a specific thread is not affected.
+pub(crate) const BINDER_PAUSE_DELIVERY: u32 = kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 89);
+
+/// Resets the statistics of the node with the given ptr, which must be owned by the calling
+/// process.
+pub(crate) const BINDER_RESET_NODE_STATS: u32 = kernel::ioctl::_IOW::<u64>(BINDER_IOC_MAGIC, 90);
+
+decl_driver_struct!(
+    /// Argument of `BINDER_GET_NODE_FANOUT`.
+    BinderNodeFanout {
+        /// Input. A handle of the calling process.
+        handle: u32,
+        /// Output. The number of processes with a reference to the node.
+        processes: u32,
+        /// Output. The strong and weak counts of the node.
+        strong_count: u32,
+        weak_count: u32,
+    }
+);
+
+/// Returns how many processes reference the node behind a handle of the calling process.
+pub(crate) const BINDER_GET_NODE_FANOUT: u32 =
+    kernel::ioctl::_IOWR::<BinderNodeFanout>(BINDER_IOC_MAGIC, 91);
+
+/// Only warnings and errors are logged. This is the default.
+pub(crate) const BINDER_LOG_QUIET: u32 = 0;
+/// Also log process, thread and node lifecycle events.
+pub(crate) const BINDER_LOG_INFO: u32 = 1;
+/// Also log individual transactions and death notifications.
+pub(crate) const BINDER_LOG_DEBUG: u32 = 2;
+
+/// Sets the module-wide verbosity to one of the `BINDER_LOG_*` levels. Requires `CAP_SYS_ADMIN`.
+pub(crate) const BINDER_SET_LOG_LEVEL: u32 = kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 92);
+
+decl_driver_struct!(
+    /// Entry of `BINDER_GET_THREAD_SERVICED`. The key is `tid`.
+    BinderThreadServiced {
+        tid: u32,
+        reserved: u32,
+        /// The number of incoming transactions the thread has picked up.
+        count: u64,
+    }
+);
+
+/// Lists how many incoming transactions each thread of the calling process has picked up, using
+/// the `BinderArray` convention.
+pub(crate) const BINDER_GET_THREAD_SERVICED: u32 =
+    kernel::ioctl::_IOWR::<BinderArray>(BINDER_IOC_MAGIC, 93);
+
+decl_driver_struct!(
+    /// Argument of `BINDER_GET_PID_STATS`. Except for `pid`, the fields are outputs summed over
+    /// the processes of the pid in all contexts.
+    BinderPidStats {
+        pid: u32,
+        /// The number of contexts that the pid has opened.
+        processes: u32,
+        threads: u32,
+        nodes: u32,
+        refs: u32,
+        outstanding_txns: u32,
+        pending_work: u32,
+        reserved: u32,
+        buffer_allocated: u64,
+        buffer_size: u64,
+    }
+);
+
+/// Returns statistics about a pid, combined over all contexts. Fails with `EINVAL` if the pid
+/// doesn't use binder. Requires `CAP_SYS_ADMIN`.
+pub(crate) const BINDER_GET_PID_STATS: u32 =
+    kernel::ioctl::_IOWR::<BinderPidStats>(BINDER_IOC_MAGIC, 94);
+
+decl_driver_struct!(
+    /// Entry of `BINDER_GET_STALE_NODES`. The key is `ptr`.
+    BinderStaleNode {
+        ptr: u64,
+        /// The time since the node was created, in milliseconds.
+        age_ms: u64,
+    }
+);
+
+decl_driver_struct!(
+    /// Argument of `BINDER_GET_STALE_NODES`.
+    BinderStaleNodes {
+        array: BinderArray,
+        /// Only nodes older than this are listed.
+        min_age_ms: u64,
+    }
+);
+
+/// Lists the nodes of the calling process that are older than `min_age_ms` and have not received
+/// any transaction, using the `BinderArray` convention. Transactions are only counted while stats
+/// are enabled with `BINDER_ENABLE_STATS`, so this fails with `EINVAL` when they are disabled.
+pub(crate) const BINDER_GET_STALE_NODES: u32 =
+    kernel::ioctl::_IOWR::<BinderStaleNodes>(BINDER_IOC_MAGIC, 95);
+
+/// Set in `BinderBufferPages::flags` when another buffer uses the first page of the buffer.
+pub(crate) const BINDER_BUFFER_SHARES_FIRST_PAGE: u32 = 0x01;
+/// Set in `BinderBufferPages::flags` when another buffer uses the last page of the buffer.
+pub(crate) const BINDER_BUFFER_SHARES_LAST_PAGE: u32 = 0x02;
+
+decl_driver_struct!(
+    /// Argument of `BINDER_GET_BUFFER_PAGES`. Except for `buffer`, the fields are outputs.
+    BinderBufferPages {
+        /// The address of a buffer received by the calling process.
+        buffer: u64,
+        /// The index of the first page of the buffer within the mapping.
+        first_page: u32,
+        /// The index of the last page of the buffer within the mapping.
+        last_page: u32,
+        flags: u32,
+        reserved: u32,
+    }
+);
+
+/// Describes how a buffer of the calling process maps onto the pages of its mapping. This is a
+/// debugging aid, so it fails with `EPERM` unless the log level is `BINDER_LOG_DEBUG`.
+pub(crate) const BINDER_GET_BUFFER_PAGES: u32 =
+    kernel::ioctl::_IOWR::<BinderBufferPages>(BINDER_IOC_MAGIC, 96);
+
+decl_driver_struct!(
+    /// Argument of `BINDER_SET_CIRCUIT_BREAKER`.
+    BinderCircuitBreaker {
+        /// The number of consecutive delivery failures after which transactions are rejected.
+        /// Zero disables the circuit breaker.
+        failures: u32,
+        /// How long transactions are rejected for, in milliseconds.
+        cooldown_ms: u32,
+    }
+);
+
+/// Configures a circuit breaker for the calling process. After `failures` consecutive failures to
+/// deliver work to one of its threads, transactions to the process fail with `EAGAIN` for
+/// `cooldown_ms`. Delivery is then retried, and a single further failure rejects transactions
+/// again. Disabled by default.
+pub(crate) const BINDER_SET_CIRCUIT_BREAKER: u32 =
+    kernel::ioctl::_IOW::<BinderCircuitBreaker>(BINDER_IOC_MAGIC, 97);
+
+/// Returns, as a `u64`, the sequence number of the last transaction delivered to the calling
+/// thread. Transactions from a process to a node are numbered from one in the order in which they
+/// are sent, so gaps or reordering can be detected by the receiver. The number is zero when
+/// the sender has no reference to the node, such as a transaction to handle zero without one.
+pub(crate) const BINDER_GET_RECEIVED_SEQ: u32 = kernel::ioctl::_IOWR::<u64>(BINDER_IOC_MAGIC, 98);
+
+/// Sets the mask of `flat_binder_object` flags that binder objects sent in transactions in this
+/// context may carry. Objects with other flags set are rejected with `EPERM`. The priority bits
+/// are part of the flags, so the mask must allow them for nodes to request a priority. Defaults to
+/// allowing every flag. Requires `CAP_SYS_ADMIN` or being the context manager.
+pub(crate) const BINDER_SET_ALLOWED_NODE_FLAGS: u32 =
+    kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 99);
+
+/// When enabled, `mmap` fails with `EINVAL` for processes in this context whose `max_threads` is
+/// zero, so that services set up their thread pool before mapping binder memory. Disabled by
+/// default. Requires `CAP_SYS_ADMIN` or being the context manager.
+pub(crate) const BINDER_SET_MMAP_REQUIRES_MAX_THREADS: u32 =
+    kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 100);
+
+decl_driver_struct!(
+    /// Entry of `BINDER_GET_READY_THREADS`. The key is `tid`.
+    BinderReadyThread {
+        tid: u32,
+    }
+);
+
+/// Lists the threads of the calling process that are idle and registered to receive work from
+/// the process queue, using the `BinderArray` convention.
+pub(crate) const BINDER_GET_READY_THREADS: u32 =
+    kernel::ioctl::_IOWR::<BinderArray>(BINDER_IOC_MAGIC, 101);
+
+/// When enabled, a `BC_REPLY` of the calling process whose caller thread exited before the reply
+/// could be delivered returns `BR_DEAD_REPLY` instead of `BR_TRANSACTION_COMPLETE`. The buffer of
+/// the reply is freed either way. Disabled by default.
+pub(crate) const BINDER_ENABLE_DEAD_REPLY_ERRORS: u32 =
+    kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 102);
+
+/// Returns, as a `u64`, how long the oldest work item on the work list of the calling process has
+/// been waiting for a thread, in milliseconds. Zero if the list is empty. A large value means that
+/// the process isn't servicing its work.
+pub(crate) const BINDER_GET_OLDEST_WORK_AGE: u32 =
+    kernel::ioctl::_IOWR::<u64>(BINDER_IOC_MAGIC, 103);
+
+/// Defers marking the pages of freed buffers as reclaimable until the given number of bytes of
+/// such pages has accumulated, to release them in bulk. Pages are also released when a thread of
+/// the calling process waits for work, and before a new buffer uses them. Zero, the default,
+/// releases the pages as soon as their buffer is freed.
+pub(crate) const BINDER_SET_DEFERRED_FREE: u32 = kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 104);
+
+decl_driver_struct!(
+    /// Argument of `BINDER_AUDIT_REFS`. Except for `repair`, the fields are outputs.
+    BinderRefAudit {
+        /// When non-zero, the discrepancies that can be fixed safely are fixed. Requires
+        /// `CAP_SYS_ADMIN`.
+        repair: u32,
+        /// The number of entries of the node-to-handle index whose handle doesn't exist or refers
+        /// to another node.
+        stale_by_node: u32,
+        /// The number of handles that are missing from the node-to-handle index.
+        missing_by_node: u32,
+        /// The number of discrepancies that were fixed.
+        repaired: u32,
+    }
+);
+
+/// Checks that the two indexes of the handles of the calling process, by handle and by node, are
+/// consistent with each other.
+pub(crate) const BINDER_AUDIT_REFS: u32 =
+    kernel::ioctl::_IOWR::<BinderRefAudit>(BINDER_IOC_MAGIC, 105);
+
+decl_driver_struct!(
+    /// Argument of `BINDER_GET_CONTEXT_TXN_STATS`.
+    BinderContextTxnStats {
+        /// The number of transactions submitted in the context, not counting replies.
+        transactions: u64,
+        /// The number of bytes of data and offsets carried by those transactions.
+        bytes: u64,
+    }
+);
+
+/// Returns the transaction totals of the context of the calling process. Requires
+/// `CAP_SYS_ADMIN` or being the context manager.
+pub(crate) const BINDER_GET_CONTEXT_TXN_STATS: u32 =
+    kernel::ioctl::_IOWR::<BinderContextTxnStats>(BINDER_IOC_MAGIC, 106);
+
+/// Limits how many nodes the calling process may create per second, with bursts of up to one
+/// second worth of nodes. Sending a new binder object beyond the limit fails with `EAGAIN`. Zero,
+/// the default, removes the limit.
+pub(crate) const BINDER_SET_NODE_CREATION_RATE: u32 =
+    kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 107);
+
+decl_driver_struct!(
+    /// Argument of `BINDER_COMPARE_HANDLES`. `same` is an output.
+    BinderCompareHandles {
+        handle1: u32,
+        handle2: u32,
+        /// Set to one if both handles refer to the same node, and to zero otherwise.
+        same: u32,
+        reserved: u32,
+    }
+);
+
+/// Reports whether two handles of the calling process refer to the same node. Fails with `ENOENT`
+/// if either handle doesn't exist.
+pub(crate) const BINDER_COMPARE_HANDLES: u32 =
+    kernel::ioctl::_IOWR::<BinderCompareHandles>(BINDER_IOC_MAGIC, 108);
+
+/// Sets how long a flush of the calling process waits for the transactions in flight to complete
+/// before asking the loopers to exit, in milliseconds. Zero, the default, makes the loopers exit
+/// immediately.
+pub(crate) const BINDER_SET_FLUSH_DRAIN_TIMEOUT: u32 =
+    kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 109);
+
+decl_driver_struct!(
+    /// Argument of `BINDER_DUMP_NODE`. Except for `debug_id`, the fields are outputs.
+    BinderNodeDump {
+        /// The debug id of the node, as shown in the binder debugfs files.
+        debug_id: u64,
+        owner_pid: u32,
+        flags: u32,
+        ptr: u64,
+        cookie: u64,
+        strong_count: u32,
+        weak_count: u32,
+        /// Whether the owner has been told that the node has strong and weak references.
+        has_strong: u32,
+        has_weak: u32,
+        /// The number of processes with a reference to the node.
+        ref_processes: u32,
+        /// The number of death notifications registered for the node.
+        death_notifications: u32,
+        /// The number of oneway transactions waiting for the one being processed.
+        oneway_backlog: u32,
+        /// Whether a oneway transaction is being processed.
+        has_oneway_transaction: u32,
+    }
+);
+
+/// Returns the state of the node with the given debug id, which may belong to any process in any
+/// context. Fails with `ENOENT` if there is no such node. Requires `CAP_SYS_ADMIN`.
+pub(crate) const BINDER_DUMP_NODE: u32 =
+    kernel::ioctl::_IOWR::<BinderNodeDump>(BINDER_IOC_MAGIC, 110);
+
+/// When enabled, oneway transactions in this context are processed at no more than the priority
+/// of the thread that sent them, even if the node asks for a higher minimum priority. Disabled by
+/// default. Requires `CAP_SYS_ADMIN` or being the context manager.
+pub(crate) const BINDER_SET_ASYNC_PRIORITY_CAP: u32 =
+    kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 111);
+
+decl_driver_struct!(
+    /// Output of `BINDER_GET_HANDLE_STATS`. All fields are zero when the process has no handles.
+    BinderHandleStats {
+        /// The number of handles.
+        count: u32,
+        /// The lowest handle value in use.
+        min: u32,
+        /// The highest handle value in use.
+        max: u32,
+        /// The number of unused handle values between `min` and `max`.
+        gaps: u32,
+    }
+);
+
+/// Returns how the handle values of the calling process are spread out. Many gaps below a large
+/// `max` make allocating new handles slower.
+pub(crate) const BINDER_GET_HANDLE_STATS: u32 =
+    kernel::ioctl::_IOWR::<BinderHandleStats>(BINDER_IOC_MAGIC, 112);
+
+decl_driver_struct!(
+    /// Output of `BINDER_GET_CONTEXT_NAME`.
+    BinderContextName {
+        /// The nul-terminated name of the context, truncated to `BINDER_CONTEXT_NAME_MAX` bytes.
+        name: [u8; BINDER_CONTEXT_NAME_MAX],
+    }
+);
+
+/// Returns the name of the context that the calling process belongs to.
+pub(crate) const BINDER_GET_CONTEXT_NAME: u32 =
+    kernel::ioctl::_IOWR::<BinderContextName>(BINDER_IOC_MAGIC, 113);
+
+/// Sets the maximum number of death notifications that the calling process may have received
+/// without acknowledging them with `BC_DEAD_BINDER_DONE`. When a new notification is delivered
+/// beyond the limit, the oldest unacknowledged one is treated as acknowledged, and acknowledging it
+/// again later has no effect. Zero means unlimited, which is the default.
+pub(crate) const BINDER_SET_MAX_DELIVERED_DEATHS: u32 =
+    kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 114);
+
+decl_driver_struct!(
+    /// Output of `BINDER_GET_ACTIVITY`. Transactions include replies.
+    BinderActivity {
+        /// The number of transactions sent successfully.
+        transactions: u64,
+        /// The number of bytes of data and offsets carried by those transactions.
+        bytes: u64,
+        /// The number of transactions that failed to be sent.
+        errors: u64,
+    }
+);
+
+/// Returns the transactions sent by the calling process since the previous call, and resets the
+/// counters. Each transaction is only reported by one call, so a monitor should not add up the
+/// results of concurrent callers. Fails with `EINVAL` unless statistics are enabled with
+/// `BINDER_ENABLE_STATS`, which also resets the counters.
+pub(crate) const BINDER_GET_ACTIVITY: u32 =
+    kernel::ioctl::_IOWR::<BinderActivity>(BINDER_IOC_MAGIC, 115);
+
+/// Sets how long transactions and replies sent by the calling process wait for buffer space in
+/// the target process before failing with `EAGAIN`, in milliseconds. The wait is interrupted by
+/// signals. Zero, the default, makes them fail immediately. `TF_DROP_IF_FULL` transactions never
+/// wait.
+pub(crate) const BINDER_SET_BUFFER_WAIT_TIMEOUT: u32 =
+    kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 116);
+
+/// Sets the number of transactions per second from a single sender process above which a warning
+/// naming the sender is printed, and counted in the optional statistics of the calling process.
+/// Zero, the default, disables the detection.
+pub(crate) const BINDER_SET_TXN_STORM_THRESHOLD: u32 =
+    kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 117);
+
+decl_driver_struct!(
+    /// Output of `BINDER_GET_MANAGER_INFO`.
+    BinderManagerInfo {
+        ptr: u64,
+        cookie: u64,
+        /// The `flat_binder_object` flags that the manager node was registered with.
+        flags: u32,
+        reserved: u32,
+    }
+);
+
+/// Returns the parameters that the context manager node of the context of the calling process was
+/// registered with. Fails with `ENOENT` if there is no context manager. Requires `CAP_SYS_ADMIN`
+/// or being the context manager.
+pub(crate) const BINDER_GET_MANAGER_INFO: u32 =
+    kernel::ioctl::_IOWR::<BinderManagerInfo>(BINDER_IOC_MAGIC, 118);
+
+/// Enables or disables buffer checksums for the calling process. While enabled, a checksum of each
+/// transaction buffer is computed once the data has been copied into it, and a warning is printed
+/// if the buffer has changed when it is freed. This reads every buffer twice, so it is meant for
+/// debugging suspected memory corruption. Disabled by default.
+pub(crate) const BINDER_ENABLE_BUFFER_CHECKSUMS: u32 =
+    kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 119);
+
+decl_driver_struct!(
+    /// Entry of `BINDER_GET_REFS`. The key is `handle`.
+    BinderRefEntry {
+        handle: u32,
+        reserved: u32,
+        /// The debug id of the node. It is unique across all processes, so it tells whether two
+        /// handles refer to the same node.
+        debug_id: u64,
+        /// The strong and weak counts that the calling process holds through this handle.
+        strong_count: u32,
+        weak_count: u32,
+    }
+);
+
+/// Lists the handles of the calling process and the nodes they refer to, using the `BinderArray`
+/// convention.
+pub(crate) const BINDER_GET_REFS: u32 = kernel::ioctl::_IOWR::<BinderArray>(BINDER_IOC_MAGIC, 120);
+
+/// Sets the maximum number of synchronous transactions in a chain in this context, where each
+/// transaction is sent by a thread handling the previous one. A transaction that would make the
+/// chain longer fails with `ELOOP`. Zero, the default, means unlimited. Requires `CAP_SYS_ADMIN`
+/// or being the context manager.
+pub(crate) const BINDER_SET_MAX_CHAIN_LEN: u32 = kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 121);
+
+decl_driver_struct!(
+    /// Argument of `BINDER_GET_CONFIG` and `BINDER_SET_CONFIG`.
+    BinderProcessConfig {
+        /// As set by `BINDER_SET_MAX_THREADS`.
+        max_threads: u32,
+        /// As set by `BINDER_ENABLE_ONEWAY_SPAM_DETECTION`. Must be zero or one.
+        oneway_spam_detection: u32,
+    }
+);
+
+/// Returns the settings of the calling process, so that they can be restored with
+/// `BINDER_SET_CONFIG` after reopening the binder device.
+pub(crate) const BINDER_GET_CONFIG: u32 =
+    kernel::ioctl::_IOWR::<BinderProcessConfig>(BINDER_IOC_MAGIC, 122);
+
+/// Applies settings returned by `BINDER_GET_CONFIG` to the calling process. Fails with `EINVAL`,
+/// without changing anything, if a value is invalid.
+pub(crate) const BINDER_SET_CONFIG: u32 =
+    kernel::ioctl::_IOW::<BinderProcessConfig>(BINDER_IOC_MAGIC, 123);
+
+decl_driver_struct!(
+    /// Output of `BINDER_GET_MAPPING_INFO`.
+    BinderMappingInfo {
+        /// One if the process has mapped its buffer space, and zero otherwise, in which case the
+        /// other fields are zero.
+        mapped: u32,
+        reserved: u32,
+        /// The userspace address of the mapping.
+        address: u64,
+        /// The size of the mapping, which bounds the size of a single transaction.
+        size: u64,
+    }
+);
+
+/// Reports whether the calling process has a buffer mapping, and where.
+pub(crate) const BINDER_GET_MAPPING_INFO: u32 =
+    kernel::ioctl::_IOWR::<BinderMappingInfo>(BINDER_IOC_MAGIC, 124);
+
+/// When enabled, transactions and replies that need a buffer in the calling process fail with
+/// `ENXIO` while it has not mapped its buffer space, instead of being reported as sent to a dead
+/// process. This makes it easier to notice a missing `mmap`. Disabled by default.
+pub(crate) const BINDER_SET_STRICT_MMAP: u32 = kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 125);
+
+/// Queues a `BR_NOOP` on the thread with the given tid, waking it if it is blocked reading. This
+/// lets a privileged caller unstick a looper without sending it a transaction. Fails with
+/// `ENOENT` if no binder thread has that tid, and requires `CAP_SYS_ADMIN`.
+pub(crate) const BINDER_WAKE_THREAD: u32 = kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 126);
+
+/// Log an error when the count of outstanding transactions of a process underflows.
+pub(crate) const BINDER_UNDERFLOW_WARN: u32 = 0;
+/// Ignore underflows of the count of outstanding transactions.
+pub(crate) const BINDER_UNDERFLOW_IGNORE: u32 = 1;
+/// Log an error with a backtrace when the count of outstanding transactions of a process
+/// underflows. Only available with `CONFIG_DEBUG_KERNEL`.
+pub(crate) const BINDER_UNDERFLOW_BACKTRACE: u32 = 2;
+/// Panic when the count of outstanding transactions of a process underflows. Only available with
+/// `CONFIG_DEBUG_KERNEL`.
+pub(crate) const BINDER_UNDERFLOW_BUG: u32 = 3;
+
+/// Sets what happens in this context when the count of outstanding transactions of a process
+/// underflows, which indicates an accounting bug. The count always stays at zero. Takes one of
+/// the `BINDER_UNDERFLOW_*` constants, and defaults to `BINDER_UNDERFLOW_WARN`. Fails with `EINVAL`
+/// for a policy that the kernel doesn't support. Requires `CAP_SYS_ADMIN`.
+pub(crate) const BINDER_SET_UNDERFLOW_POLICY: u32 =
+    kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 127);
+
+/// Sets the maximum total size (in bytes) of the buffer mappings of all processes using this
+/// context. An `mmap` that would exceed it fails with `ENOMEM`; existing mappings are not
+/// affected. Zero means unlimited, which is the default. Requires `CAP_SYS_ADMIN` or being the
+/// context manager.
+pub(crate) const BINDER_SET_CONTEXT_MEMORY_LIMIT: u32 =
+    kernel::ioctl::_IOW::<u64>(BINDER_IOC_MAGIC, 128);
+
+/// Sets the maximum nesting depth of `BINDER_TYPE_PTR` objects in transactions in this context,
+/// where a buffer without a parent has depth one. Transactions with deeper buffers fail with
+/// `EINVAL`. Zero, the default, means unlimited. Requires `CAP_SYS_ADMIN` or being the context
+/// manager.
+pub(crate) const BINDER_SET_MAX_BUFFER_DEPTH: u32 =
+    kernel::ioctl::_IOW::<u32>(BINDER_IOC_MAGIC, 129);
@@ -23,6 +23,14 @@ pub(crate) fn new_dead() -> Self {
         }
     }
 
+    /// A dead reply whose cause is reported to userspace as the extended error.
+    pub(crate) fn new_dead_with(source: Error) -> Self {
+        Self {
+            reply: BR_DEAD_REPLY,
+            source: Some(source),
+        }
+    }
+
     pub(crate) fn new_frozen() -> Self {
         Self {
             reply: BR_FROZEN_REPLY,
@@ -37,14 +45,23 @@ pub(crate) fn new_frozen_oneway() -> Self {
         }
     }
 
-    pub(crate) fn should_pr_warn(&self) -> bool {
+    /// Whether the error carries an errno that should be stored as the thread's extended error.
+    pub(crate) fn has_source(&self) -> bool {
         self.source.is_some()
     }
 
+    pub(crate) fn should_pr_warn(&self) -> bool {
+        self.source.is_some() && !self.is_dead()
+    }
+
     pub(crate) fn is_dead(&self) -> bool {
         self.reply == BR_DEAD_REPLY
     }
 
+    pub(crate) fn is_out_of_space(&self) -> bool {
+        self.source == Some(ENOSPC)
+    }
+
     pub(crate) fn as_errno(&self) -> core::ffi::c_int {
         self.source.unwrap_or(EINVAL).to_errno()
     }
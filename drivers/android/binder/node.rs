@@ -12,12 +12,15 @@
     seq_print,
     sync::lock::{spinlock::SpinLockBackend, Guard},
     sync::{Arc, LockedBy, SpinLock},
+    task::Kuid,
+    time::{ktime_ms_delta, Ktime},
     uaccess::UserSliceWriter,
 };
 
 use crate::{
     defs::*,
     error::BinderError,
+    log_enabled,
     prio::{self, BinderPriority},
     process::{NodeRefInfo, Process, ProcessInner},
     thread::Thread,
@@ -25,6 +28,8 @@
     DArc, DLArc, DTRWrap, DeliverToRead,
 };
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
 mod wrapper;
 pub(crate) use self::wrapper::CritIncrWrapper;
 
@@ -177,6 +182,40 @@ struct NodeInner {
     active_inc_refs: u8,
     /// List of `NodeRefInfo` objects that reference this node.
     refs: List<NodeRefInfo, { NodeRefInfo::LIST_NODE }>,
+    /// Restricts which uids may send transactions to this node. `None` means no restriction.
+    uid_filter: Option<UidFilter>,
+    stats: NodeStats,
+}
+
+/// Optional statistics about a node. Like `ProcessStats`, these are only updated while
+/// `ProcessInner::stats_enabled` is set for the owner.
+#[derive(Default)]
+struct NodeStats {
+    /// The number of transactions sent to this node.
+    transactions: u64,
+    /// The largest number of oneway transactions waiting in `oneway_todo`.
+    max_oneway_backlog: u32,
+    /// The number of zero-to-one transitions of the strong count that were reported to the owner,
+    /// and how many of them needed a `CritIncrWrapper` because the node was already queued.
+    strong_promotions: u64,
+    wrapped_promotions: u64,
+}
+
+/// A list of uids that are allowed or denied to send transactions to a node.
+pub(crate) struct UidFilter {
+    /// Whether the listed uids are the only ones allowed, rather than the ones denied.
+    allow: bool,
+    uids: Vec<Kuid>,
+}
+
+impl UidFilter {
+    pub(crate) fn new(allow: bool, uids: Vec<Kuid>) -> Self {
+        Self { allow, uids }
+    }
+
+    fn accepts(&self, euid: Kuid) -> bool {
+        self.uids.contains(&euid) == self.allow
+    }
 }
 
 #[pin_data]
@@ -186,6 +225,10 @@ pub(crate) struct Node {
     cookie: u64,
     pub(crate) flags: u32,
     pub(crate) owner: Arc<Process>,
+    created: Ktime,
+    /// Whether `inner.uid_filter` is set. This lets transactions skip the owner lock in the common
+    /// case where the node has no filter.
+    has_uid_filter: AtomicBool,
     inner: LockedBy<NodeInner, ProcessInner>,
     #[pin]
     links_track: AtomicListArcTracker,
@@ -231,6 +274,8 @@ pub(crate) fn new(
                     has_oneway_transaction: false,
                     active_inc_refs: 0,
                     refs: List::new(),
+                    uid_filter: None,
+                    stats: NodeStats::default(),
                 },
             ),
             debug_id: super::next_debug_id(),
@@ -238,6 +283,8 @@ pub(crate) fn new(
             cookie,
             flags,
             owner,
+            created: Ktime::ktime_get(),
+            has_uid_filter: AtomicBool::new(false),
             links_track <- AtomicListArcTracker::new(),
         })
     }
@@ -249,6 +296,7 @@ pub(crate) fn full_debug_print(
         owner_inner: &mut ProcessInner,
     ) -> Result<()> {
         let prio = self.node_prio();
+        let stats_enabled = owner_inner.stats_enabled;
         let inner = self.inner.access_mut(owner_inner);
         seq_print!(
             m,
@@ -263,12 +311,30 @@ pub(crate) fn full_debug_print(
             inner.strong.count,
             inner.weak.count,
         );
+        if stats_enabled {
+            seq_print!(
+                m,
+                " txns {} max async {} promotions {} wrapped {}",
+                inner.stats.transactions,
+                inner.stats.max_oneway_backlog,
+                inner.stats.strong_promotions,
+                inner.stats.wrapped_promotions,
+            );
+        }
         if !inner.refs.is_empty() {
             seq_print!(m, " proc");
             for node_ref in &inner.refs {
                 seq_print!(m, " {}", node_ref.process.task.pid());
             }
         }
+        // Death notifications are sent in this order when the owner dies, which need not match
+        // the order of `refs`.
+        if !inner.death_list.is_empty() {
+            seq_print!(m, " deaths");
+            for death in &inner.death_list {
+                seq_print!(m, " {}", death.process.task.pid());
+            }
+        }
         seq_print!(m, "\n");
         for t in &inner.oneway_todo {
             t.debug_print_inner(m, "    pending async transaction ");
@@ -327,6 +393,18 @@ pub(crate) fn inherit_rt(&self) -> bool {
         (self.flags & FLAT_BINDER_FLAG_INHERIT_RT) != 0
     }
 
+    pub(crate) fn is_sync_only(&self) -> bool {
+        (self.flags & FLAT_BINDER_FLAG_SYNC_ONLY) != 0
+    }
+
+    pub(crate) fn drops_oldest_oneway(&self) -> bool {
+        (self.flags & FLAT_BINDER_FLAG_ONEWAY_DROP_OLDEST) != 0
+    }
+
+    pub(crate) fn is_high_priority(&self) -> bool {
+        (self.flags & FLAT_BINDER_FLAG_HIGH_PRIORITY) != 0
+    }
+
     /// An id that is unique across all binder nodes on the system. Used as the key in the
     /// `by_node` map.
     pub(crate) fn global_id(&self) -> usize {
@@ -441,6 +519,7 @@ pub(crate) fn incr_refcount_allow_zero2one(
         owner_inner: &mut ProcessInner,
     ) -> Result<Option<DLArc<Node>>, CouldNotDeliverCriticalIncrement> {
         let is_dead = owner_inner.is_dead;
+        let stats_enabled = owner_inner.stats_enabled;
         let inner = self.inner.access_mut(owner_inner);
 
         // Get a reference to the state we'll update.
@@ -466,6 +545,9 @@ pub(crate) fn incr_refcount_allow_zero2one(
             if inner.delivery_state.can_push_strong_zero2one_normally() {
                 let list_arc = ListArc::try_from_arc(self.clone()).ok().unwrap();
                 inner.delivery_state.did_push_strong_zero2one();
+                if stats_enabled {
+                    inner.stats.strong_promotions += 1;
+                }
                 Ok(Some(list_arc))
             } else {
                 state.count -= 1;
@@ -488,9 +570,14 @@ pub(crate) fn incr_refcount_allow_zero2one_with_wrapper(
             Ok(None) => None,
             Err(CouldNotDeliverCriticalIncrement) => {
                 assert!(strong);
+                let stats_enabled = owner_inner.stats_enabled;
                 let inner = self.inner.access_mut(owner_inner);
                 inner.strong.count += 1;
                 inner.delivery_state.did_push_strong_zero2one_wrapper();
+                if stats_enabled {
+                    inner.stats.strong_promotions += 1;
+                    inner.stats.wrapped_promotions += 1;
+                }
                 Some(wrapper.init(self.clone()))
             }
         }
@@ -513,6 +600,40 @@ pub(crate) fn populate_counts(
         out.weak_count = inner.weak.count as _;
     }
 
+    /// Returns the strong and weak counts of this node.
+    pub(crate) fn counts(&self, guard: &Guard<'_, ProcessInner, SpinLockBackend>) -> (u32, u32) {
+        let inner = self.inner.access(guard);
+        (inner.strong.count as _, inner.weak.count as _)
+    }
+
+    /// Fills in the state of this node. The owner must be set by the caller.
+    pub(crate) fn dump(
+        &self,
+        dump: &mut BinderNodeDump,
+        guard: &Guard<'_, ProcessInner, SpinLockBackend>,
+    ) {
+        let inner = self.inner.access(guard);
+        dump.flags = self.flags;
+        dump.ptr = self.ptr;
+        dump.cookie = self.cookie;
+        dump.strong_count = inner.strong.count as u32;
+        dump.weak_count = inner.weak.count as u32;
+        dump.has_strong = inner.strong.has_count as u32;
+        dump.has_weak = inner.weak.has_count as u32;
+        dump.ref_processes = inner.refs.iter().count() as u32;
+        dump.death_notifications = inner.death_list.iter().count() as u32;
+        dump.oneway_backlog = inner.oneway_todo.iter().count() as u32;
+        dump.has_oneway_transaction = inner.has_oneway_transaction as u32;
+    }
+
+    /// Returns the number of processes with a reference to this node.
+    pub(crate) fn ref_process_count(
+        &self,
+        guard: &Guard<'_, ProcessInner, SpinLockBackend>,
+    ) -> usize {
+        self.inner.access(guard).refs.iter().count()
+    }
+
     pub(crate) fn populate_debug_info(
         &self,
         out: &mut BinderNodeDebugInfo,
@@ -551,9 +672,15 @@ pub(crate) fn submit_oneway(
             return Err((BinderError::new_dead(), transaction));
         }
 
+        self.count_transaction(guard);
+        let stats_enabled = guard.stats_enabled;
         let inner = self.inner.access_mut(guard);
         if inner.has_oneway_transaction {
             inner.oneway_todo.push_back(transaction);
+            if stats_enabled {
+                let backlog = inner.oneway_todo.iter().count() as u32;
+                inner.stats.max_oneway_backlog = u32::max(inner.stats.max_oneway_backlog, backlog);
+            }
         } else {
             inner.has_oneway_transaction = true;
             guard.push_work(transaction)?;
@@ -561,6 +688,30 @@ pub(crate) fn submit_oneway(
         Ok(())
     }
 
+    /// Counts a transaction sent to this node in the node statistics, if they are enabled.
+    pub(crate) fn count_transaction(&self, guard: &mut Guard<'_, ProcessInner, SpinLockBackend>) {
+        if guard.stats_enabled {
+            self.inner.access_mut(guard).stats.transactions += 1;
+        }
+    }
+
+    /// Returns the time since this node was created, in milliseconds.
+    pub(crate) fn age_ms(&self) -> i64 {
+        ktime_ms_delta(Ktime::ktime_get(), self.created)
+    }
+
+    /// Returns the number of transactions counted since stats were last enabled or reset.
+    pub(crate) fn transaction_count(
+        &self,
+        guard: &Guard<'_, ProcessInner, SpinLockBackend>,
+    ) -> u64 {
+        self.inner.access(guard).stats.transactions
+    }
+
+    pub(crate) fn reset_stats(&self, guard: &mut Guard<'_, ProcessInner, SpinLockBackend>) {
+        self.inner.access_mut(guard).stats = NodeStats::default();
+    }
+
     pub(crate) fn release(&self, guard: &mut Guard<'_, ProcessInner, SpinLockBackend>) {
         // Move every pending oneshot message to the process todolist. The process
         // will cancel it later.
@@ -599,6 +750,38 @@ pub(crate) fn pending_oneway_finished(&self) {
         }
     }
 
+    /// Replaces the filter restricting which uids may send transactions to this node.
+    pub(crate) fn set_uid_filter(
+        &self,
+        filter: Option<UidFilter>,
+        guard: &mut Guard<'_, ProcessInner, SpinLockBackend>,
+    ) {
+        self.has_uid_filter
+            .store(filter.is_some(), Ordering::Relaxed);
+        self.inner.access_mut(guard).uid_filter = filter;
+    }
+
+    /// Returns whether a process with the given effective uid may send transactions to this node.
+    pub(crate) fn accepts_uid(&self, euid: Kuid) -> bool {
+        if !self.has_uid_filter.load(Ordering::Relaxed) {
+            return true;
+        }
+        let guard = self.owner.inner.lock();
+        match &self.inner.access(&guard).uid_filter {
+            Some(filter) => filter.accepts(euid),
+            None => true,
+        }
+    }
+
+    /// Removes the oldest oneway transaction that is queued on this node, but not yet delivered.
+    ///
+    /// The caller should drop the returned transaction after releasing any locks, which frees its
+    /// buffer.
+    pub(crate) fn take_oldest_oneway(&self) -> Option<DLArc<Transaction>> {
+        let mut guard = self.owner.inner.lock();
+        self.inner.access_mut(&mut guard).oneway_todo.pop_front()
+    }
+
     /// Finds an outdated transaction that the given transaction can replace.
     ///
     /// If one is found, it is removed from the list and returned.
@@ -842,6 +1025,8 @@ struct NodeDeathInner {
     /// need behave as if the death notification didn't exist (i.e., we don't deliver anything to
     /// the user.
     aborted: bool,
+    /// When `dead` was set.
+    dead_time: Option<Ktime>,
 }
 
 /// Used to deliver notifications when a process dies.
@@ -914,6 +1099,7 @@ pub(crate) fn new(
                     cleared: false,
                     notification_done: false,
                     aborted: false,
+                    dead_time: None,
                 }, "NodeDeath::inner"),
             }
         ))
@@ -965,6 +1151,12 @@ pub(crate) fn set_notification_done(self: DArc<Self>, thread: &Thread) {
         }
     }
 
+    /// Returns the number of milliseconds since the 'dead' flag was set, if it is set.
+    pub(crate) fn ms_since_dead(&self) -> Option<i64> {
+        let dead_time = self.inner.lock().dead_time?;
+        Some(ktime_ms_delta(Ktime::ktime_get(), dead_time))
+    }
+
     /// Sets the 'dead' flag to `true` and queues work item if needed.
     pub(crate) fn set_dead(self: DArc<Self>) {
         let needs_queueing = {
@@ -973,12 +1165,21 @@ pub(crate) fn set_dead(self: DArc<Self>) {
                 false
             } else {
                 inner.dead = true;
+                inner.dead_time = Some(Ktime::ktime_get());
                 true
             }
         };
         if needs_queueing {
             // Push the death notification to the target process. There is nothing else to do if
             // it's already dead.
+            if log_enabled(BINDER_LOG_DEBUG) {
+                pr_info!(
+                    "{}: death notification {:x} for node {}\n",
+                    self.process.task.pid(),
+                    self.cookie,
+                    self.node.debug_id
+                );
+            }
             if let Some(death) = ListArc::try_from_arc_or_drop(self) {
                 let process = death.process.clone();
                 let _ = process.push_work(death);
@@ -1025,7 +1226,7 @@ impl ListItem<2> for DTRWrap<NodeDeath> {
 }
 
 impl DeliverToRead for NodeDeath {
-    fn do_work(self: DArc<Self>, _thread: &Thread, writer: &mut UserSliceWriter) -> Result<bool> {
+    fn do_work(self: DArc<Self>, thread: &Thread, writer: &mut UserSliceWriter) -> Result<bool> {
         let done = {
             let inner = self.inner.lock();
             if inner.aborted {
@@ -1046,7 +1247,18 @@ fn do_work(self: DArc<Self>, _thread: &Thread, writer: &mut UserSliceWriter) ->
             }
             // We're still holding the inner lock, so it cannot be aborted while we insert it into
             // the delivered list.
-            process_inner.death_delivered(self.clone());
+            let evicted = process_inner.death_delivered(self.clone());
+            drop(inner);
+            drop(process_inner);
+            if let Some(evicted) = evicted {
+                pr_warn!(
+                    "{}: dropped unacknowledged death notification {:#x}, limit reached\n",
+                    process.task.pid(),
+                    evicted.cookie
+                );
+                // Treat it as acknowledged, so that clearing it completes.
+                evicted.set_notification_done(thread);
+            }
             BR_DEAD_BINDER
         };
 
@@ -63,6 +63,39 @@ pub(crate) fn is_supported_policy(policy: Policy) -> bool {
     is_fair_policy(policy) || is_rt_policy(policy)
 }
 
+/// Returns the priority to use instead of `desired` for a thread currently running at `current`
+/// that keeps its scheduler policy. The priority level is only inherited when both policies are of
+/// the same class, since real-time and fair priorities can't be translated into each other.
+pub(crate) fn keep_policy(current: BinderPriority, desired: BinderPriority) -> BinderPriority {
+    if is_supported_policy(current.sched_policy)
+        && is_rt_policy(current.sched_policy) == is_rt_policy(desired.sched_policy)
+    {
+        BinderPriority {
+            sched_policy: current.sched_policy,
+            prio: desired.prio,
+        }
+    } else {
+        current
+    }
+}
+
+/// Returns `desired` lowered to the priority level of `cap` if it is higher. This never raises the
+/// priority or changes the scheduler policy of `desired`, so the level is only capped when both
+/// policies are of the same class, like in `keep_policy`.
+pub(crate) fn cap_priority(desired: BinderPriority, cap: BinderPriority) -> BinderPriority {
+    // Lower values are higher priorities.
+    if is_rt_policy(desired.sched_policy) == is_rt_policy(cap.sched_policy)
+        && desired.prio < cap.prio
+    {
+        BinderPriority {
+            sched_policy: desired.sched_policy,
+            prio: cap.prio,
+        }
+    } else {
+        desired
+    }
+}
+
 pub(crate) fn to_userspace_prio(policy: Policy, prio: Priority) -> Nice {
     if is_fair_policy(policy) {
         prio - DEFAULT_PRIO
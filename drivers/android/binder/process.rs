@@ -25,10 +25,12 @@
     seq_print,
     sync::poll::PollTable,
     sync::{
-        lock::Guard, Arc, ArcBorrow, CondVar, CondVarTimeoutResult, Mutex, SpinLock, UniqueArc,
+        lock::{spinlock::SpinLockBackend, Guard},
+        Arc, ArcBorrow, CondVar, CondVarTimeoutResult, Mutex, SpinLock, UniqueArc,
     },
-    task::Task,
-    types::{ARef, Either},
+    task::{Kuid, Task},
+    time::{ktime_ms_delta, Ktime},
+    types::{ARef, Either, ScopeGuard},
     uaccess::{UserSlice, UserSliceReader},
     workqueue::{self, Work},
 };
@@ -38,31 +40,303 @@
     context::Context,
     defs::*,
     error::{BinderError, BinderResult},
-    node::{CouldNotDeliverCriticalIncrement, CritIncrWrapper, Node, NodeDeath, NodeRef},
+    log_enabled,
+    node::{
+        CouldNotDeliverCriticalIncrement, CritIncrWrapper, Node, NodeDeath, NodeRef, UidFilter,
+    },
     prio::{self, BinderPriority},
-    range_alloc::{self, RangeAllocator},
+    range_alloc::{self, FreedRange, RangeAllocator},
     thread::{PushWorkRes, Thread},
-    DArc, DLArc, DTRWrap, DeliverToRead,
+    DArc, DLArc, DTRWrap, DeliverCode, DeliverToRead, RateLimit,
 };
 
-use core::mem::take;
+use core::mem::{size_of, take};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+/// The maximum number of freed page ranges whose release can be deferred at once.
+const DEFERRED_FREE_MAX_RANGES: usize = 16;
 
 struct Mapping {
     address: usize,
     alloc: RangeAllocator<AllocationInfo>,
+    /// Page ranges whose buffers have been freed, but which have not been passed to
+    /// `stop_using_range` yet. Their pages are still marked as in use.
+    deferred: [FreedRange; DEFERRED_FREE_MAX_RANGES],
+    deferred_count: usize,
+    /// The number of bytes in the pages of `deferred`.
+    deferred_bytes: usize,
 }
 
 impl Mapping {
     fn new(address: usize, size: usize) -> Result<Self> {
         let alloc = RangeAllocator::new(size)?;
-        Ok(Self { address, alloc })
+        Ok(Self {
+            address,
+            alloc,
+            deferred: [FreedRange::default(); DEFERRED_FREE_MAX_RANGES],
+            deferred_count: 0,
+            deferred_bytes: 0,
+        })
+    }
+
+    /// Defers the release of the pages in `range`. Returns false if there is no room for it.
+    fn defer_release(&mut self, range: FreedRange) -> bool {
+        use kernel::page::PAGE_SIZE;
+        if range.start_page_idx >= range.end_page_idx {
+            return true;
+        }
+        if self.deferred_count == DEFERRED_FREE_MAX_RANGES {
+            return false;
+        }
+        self.deferred[self.deferred_count] = range;
+        self.deferred_count += 1;
+        self.deferred_bytes += (range.end_page_idx - range.start_page_idx) * PAGE_SIZE;
+        true
+    }
+
+    /// Removes the deferred ranges that overlap the pages `start..end`, or all of them if `None`
+    /// is given, and passes them to `release`.
+    fn take_deferred(
+        &mut self,
+        overlapping: Option<(usize, usize)>,
+        mut release: impl FnMut(FreedRange),
+    ) {
+        use kernel::page::PAGE_SIZE;
+        let mut kept = 0;
+        for i in 0..self.deferred_count {
+            let range = self.deferred[i];
+            let take = match overlapping {
+                Some((start, end)) => range.start_page_idx < end && start < range.end_page_idx,
+                None => true,
+            };
+            if take {
+                self.deferred_bytes -= (range.end_page_idx - range.start_page_idx) * PAGE_SIZE;
+                release(range);
+            } else {
+                self.deferred[kept] = range;
+                kept += 1;
+            }
+        }
+        self.deferred_count = kept;
     }
 }
 
+/// Waiting longer than this for outstanding transactions to drain when freezing a process prints a
+/// warning.
+const FREEZE_WAIT_WARN_MS: i64 = 1000;
+
+static FREEZE_WAIT_RATELIMIT: RateLimit = RateLimit::new(5000, 1);
+
+static LARGE_TXN_RATELIMIT: RateLimit = RateLimit::new(5000, 1);
+
+static HANDLE_COUNT_RATELIMIT: RateLimit = RateLimit::new(5000, 1);
+
+static IOCTL_LOG_RATELIMIT: RateLimit = RateLimit::new(5000, 100);
+
+static MMAP_MAX_THREADS_RATELIMIT: RateLimit = RateLimit::new(5000, 1);
+
+static MMAP_MEMORY_LIMIT_RATELIMIT: RateLimit = RateLimit::new(5000, 1);
+
+/// Whether freezing a process other than the caller requires `CAP_SYS_ADMIN`. Set with
+/// `BINDER_SET_STRICT_FREEZE`.
+static STRICT_FREEZE: AtomicBool = AtomicBool::new(false);
+
 // bitflags for defer_work.
 const PROC_DEFER_FLUSH: u8 = 1;
 const PROC_DEFER_RELEASE: u8 = 2;
 
+/// Optional statistics about a process. These are only updated while
+/// `ProcessInner::stats_enabled` is set, and are reset when it is turned on.
+#[derive(Default)]
+pub(crate) struct ProcessStats {
+    /// The number of buffers allocated for transactions sent to this process.
+    pub(crate) buffers_allocated: u64,
+    /// The total size of those buffers.
+    pub(crate) buffer_bytes_allocated: u64,
+    /// The sizes of those buffers, in power-of-two buckets.
+    pub(crate) size_histogram: [u64; BINDER_SIZE_HISTOGRAM_BUCKETS],
+    /// Milliseconds between the death of a node owner and the `BC_DEAD_BINDER_DONE` for it, for
+    /// the most recent and the slowest death notification.
+    pub(crate) death_latency_last_ms: i64,
+    pub(crate) death_latency_max_ms: i64,
+    /// The number of `TF_DROP_IF_FULL` transactions dropped for lack of buffer space. These are
+    /// not included in `buffers_allocated`.
+    pub(crate) oneway_dropped: u64,
+    /// The number of calls to `stop_using_range` made when freeing buffers.
+    pub(crate) page_releases: u64,
+    /// The number of death notifications delivered to this process, and how many of them were
+    /// acknowledged with `BC_DEAD_BINDER_DONE`.
+    pub(crate) deaths_delivered: u64,
+    pub(crate) deaths_acknowledged: u64,
+    /// The number of times a sender exceeded the transaction storm threshold.
+    pub(crate) txn_storms: u64,
+    /// The number of times `get_node` had to retry with a `CritIncrWrapper` because the
+    /// zero-to-one increment of a node could not be delivered normally.
+    pub(crate) crit_incr_retries: u64,
+    /// The number of times a thread registered in `ready_threads` to wait for work, and how many
+    /// of those times it deregistered without having been given work.
+    pub(crate) ready_registrations: u64,
+    pub(crate) idle_deregistrations: u64,
+    /// Transactions and replies sent by this process since the last `BINDER_GET_ACTIVITY`.
+    pub(crate) activity: BinderActivity,
+    /// The number of transactions serviced by this process whose reply was delivered to the
+    /// sender, and the number for which the sender got `BR_FAILED_REPLY` instead.
+    pub(crate) replies_succeeded: u64,
+    pub(crate) replies_failed: u64,
+}
+
+impl ProcessStats {
+    fn record_buffer(&mut self, size: usize) {
+        self.buffers_allocated += 1;
+        self.buffer_bytes_allocated += size as u64;
+
+        let bucket = (usize::BITS - (size | 1).leading_zeros() - 1) as usize;
+        self.size_histogram[bucket.min(BINDER_SIZE_HISTOGRAM_BUCKETS - 1)] += 1;
+    }
+
+    fn record_death_latency(&mut self, ms: i64) {
+        self.death_latency_last_ms = ms;
+        self.death_latency_max_ms = i64::max(self.death_latency_max_ms, ms);
+    }
+}
+
+/// Stops the delivery of transactions to a process after repeated delivery failures.
+#[derive(Default)]
+struct CircuitBreaker {
+    /// The number of consecutive failures that opens the breaker. Zero disables it.
+    threshold: u32,
+    cooldown_ms: u32,
+    /// The number of consecutive failures so far.
+    failures: u32,
+    /// When the breaker was opened, if it is open.
+    opened: Option<Ktime>,
+}
+
+impl CircuitBreaker {
+    fn record_delivery(&mut self, ok: bool) {
+        if self.threshold == 0 {
+            return;
+        }
+        if ok {
+            self.failures = 0;
+            return;
+        }
+        self.failures = self.failures.saturating_add(1);
+        if self.failures >= self.threshold && self.opened.is_none() {
+            self.opened = Some(Ktime::ktime_get());
+        }
+    }
+
+    /// Returns whether transactions may be delivered. When the cooldown has elapsed, the breaker
+    /// is closed again, but with one failure short of the threshold so that a process which is
+    /// still broken is cut off immediately.
+    fn allows_delivery(&mut self) -> bool {
+        let opened = match self.opened {
+            Some(opened) => opened,
+            None => return true,
+        };
+        if ktime_ms_delta(Ktime::ktime_get(), opened) < self.cooldown_ms as i64 {
+            return false;
+        }
+        self.opened = None;
+        self.failures = self.threshold - 1;
+        true
+    }
+}
+
+/// The number of senders whose transactions are counted by `StormDetector`.
+const STORM_SENDERS: usize = 8;
+
+/// Detects senders that send transactions to a process at a high rate, by counting the
+/// transactions of each sender over one-second windows. Only the busiest senders of the window are
+/// tracked.
+#[derive(Default)]
+struct StormDetector {
+    /// The number of transactions per second from a single sender that is reported. Zero disables
+    /// the detection.
+    threshold: u32,
+    window_start: Option<Ktime>,
+    /// The pids of the senders of the current window, and their transaction counts. Entries with
+    /// a zero count are unused.
+    senders: [(i32, u32); STORM_SENDERS],
+}
+
+impl StormDetector {
+    /// Counts a transaction from `pid`. Returns true when the sender reaches the threshold, which
+    /// happens at most once per window.
+    fn record(&mut self, pid: i32) -> bool {
+        if self.threshold == 0 {
+            return false;
+        }
+        let now = Ktime::ktime_get();
+        match self.window_start {
+            Some(start) if ktime_ms_delta(now, start) < 1000 => {}
+            _ => {
+                self.window_start = Some(now);
+                self.senders = Default::default();
+            }
+        }
+
+        let entry = match self
+            .senders
+            .iter()
+            .position(|(p, count)| *p == pid && *count > 0)
+        {
+            Some(i) => &mut self.senders[i],
+            None => {
+                // Replace the sender with the fewest transactions.
+                let entry = self
+                    .senders
+                    .iter_mut()
+                    .min_by_key(|(_, count)| *count)
+                    .unwrap();
+                *entry = (pid, 0);
+                entry
+            }
+        };
+        entry.1 += 1;
+        entry.1 == self.threshold
+    }
+}
+
+/// Limits how fast a process can create nodes. Holds up to one second worth of creations.
+#[derive(Default)]
+struct NodeCreationLimit {
+    /// The number of nodes that may be created per second. Zero means unlimited.
+    rate: u32,
+    tokens: u32,
+    last_refill: Option<Ktime>,
+}
+
+impl NodeCreationLimit {
+    /// Takes a token for a new node. Returns false if the rate has been exceeded.
+    fn try_take(&mut self) -> bool {
+        if self.rate == 0 {
+            return true;
+        }
+        let now = Ktime::ktime_get();
+        match self.last_refill {
+            Some(last) => {
+                let elapsed_ms = ktime_ms_delta(now, last).max(0) as u64;
+                let refill = elapsed_ms * self.rate as u64 / 1000;
+                if refill > 0 {
+                    self.tokens = u64::min(self.tokens as u64 + refill, self.rate as u64) as u32;
+                    self.last_refill = Some(now);
+                }
+            }
+            None => {
+                self.tokens = self.rate;
+                self.last_refill = Some(now);
+            }
+        }
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+}
+
 /// The fields of `Process` protected by the spinlock.
 pub(crate) struct ProcessInner {
     is_manager: bool,
@@ -73,29 +347,78 @@ pub(crate) struct ProcessInner {
     nodes: RBTree<u64, DArc<Node>>,
     mapping: Option<Mapping>,
     work: List<DTRWrap<dyn DeliverToRead>>,
+    /// Work for high-priority nodes, which is delivered before the work in `work`.
+    urgent_work: List<DTRWrap<dyn DeliverToRead>>,
     delivered_deaths: List<DTRWrap<NodeDeath>, 2>,
+    /// The number of death notifications in `delivered_deaths`.
+    delivered_death_count: u32,
+    /// The maximum value of `delivered_death_count`. When it is reached, the oldest delivered
+    /// death notification is dropped to make room for a new one. Zero means unlimited.
+    max_delivered_deaths: u32,
 
     /// The number of requested threads that haven't registered yet.
     requested_thread_count: u32,
     /// The maximum number of threads used by the process thread pool.
     max_threads: u32,
+    /// A new thread is requested when no more than this many threads are ready.
+    spare_threads: u32,
     /// The number of threads the started and registered with the thread pool.
     started_thread_count: u32,
 
     /// Bitmap of deferred work to do.
     defer_work: u8,
+    /// How long a flush waits for `outstanding_txns` to drain before the loopers exit, in
+    /// milliseconds. Zero means that the loopers exit immediately.
+    flush_drain_ms: u32,
+    /// The number of flushes waiting in `freeze_wait` for `outstanding_txns` to drain.
+    flush_draining: u32,
 
     /// Number of transactions to be transmitted before processes in freeze_wait
     /// are woken up.
     outstanding_txns: u32,
+    /// The number of synchronous transactions sent to this process that haven't finished yet.
+    sync_txns: u32,
+    /// The maximum value of `sync_txns`. Zero means unlimited.
+    max_sync_txns: u32,
+    /// While set, work pushed to the process is queued without waking up any threads.
+    delivery_paused: bool,
     /// Process is frozen and unable to service binder transactions.
     pub(crate) is_frozen: bool,
+    /// When `ioctl_freeze` started waiting for `outstanding_txns` to drain, if it is waiting.
+    freeze_wait_start: Option<Ktime>,
     /// Process received sync transactions since last frozen.
     pub(crate) sync_recv: bool,
     /// Process received async transactions since last frozen.
     pub(crate) async_recv: bool,
     /// Check for oneway spam
     oneway_spam_detection_enabled: bool,
+    /// Buffer usage (in percent of the mapping) above which `poll` reports `POLLPRI`. Zero means
+    /// that the notification is disabled.
+    buffer_pressure_threshold: u32,
+    /// Whether buffer usage is currently above `buffer_pressure_threshold`.
+    buffer_pressure: bool,
+    /// Whether transactions to handle zero are rejected unless the process holds a reference to
+    /// the context manager.
+    strict_handles: bool,
+    /// Whether allocating a buffer before the process has mapped its buffer space fails with
+    /// `ENXIO`, instead of reporting the process as dead.
+    strict_mmap: bool,
+    /// Whether a reply fails with `BR_DEAD_REPLY` when the thread that made the transaction exited
+    /// before it could be delivered, instead of completing as if it had been delivered.
+    dead_reply_errors: bool,
+    /// The number of bytes of freed pages at which their release is done in bulk. Zero means
+    /// that pages are released as soon as their buffers are freed.
+    deferred_free_bytes: u32,
+    /// The last error encountered when sending a transaction from any thread of this process.
+    last_error: BinderLastError,
+    /// The protocol version returned by the last `BINDER_VERSION` ioctl, if the process made one.
+    queried_version: Option<i32>,
+    circuit_breaker: CircuitBreaker,
+    node_creation_limit: NodeCreationLimit,
+    txn_storms: StormDetector,
+    /// Whether optional statistics are collected in `stats`.
+    pub(crate) stats_enabled: bool,
+    pub(crate) stats: ProcessStats,
 }
 
 impl ProcessInner {
@@ -108,16 +431,39 @@ fn new() -> Self {
             mapping: None,
             nodes: RBTree::new(),
             work: List::new(),
+            urgent_work: List::new(),
             delivered_deaths: List::new(),
+            delivered_death_count: 0,
+            max_delivered_deaths: 0,
             requested_thread_count: 0,
             max_threads: 0,
+            spare_threads: 0,
             started_thread_count: 0,
             defer_work: 0,
+            flush_drain_ms: 0,
+            flush_draining: 0,
             outstanding_txns: 0,
+            sync_txns: 0,
+            max_sync_txns: 0,
+            delivery_paused: false,
             is_frozen: false,
+            freeze_wait_start: None,
             sync_recv: false,
             async_recv: false,
             oneway_spam_detection_enabled: false,
+            buffer_pressure_threshold: 0,
+            buffer_pressure: false,
+            strict_handles: false,
+            strict_mmap: false,
+            dead_reply_errors: false,
+            deferred_free_bytes: 0,
+            last_error: BinderLastError::default(),
+            queried_version: None,
+            circuit_breaker: CircuitBreaker::default(),
+            node_creation_limit: NodeCreationLimit::default(),
+            txn_storms: StormDetector::default(),
+            stats_enabled: false,
+            stats: ProcessStats::default(),
         }
     }
 
@@ -134,6 +480,17 @@ pub(crate) fn push_work(
         &mut self,
         work: DLArc<dyn DeliverToRead>,
     ) -> Result<(), (BinderError, DLArc<dyn DeliverToRead>)> {
+        // Check `is_dead` before looking for a ready thread. Threads stay in `ready_threads` until
+        // `deferred_release` releases them, and work must not reach them once teardown started.
+        if self.is_dead {
+            return Err((BinderError::new_dead(), work));
+        }
+
+        if self.delivery_paused {
+            self.queue_work(work);
+            return Ok(());
+        }
+
         // Try to find a ready thread to which to push the work.
         if let Some(thread) = self.ready_threads.pop_front() {
             work.on_thread_selected(&thread);
@@ -141,11 +498,15 @@ pub(crate) fn push_work(
             // Push to thread while holding state lock. This prevents the thread from giving up
             // (for example, because of a signal) when we're about to deliver work.
             match thread.push_work(work) {
-                PushWorkRes::Ok => Ok(()),
-                PushWorkRes::FailedDead(work) => Err((BinderError::new_dead(), work)),
+                PushWorkRes::Ok => {
+                    self.record_delivery(true);
+                    Ok(())
+                }
+                PushWorkRes::FailedDead(work) => {
+                    self.record_delivery(false);
+                    Err((BinderError::new_dead(), work))
+                }
             }
-        } else if self.is_dead {
-            Err((BinderError::new_dead(), work))
         } else {
             let sync = work.should_sync_wakeup();
 
@@ -161,7 +522,7 @@ pub(crate) fn push_work(
             //    wake them all up. We wake all because we don't know whether
             //    a thread that called into (e)poll is handling non-binder
             //    work currently.
-            self.work.push_back(work);
+            self.queue_work(work);
 
             // Wake up polling threads, if any.
             for thread in self.threads.values() {
@@ -172,7 +533,101 @@ pub(crate) fn push_work(
         }
     }
 
+    /// Delivers the work queued while delivery was paused. Ready threads are given work directly,
+    /// and polling threads are woken up if work remains.
+    fn resume_delivery(&mut self) {
+        self.delivery_paused = false;
+        // Once the process is dead, the queued work is only there to be cancelled.
+        if self.is_dead {
+            return;
+        }
+        while self.has_queued_work() {
+            let thread = match self.ready_threads.pop_front() {
+                Some(thread) => thread,
+                None => break,
+            };
+            let work = match self.pop_work() {
+                Some(work) => work,
+                None => break,
+            };
+            work.on_thread_selected(&thread);
+            if let PushWorkRes::FailedDead(work) = thread.push_work(work) {
+                if work.is_high_priority() {
+                    self.urgent_work.push_front(work);
+                } else {
+                    self.work.push_front(work);
+                }
+            }
+        }
+
+        if self.has_queued_work() {
+            for thread in self.threads.values() {
+                thread.notify_if_poll_ready(true);
+            }
+        }
+    }
+
+    /// Queues work on the process, in `urgent_work` if it is for a high-priority node.
+    fn queue_work(&mut self, work: DLArc<dyn DeliverToRead>) {
+        work.mark_queued();
+        if work.is_high_priority() {
+            self.urgent_work.push_back(work);
+        } else {
+            self.work.push_back(work);
+        }
+    }
+
+    /// Takes the next queued work item, taking high-priority work first.
+    fn pop_work(&mut self) -> Option<DLArc<dyn DeliverToRead>> {
+        self.urgent_work
+            .pop_front()
+            .or_else(|| self.work.pop_front())
+    }
+
+    fn has_queued_work(&self) -> bool {
+        !self.urgent_work.is_empty() || !self.work.is_empty()
+    }
+
+    /// Records whether work could be handed to a thread, for the circuit breaker.
+    pub(crate) fn record_delivery(&mut self, ok: bool) {
+        self.circuit_breaker.record_delivery(ok);
+    }
+
+    /// Counts an incoming transaction from `pid`. Returns true if the sender just exceeded the
+    /// transaction storm threshold.
+    pub(crate) fn record_sender(&mut self, pid: i32) -> bool {
+        let storm = self.txn_storms.record(pid);
+        if storm && self.stats_enabled {
+            self.stats.txn_storms += 1;
+        }
+        storm
+    }
+
+    /// Returns whether the circuit breaker currently lets transactions through.
+    pub(crate) fn allows_delivery(&mut self) -> bool {
+        self.circuit_breaker.allows_delivery()
+    }
+
+    fn count_page_releases(&mut self, count: u64) {
+        if self.stats_enabled {
+            self.stats.page_releases += count;
+        }
+    }
+
+    /// Returns how long the oldest item of the work list has been queued, in milliseconds, or zero
+    /// if the list is empty.
+    fn oldest_work_age_ms(&self) -> i64 {
+        let oldest = |list: &List<DTRWrap<dyn DeliverToRead>>| {
+            list.iter().next().map_or(0, |work| work.ms_since_queued())
+        };
+        i64::max(oldest(&self.urgent_work), oldest(&self.work))
+    }
+
     /// Push work to be cancelled. Only used during process teardown.
+    ///
+    /// The work is never delivered: `push_work`, `resume_delivery` and `get_work_or_register`
+    /// all ignore the queue once `is_dead` is set, so only the draining loop in
+    /// `deferred_release` can take it.
     pub(crate) fn push_work_for_release(&mut self, work: DLArc<dyn DeliverToRead>) {
         self.work.push_back(work);
     }
@@ -269,6 +724,7 @@ fn pull_delivered_death(&mut self, cookie: usize) -> Option<DArc<NodeDeath>> {
         let mut cursor_opt = self.delivered_deaths.cursor_front();
         while let Some(cursor) = cursor_opt {
             if cursor.current().cookie == cookie {
+                self.delivered_death_count -= 1;
                 return Some(cursor.remove().into_arc());
             }
             cursor_opt = cursor.next();
@@ -276,18 +732,65 @@ fn pull_delivered_death(&mut self, cookie: usize) -> Option<DArc<NodeDeath>> {
         None
     }
 
-    pub(crate) fn death_delivered(&mut self, death: DArc<NodeDeath>) {
-        if let Some(death) = ListArc::try_from_arc_or_drop(death) {
-            self.delivered_deaths.push_back(death);
-        } else {
+    /// Adds a death notification to `delivered_deaths`, where it stays until it is acknowledged.
+    ///
+    /// If `max_delivered_deaths` has been reached, the oldest delivered notification is removed
+    /// and returned. The caller should treat it as acknowledged after releasing the lock.
+    #[must_use]
+    pub(crate) fn death_delivered(&mut self, death: DArc<NodeDeath>) -> Option<DArc<NodeDeath>> {
+        if self.stats_enabled {
+            self.stats.deaths_delivered += 1;
+        }
+        let Some(death) = ListArc::try_from_arc_or_drop(death) else {
             pr_warn!("Notification added to `delivered_deaths` twice.");
+            return None;
+        };
+
+        let mut evicted = None;
+        if self.max_delivered_deaths != 0 && self.delivered_death_count >= self.max_delivered_deaths
+        {
+            if let Some(oldest) = self.delivered_deaths.pop_front() {
+                self.delivered_death_count -= 1;
+                evicted = Some(oldest.into_arc());
+            }
         }
+        self.delivered_deaths.push_back(death);
+        self.delivered_death_count += 1;
+        evicted
     }
 
     pub(crate) fn add_outstanding_txn(&mut self) {
         self.outstanding_txns += 1;
     }
 
+    /// Counts a new synchronous transaction in `sync_txns`. Returns false if `max_sync_txns` has
+    /// been reached.
+    pub(crate) fn try_add_sync_txn(&mut self) -> bool {
+        if self.max_sync_txns != 0 && self.sync_txns >= self.max_sync_txns {
+            return false;
+        }
+        self.sync_txns += 1;
+        true
+    }
+
+    /// Recomputes whether the buffer usage is above the registered threshold, and wakes up polling
+    /// threads when the threshold is crossed.
+    fn update_buffer_pressure(&mut self) {
+        let above = match &self.mapping {
+            Some(mapping) if self.buffer_pressure_threshold != 0 => {
+                mapping.alloc.allocated_size() * 100
+                    > mapping.alloc.size() * self.buffer_pressure_threshold as usize
+            }
+            _ => false,
+        };
+        if above && !self.buffer_pressure {
+            for thread in self.threads.values() {
+                thread.notify_if_polling();
+            }
+        }
+        self.buffer_pressure = above;
+    }
+
     fn txns_pending_locked(&self) -> bool {
         if self.outstanding_txns > 0 {
             return true;
@@ -315,6 +818,8 @@ pub(crate) struct NodeRefInfo {
     handle: u32,
     /// The process that has a handle to the node.
     pub(crate) process: Arc<Process>,
+    /// The number of transactions that `process` has sent to the node.
+    txn_seq: AtomicU64,
 }
 
 impl NodeRefInfo {
@@ -331,9 +836,15 @@ fn new(node_ref: NodeRef, handle: u32, process: Arc<Process>) -> impl PinInit<Se
             links <- ListLinks::new(),
             handle,
             process,
+            txn_seq: AtomicU64::new(0),
         })
     }
 
+    /// Returns the sequence number of the next transaction sent through this reference.
+    pub(crate) fn next_txn_seq(&self) -> u64 {
+        self.txn_seq.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
     kernel::list::define_list_arc_field_getter! {
         pub(crate) fn death(&mut self<{Self::LIST_PROC}>) -> &mut Option<DArc<NodeDeath>> { death }
         pub(crate) fn node_ref(&mut self<{Self::LIST_PROC}>) -> &mut NodeRef { node_ref }
@@ -365,6 +876,10 @@ struct ProcessNodeRefs {
     /// Used to look up nodes without knowing their local 32-bit id. The usize is the address of
     /// the underlying `Node` struct as returned by `Node::global_id`.
     by_node: RBTree<usize, u32>,
+    /// The number of entries in `by_handle`.
+    handle_count: u32,
+    /// The lowest handle used for nodes other than the context manager.
+    handle_base: u32,
 }
 
 impl ProcessNodeRefs {
@@ -372,6 +887,8 @@ fn new() -> Self {
         Self {
             by_handle: RBTree::new(),
             by_node: RBTree::new(),
+            handle_count: 0,
+            handle_base: 1,
         }
     }
 }
@@ -399,11 +916,35 @@ pub(crate) struct Process {
     #[pin]
     pub(crate) pages: ShrinkablePageRange,
 
+    // When this process was created. Set once in `new`.
+    created: Ktime,
+
+    // Whether every ioctl on this process is logged.
+    ioctl_log: AtomicBool,
+
+    // Whether a checksum of each buffer allocated in this process is stored, and verified when
+    // the buffer is freed.
+    buffer_checksums: AtomicBool,
+
+    // How long transactions sent by this process wait for buffer space in the target process, in
+    // milliseconds. Zero means that they fail immediately when there is not enough space.
+    buffer_wait_ms: AtomicU32,
+
+    // Counters for acquisitions of `inner` through `lock_inner`, which are only updated while
+    // stats are enabled, so that contention on the lock can be observed.
+    lock_stats: AtomicBool,
+    inner_locks: AtomicU64,
+    inner_contended: AtomicU64,
+
     // Waitqueue of processes waiting for all outstanding transactions to be
     // processed.
     #[pin]
     freeze_wait: CondVar,
 
+    // Waitqueue of transactions waiting for buffer space in this process.
+    #[pin]
+    buffer_wait: CondVar,
+
     // Node references are in a different lock to avoid recursive acquisition when
     // incrementing/decrementing a node in another process.
     #[pin]
@@ -457,29 +998,171 @@ fn run(me: Arc<Self>) {
 impl Process {
     fn new(ctx: Arc<Context>, cred: ARef<Credential>) -> Result<Arc<Self>> {
         let current = kernel::current!();
+        let mut inner = ProcessInner::new();
+        inner.max_threads = ctx.default_max_threads.load(Ordering::Relaxed);
         let list_process = ListArc::pin_init(try_pin_init!(Process {
             ctx,
             cred,
             default_priority: prio::get_default_prio_from_task(current),
-            inner <- kernel::new_spinlock!(ProcessInner::new(), "Process::inner"),
+            inner <- kernel::new_spinlock!(inner, "Process::inner"),
             pages <- ShrinkablePageRange::new(&super::BINDER_SHRINKER),
+            created: Ktime::ktime_get(),
+            ioctl_log: AtomicBool::new(false),
+            buffer_wait_ms: AtomicU32::new(0),
+            buffer_checksums: AtomicBool::new(false),
+            lock_stats: AtomicBool::new(false),
+            inner_locks: AtomicU64::new(0),
+            inner_contended: AtomicU64::new(0),
             node_refs <- kernel::new_mutex!(ProcessNodeRefs::new(), "Process::node_refs"),
             freeze_wait <- kernel::new_condvar!("Process::freeze_wait"),
+            buffer_wait <- kernel::new_condvar!("Process::buffer_wait"),
             task: current.group_leader().into(),
             defer_work <- kernel::new_work!("Process::defer_work"),
             links <- ListLinks::new(),
         }))?;
 
         let process = list_process.clone_arc();
-        process.ctx.register_process(list_process);
+        process.ctx.register_process(list_process)?;
+
+        if log_enabled(BINDER_LOG_INFO) {
+            pr_info!("{}: opened {}\n", process.task.pid(), &*process.ctx.name);
+        }
 
         Ok(process)
     }
 
+    /// Locks `inner` on a hot path. While stats are enabled, this counts the acquisitions and how
+    /// many of them found the lock already held. Otherwise it costs a single relaxed load.
+    pub(crate) fn lock_inner(&self) -> Guard<'_, ProcessInner, SpinLockBackend> {
+        if self.lock_stats.load(Ordering::Relaxed) {
+            self.inner_locks.fetch_add(1, Ordering::Relaxed);
+            if let Some(guard) = self.inner.try_lock() {
+                return guard;
+            }
+            self.inner_contended.fetch_add(1, Ordering::Relaxed);
+        }
+        self.inner.lock()
+    }
+
+    /// Returns the time since this process opened the binder device, in milliseconds.
+    pub(crate) fn uptime_ms(&self) -> i64 {
+        ktime_ms_delta(Ktime::ktime_get(), self.created)
+    }
+
+    fn debug_print_created(&self, m: &mut SeqFile) {
+        seq_print!(
+            m,
+            "  created: {} ns, uptime: {} ms\n",
+            self.created.to_ns(),
+            self.uptime_ms()
+        );
+    }
+
+    #[inline(never)]
+    pub(crate) fn debug_print_stats(&self, m: &mut SeqFile, ctx: &Context) -> Result<()> {
+        seq_print!(m, "proc {}\n", self.task.pid_in_current_ns());
+        seq_print!(m, "context {}\n", &*ctx.name);
+        self.debug_print_created(m);
+
+        let inner = self.inner.lock();
+        seq_print!(
+            m,
+            "  threads: {}\n  requested threads: {}+{}/{}\n",
+            inner.threads.iter().count(),
+            inner.requested_thread_count,
+            inner.started_thread_count,
+            inner.max_threads,
+        );
+        seq_print!(m, "  nodes: {}\n", inner.nodes.iter().count());
+        if let Some(mapping) = &inner.mapping {
+            seq_print!(
+                m,
+                "  buffer: {}/{} bytes\n  oneway spam detected: {}\n",
+                mapping.alloc.allocated_size(),
+                mapping.alloc.size(),
+                mapping.alloc.oneway_spam_detected,
+            );
+        }
+        if inner.stats_enabled {
+            let stats = &inner.stats;
+            seq_print!(
+                m,
+                "  buffers allocated: {} ({} bytes)\n",
+                stats.buffers_allocated,
+                stats.buffer_bytes_allocated,
+            );
+            seq_print!(m, "  oneway dropped: {}\n", stats.oneway_dropped);
+            seq_print!(m, "  page releases: {}\n", stats.page_releases);
+            seq_print!(m, "  transaction storms: {}\n", stats.txn_storms);
+            seq_print!(
+                m,
+                "  critical increment retries: {}\n",
+                stats.crit_incr_retries
+            );
+            seq_print!(
+                m,
+                "  ready thread registrations: {} ({} without work)\n",
+                stats.ready_registrations,
+                stats.idle_deregistrations,
+            );
+            seq_print!(
+                m,
+                "  transactions serviced: {} succeeded {} failed\n",
+                stats.replies_succeeded,
+                stats.replies_failed,
+            );
+            seq_print!(
+                m,
+                "  inner lock: {} contended of {}\n",
+                self.inner_contended.load(Ordering::Relaxed),
+                self.inner_locks.load(Ordering::Relaxed),
+            );
+            seq_print!(
+                m,
+                "  death notifications: {} delivered {} acknowledged\n",
+                stats.deaths_delivered,
+                stats.deaths_acknowledged,
+            );
+            seq_print!(
+                m,
+                "  death notification latency: last {}ms max {}ms\n",
+                stats.death_latency_last_ms,
+                stats.death_latency_max_ms,
+            );
+        }
+        drop(inner);
+
+        let (refs, strong, weak) = self.ref_count_totals();
+        seq_print!(m, "  refs: {refs} s {strong} w {weak}\n");
+
+        Ok(())
+    }
+
+    /// Returns the number of outgoing references of this process, along with the sum of their
+    /// strong and weak counts.
+    pub(crate) fn ref_count_totals(&self) -> (usize, usize, usize) {
+        let mut refs = self.node_refs.lock();
+        let mut count = 0;
+        let mut strong = 0;
+        let mut weak = 0;
+        for r in refs.by_handle.values_mut() {
+            let (s, w) = r.node_ref().get_count();
+            count += 1;
+            strong += s;
+            weak += w;
+        }
+        (count, strong, weak)
+    }
+
     #[inline(never)]
     pub(crate) fn debug_print(&self, m: &mut SeqFile, ctx: &Context) -> Result<()> {
         seq_print!(m, "proc {}\n", self.task.pid_in_current_ns());
         seq_print!(m, "context {}\n", &*ctx.name);
+        self.debug_print_created(m);
+        match self.inner.lock().queried_version {
+            Some(version) => seq_print!(m, "  protocol version: {}\n", version),
+            None => seq_print!(m, "  protocol version: unqueried\n"),
+        }
 
         let mut all_threads = Vec::new();
         let mut all_nodes = Vec::new();
@@ -509,7 +1192,7 @@ pub(crate) fn debug_print(&self, m: &mut SeqFile, ctx: &Context) -> Result<()> {
         }
 
         for thread in all_threads {
-            thread.debug_print(m);
+            thread.debug_print(m)?;
         }
 
         let mut inner = self.inner.lock();
@@ -536,8 +1219,16 @@ pub(crate) fn debug_print(&self, m: &mut SeqFile, ctx: &Context) -> Result<()> {
         drop(refs);
 
         let inner = self.inner.lock();
-        for work in &inner.work {
-            work.debug_print(m, "  ", "  pending transaction")?;
+        if let Some(start) = inner.freeze_wait_start {
+            seq_print!(
+                m,
+                "  freeze wait: {}ms, {} outstanding transactions\n",
+                ktime_ms_delta(Ktime::ktime_get(), start),
+                inner.outstanding_txns
+            );
+        }
+        for work in inner.urgent_work.iter().chain(inner.work.iter()) {
+            work.debug_print(m, "  ", "  pending transaction ")?;
         }
         for _death in &inner.delivered_deaths {
             seq_print!(m, "  has delivered dead binder\n");
@@ -552,7 +1243,7 @@ pub(crate) fn debug_print(&self, m: &mut SeqFile, ctx: &Context) -> Result<()> {
 
     /// Attempts to fetch a work item from the process queue.
     pub(crate) fn get_work(&self) -> Option<DLArc<dyn DeliverToRead>> {
-        self.inner.lock().work.pop_front()
+        self.inner.lock().pop_work()
     }
 
     /// Attempts to fetch a work item from the process queue. If none is available, it registers the
@@ -565,12 +1256,18 @@ pub(crate) fn get_work_or_register<'a>(
         &'a self,
         thread: &'a Arc<Thread>,
     ) -> Either<DLArc<dyn DeliverToRead>, Registration<'a>> {
-        let mut inner = self.inner.lock();
-        // Try to get work from the process queue.
-        if let Some(work) = inner.work.pop_front() {
-            return Either::Left(work);
+        let mut inner = self.lock_inner();
+        // Try to get work from the process queue, unless delivery is paused. Once the process is
+        // dead, the queue holds work pushed for release, which is cancelled by `deferred_release`.
+        if !inner.delivery_paused && !inner.is_dead {
+            if let Some(work) = inner.pop_work() {
+                return Either::Left(work);
+            }
         }
 
+        // The thread is about to wait, so this is a good time to release deferred pages.
+        self.release_deferred_pages(&mut inner);
+
         // Register the thread as ready.
         Either::Right(Registration::new(thread, &mut inner))
     }
@@ -595,6 +1292,9 @@ fn get_current_thread(self: ArcBorrow<'_, Self>) -> Result<Arc<Thread>> {
         // Allocate a new `Thread` without holding any locks.
         let reservation = RBTree::try_reserve_node()?;
         let ta: Arc<Thread> = Thread::new(id, self.into())?;
+        if log_enabled(BINDER_LOG_INFO) {
+            pr_info!("{}:{} new thread\n", self.task.pid(), id);
+        }
 
         let mut inner = self.inner.lock();
         match inner.threads.entry(id) {
@@ -611,7 +1311,7 @@ fn get_current_thread(self: ArcBorrow<'_, Self>) -> Result<Arc<Thread>> {
 
     pub(crate) fn push_work(&self, work: DLArc<dyn DeliverToRead>) -> BinderResult {
         // If push_work fails, drop the work item outside the lock.
-        let res = self.inner.lock().push_work(work);
+        let res = self.lock_inner().push_work(work);
         match res {
             Ok(()) => Ok(()),
             Err((err, work)) => {
@@ -672,8 +1372,19 @@ fn get_node_inner(
         if let Some(node) = inner.get_existing_node(ptr, cookie)? {
             return Ok(inner.new_node_ref_with_thread(node, strong, thread, wrapper));
         }
+        if !inner.node_creation_limit.try_take() {
+            return Err(EAGAIN);
+        }
 
         inner.nodes.insert(rbnode);
+        if log_enabled(BINDER_LOG_INFO) {
+            pr_info!(
+                "{}: new node {} u{:016x}\n",
+                self.task.pid(),
+                node.debug_id,
+                ptr
+            );
+        }
         // This can only fail if someone has already pushed the node to a list, but we just created
         // it and still hold the lock, so it can't fail right now.
         let node_ref = inner
@@ -698,6 +1409,10 @@ pub(crate) fn get_node(
                 Ok(Ok(node_ref)) => return Ok(node_ref),
                 Ok(Err(CouldNotDeliverCriticalIncrement)) => {
                     wrapper = Some(CritIncrWrapper::new()?);
+                    let mut inner = self.inner.lock();
+                    if inner.stats_enabled {
+                        inner.stats.crit_incr_retries += 1;
+                    }
                 }
             }
         }
@@ -739,7 +1454,7 @@ pub(crate) fn insert_or_update_handle(
         }
 
         // Find id.
-        let mut target: u32 = if is_mananger { 0 } else { 1 };
+        let mut target: u32 = if is_mananger { 0 } else { refs.handle_base };
         for handle in refs.by_handle.keys() {
             if *handle > target {
                 break;
@@ -774,18 +1489,44 @@ pub(crate) fn insert_or_update_handle(
 
         refs.by_node.insert(reserve1.into_node(gid, target));
         refs.by_handle.insert(reserve2.into_node(target, info_proc));
+        refs.handle_count += 1;
+
+        let warn_count = self.ctx.handle_warn_count.load(Ordering::Relaxed);
+        if refs.handle_count == warn_count && HANDLE_COUNT_RATELIMIT.allow() {
+            pr_warn!(
+                "{}: process holds {} handles\n",
+                self.task.pid(),
+                refs.handle_count
+            );
+        }
         Ok(target)
     }
 
     pub(crate) fn get_transaction_node(&self, handle: u32) -> BinderResult<NodeRef> {
         // When handle is zero, try to get the context manager.
         if handle == 0 {
+            // In strict mode, a zero handle is assumed to be an uninitialized handle unless the
+            // process has explicitly taken a reference on the context manager.
+            let strict = self.inner.lock().strict_handles;
+            if strict && self.node_refs.lock().by_handle.get(&0).is_none() {
+                return Err(EINVAL.into());
+            }
             Ok(self.ctx.get_manager_node(true)?)
         } else {
             Ok(self.get_node_from_handle(handle, true)?)
         }
     }
 
+    /// Returns the sequence number of the next transaction sent through `handle`, or zero if this
+    /// process has no reference with that handle.
+    pub(crate) fn next_txn_seq(&self, handle: u32) -> u64 {
+        self.node_refs
+            .lock()
+            .by_handle
+            .get(&handle)
+            .map_or(0, |info| info.next_txn_seq())
+    }
+
     pub(crate) fn get_node_from_handle(&self, handle: u32, strong: bool) -> Result<NodeRef> {
         self.node_refs
             .lock()
@@ -800,6 +1541,9 @@ pub(crate) fn remove_from_delivered_deaths(&self, death: &DArc<NodeDeath>) {
         let mut inner = self.inner.lock();
         // SAFETY: By the invariant on the `delivered_links` field, this is the right linked list.
         let removed = unsafe { inner.delivered_deaths.remove(death) };
+        if removed.is_some() {
+            inner.delivered_death_count -= 1;
+        }
         drop(inner);
         drop(removed);
     }
@@ -839,6 +1583,7 @@ pub(crate) fn update_ref(
                 let id = info.node_ref().node.global_id();
                 refs.by_handle.remove(&handle);
                 refs.by_node.remove(&id);
+                refs.handle_count -= 1;
             }
         }
         Ok(())
@@ -865,20 +1610,85 @@ pub(crate) fn inc_ref_done(&self, reader: &mut UserSliceReader, strong: bool) ->
         Ok(())
     }
 
+    pub(crate) fn buffer_checksums_enabled(&self) -> bool {
+        self.buffer_checksums.load(Ordering::Relaxed)
+    }
+
+    /// Returns how long transactions sent by this process may wait for buffer space, in
+    /// milliseconds.
+    pub(crate) fn buffer_wait_ms(&self) -> u32 {
+        self.buffer_wait_ms.load(Ordering::Relaxed)
+    }
+
+    /// Allocates a buffer of `size` bytes for a transaction from `from_pid`.
+    ///
+    /// If there is not enough space, this waits for up to `wait_ms` milliseconds for buffers to be
+    /// freed, and then fails with `EAGAIN`. The wait is interrupted by signals, which fail the
+    /// allocation with `ERESTARTSYS`.
     pub(crate) fn buffer_alloc(
         self: &Arc<Self>,
         size: usize,
         is_oneway: bool,
         from_pid: i32,
+        wait_ms: u32,
     ) -> BinderResult<Allocation> {
         use kernel::page::PAGE_SIZE;
 
-        let alloc = range_alloc::ReserveNewBox::try_new()?;
-        let mut inner = self.inner.lock();
-        let mapping = inner.mapping.as_mut().ok_or_else(BinderError::new_dead)?;
-        let offset = mapping
-            .alloc
-            .reserve_new(size, is_oneway, from_pid, alloc)?;
+        let warn_size = self.ctx.large_txn_warn_size.load(Ordering::Relaxed);
+        if warn_size != 0 && size > warn_size && LARGE_TXN_RATELIMIT.allow() {
+            pr_warn!(
+                "{}: large transaction of {} bytes from {}\n",
+                self.task.pid(),
+                size,
+                from_pid
+            );
+        }
+
+        let mut jiffies = if wait_ms > 0 {
+            kernel::time::msecs_to_jiffies(wait_ms)
+        } else {
+            0
+        };
+        let (mut inner, offset) = loop {
+            let alloc = range_alloc::ReserveNewBox::try_new()?;
+            let mut inner = self.lock_inner();
+            if inner.mapping.is_none() && inner.strict_mmap && !inner.is_dead {
+                return Err(ENXIO.into());
+            }
+            let mapping = inner
+                .mapping
+                .as_mut()
+                .ok_or_else(|| BinderError::new_dead_with(ESRCH))?;
+            match mapping.alloc.reserve_new(size, is_oneway, from_pid, alloc) {
+                Ok(offset) => break (inner, offset),
+                Err(err) if err == ENOSPC && jiffies > 0 => {}
+                Err(err) => return Err(err.into()),
+            }
+
+            // Wait for a buffer to be freed, and try again.
+            match self
+                .buffer_wait
+                .wait_interruptible_timeout(&mut inner, jiffies)
+            {
+                CondVarTimeoutResult::Signal { .. } => return Err(ERESTARTSYS.into()),
+                CondVarTimeoutResult::Woken { jiffies: remaining } => jiffies = remaining,
+                CondVarTimeoutResult::Timeout => return Err(EAGAIN.into()),
+            }
+            if inner.is_dead {
+                return Err(BinderError::new_dead());
+            }
+        };
+        // The lock has been held since the buffer was reserved, so the mapping is still there.
+        let mapping = inner.mapping.as_mut().unwrap();
+
+        // Release the deferred ranges that share pages with the new buffer, so that the
+        // `stop_using_range` for them can't happen after the `use_range` below.
+        let mut releases = 0;
+        let first_page = offset / PAGE_SIZE;
+        let end_page = (offset + size + (PAGE_SIZE - 1)) / PAGE_SIZE;
+        mapping.take_deferred(Some((first_page, end_page)), |range| {
+            self.release_pages(range, &mut releases)
+        });
 
         let res = Allocation::new(
             self.clone(),
@@ -887,6 +1697,11 @@ pub(crate) fn buffer_alloc(
             mapping.address + offset,
             mapping.alloc.oneway_spam_detected,
         );
+        inner.count_page_releases(releases);
+        inner.update_buffer_pressure();
+        if inner.stats_enabled {
+            inner.stats.record_buffer(size);
+        }
         drop(inner);
 
         // This allocation will be marked as in use until the `Allocation` is used to free it.
@@ -899,10 +1714,7 @@ pub(crate) fn buffer_alloc(
         // We will not call `stop_using_range` in parallel with this on the same page, because the
         // allocation can only be removed via the destructor of the `Allocation` object that we
         // currently own.
-        match self.pages.use_range(
-            offset / PAGE_SIZE,
-            (offset + size + (PAGE_SIZE - 1)) / PAGE_SIZE,
-        ) {
+        match self.pages.use_range(first_page, end_page) {
             Ok(()) => {}
             Err(err) => {
                 pr_warn!("use_range failure {:?}", err);
@@ -913,11 +1725,15 @@ pub(crate) fn buffer_alloc(
         Ok(res)
     }
 
-    pub(crate) fn buffer_get(self: &Arc<Self>, ptr: usize) -> Option<Allocation> {
-        let mut inner = self.inner.lock();
-        let mapping = inner.mapping.as_mut()?;
-        let offset = ptr.checked_sub(mapping.address)?;
-        let (size, odata) = mapping.alloc.reserve_existing(offset).ok()?;
+    /// Looks up the allocated buffer at `ptr` so that it can be freed.
+    ///
+    /// Fails with `ENOENT` if there is no allocated buffer at `ptr`, which usually means that
+    /// userspace already freed it.
+    pub(crate) fn buffer_get(self: &Arc<Self>, ptr: usize) -> Result<Allocation> {
+        let mut inner = self.lock_inner();
+        let mapping = inner.mapping.as_mut().ok_or(EINVAL)?;
+        let offset = ptr.checked_sub(mapping.address).ok_or(EINVAL)?;
+        let (size, odata) = mapping.alloc.reserve_existing(offset)?;
         let mut alloc = Allocation::new(
             self.clone(),
             offset,
@@ -928,11 +1744,12 @@ pub(crate) fn buffer_get(self: &Arc<Self>, ptr: usize) -> Option<Allocation> {
         if let Some(data) = odata {
             alloc.set_info(data);
         }
-        Some(alloc)
+        Ok(alloc)
     }
 
     pub(crate) fn buffer_raw_free(&self, ptr: usize) {
-        let mut inner = self.inner.lock();
+        let mut inner = self.lock_inner();
+        let deferred_free_bytes = inner.deferred_free_bytes as usize;
         if let Some(ref mut mapping) = &mut inner.mapping {
             let offset = match ptr.checked_sub(mapping.address) {
                 Some(offset) => offset,
@@ -951,17 +1768,50 @@ pub(crate) fn buffer_raw_free(&self, ptr: usize) {
                 }
             };
 
-            // No more allocations in this range. Mark them as not in use.
+            // No more allocations in this range. Mark them as not in use, or defer that until
+            // enough pages have been freed.
             //
             // Must be done before we release the lock so that `use_range` is not used on these
-            // indices until `stop_using_range` returns.
-            self.pages
-                .stop_using_range(freed_range.start_page_idx, freed_range.end_page_idx);
+            // indices until `stop_using_range` returns. Deferred ranges are released by
+            // `buffer_alloc` before pages overlapping them are used again.
+            let mut releases = 0;
+            if deferred_free_bytes == 0 {
+                self.release_pages(freed_range, &mut releases);
+            } else {
+                if !mapping.defer_release(freed_range) {
+                    mapping.take_deferred(None, |range| self.release_pages(range, &mut releases));
+                    mapping.defer_release(freed_range);
+                }
+                if mapping.deferred_bytes >= deferred_free_bytes {
+                    mapping.take_deferred(None, |range| self.release_pages(range, &mut releases));
+                }
+            }
+
+            inner.count_page_releases(releases);
+            inner.update_buffer_pressure();
+            drop(inner);
+            self.buffer_wait.notify_all();
         }
     }
 
-    pub(crate) fn buffer_make_freeable(&self, offset: usize, data: Option<AllocationInfo>) {
-        let mut inner = self.inner.lock();
+    /// Marks the pages of `range` as not in use. Must be called with the lock held.
+    fn release_pages(&self, range: FreedRange, releases: &mut u64) {
+        self.pages
+            .stop_using_range(range.start_page_idx, range.end_page_idx);
+        *releases += 1;
+    }
+
+    /// Releases all deferred page ranges. Must be called with the lock held.
+    fn release_deferred_pages(&self, inner: &mut ProcessInner) {
+        let mut releases = 0;
+        if let Some(mapping) = &mut inner.mapping {
+            mapping.take_deferred(None, |range| self.release_pages(range, &mut releases));
+        }
+        inner.count_page_releases(releases);
+    }
+
+    pub(crate) fn buffer_make_freeable(&self, offset: usize, data: Option<AllocationInfo>) {
+        let mut inner = self.inner.lock();
         if let Some(ref mut mapping) = &mut inner.mapping {
             if mapping.alloc.reservation_commit(offset, data).is_err() {
                 pr_warn!("Offset {} failed to be marked freeable\n", offset);
@@ -970,43 +1820,746 @@ pub(crate) fn buffer_make_freeable(&self, offset: usize, data: Option<Allocation
     }
 
     fn create_mapping(&self, vma: &mut mm::virt::Area) -> Result {
-        use kernel::page::PAGE_SIZE;
-        let size = usize::min(vma.end() - vma.start(), bindings::SZ_4M as usize);
-        let mapping = Mapping::new(vma.start(), size)?;
-        let page_count = self.pages.register_with_vma(vma)?;
-        if page_count * PAGE_SIZE != size {
+        if self.ctx.mmap_requires_max_threads.load(Ordering::Relaxed)
+            && self.inner.lock().max_threads == 0
+        {
+            if MMAP_MAX_THREADS_RATELIMIT.allow() {
+                pr_warn!("{}: mmap before setting max threads\n", self.task.pid());
+            }
             return Err(EINVAL);
         }
 
+        // The mm rounds the length of the vma up to whole pages and never creates an empty vma,
+        // so `register_with_vma` always registers exactly `size / PAGE_SIZE` pages.
+        let size = usize::min(vma.end() - vma.start(), bindings::SZ_4M as usize);
+        if let Err(err) = self.ctx.reserve_mapped_bytes(size) {
+            if MMAP_MEMORY_LIMIT_RATELIMIT.allow() {
+                pr_warn!(
+                    "{}: mmap of {} bytes exceeds the memory limit of context {}\n",
+                    self.task.pid(),
+                    size,
+                    &*self.ctx.name
+                );
+            }
+            return Err(err);
+        }
+        // Give the bytes back to the context if the mapping can't be set up, for example because
+        // the process already has one.
+        let reservation = ScopeGuard::new(|| self.ctx.release_mapped_bytes(size));
+        let mapping = Mapping::new(vma.start(), size)?;
+        self.pages.register_with_vma(vma)?;
+        reservation.dismiss();
+
         // Save range allocator for later.
         self.inner.lock().mapping = Some(mapping);
 
         Ok(())
     }
 
-    fn version(&self, data: UserSlice) -> Result {
-        data.writer().write(&BinderVersion::current())
+    fn version(&self, data: UserSlice) -> Result {
+        self.inner.lock().queried_version = Some(bindings::BINDER_CURRENT_PROTOCOL_VERSION as _);
+        data.writer().write(&BinderVersion::current())
+    }
+
+    pub(crate) fn register_thread(&self) -> bool {
+        self.inner.lock().register_thread()
+    }
+
+    fn remove_thread(&self, thread: Arc<Thread>) {
+        if log_enabled(BINDER_LOG_INFO) {
+            pr_info!("{}:{} thread exit\n", self.task.pid(), thread.id);
+        }
+        self.inner.lock().threads.remove(&thread.id);
+        thread.release();
+    }
+
+    fn set_max_threads(&self, max: u32) {
+        self.inner.lock().max_threads = max;
+    }
+
+    fn set_spare_threads(&self, spare: u32) {
+        self.inner.lock().spare_threads = spare;
+    }
+
+    fn set_oneway_spam_detection_enabled(&self, enabled: u32) {
+        self.inner.lock().oneway_spam_detection_enabled = enabled != 0;
+    }
+
+    fn get_mapping_info(&self, data: UserSlice) -> Result {
+        let mut info = BinderMappingInfo::default();
+        if let Some(mapping) = &self.inner.lock().mapping {
+            info.mapped = 1;
+            info.address = mapping.address as u64;
+            info.size = mapping.alloc.size() as u64;
+        }
+        data.writer().write(&info)
+    }
+
+    fn get_config(&self, data: UserSlice) -> Result {
+        let inner = self.inner.lock();
+        let config = BinderProcessConfig {
+            max_threads: inner.max_threads,
+            oneway_spam_detection: inner.oneway_spam_detection_enabled as u32,
+        };
+        drop(inner);
+        data.writer().write(&config)
+    }
+
+    fn set_config(&self, config: BinderProcessConfig) -> Result {
+        if config.oneway_spam_detection > 1 {
+            return Err(EINVAL);
+        }
+        let mut inner = self.inner.lock();
+        inner.max_threads = config.max_threads;
+        inner.oneway_spam_detection_enabled = config.oneway_spam_detection != 0;
+        Ok(())
+    }
+
+    pub(crate) fn is_oneway_spam_detection_enabled(&self) -> bool {
+        self.inner.lock().oneway_spam_detection_enabled
+    }
+
+    fn get_node_debug_ids(&self, data: UserSlice) -> Result {
+        let (mut reader, mut writer) = data.reader_writer();
+        let mut array = reader.read::<BinderArray>()?;
+
+        let mut entries = Vec::try_with_capacity(array.max_entries())?;
+        let inner = self.inner.lock();
+        let nodes = inner
+            .nodes
+            .iter()
+            .skip_while(|(ptr, _)| **ptr < array.start)
+            .take(entries.capacity());
+        for (ptr, node) in nodes {
+            let _ = entries.try_push(BinderNodeDebugId {
+                ptr: *ptr,
+                debug_id: node.debug_id as u64,
+            });
+        }
+        drop(inner);
+
+        array.write_entries(&entries)?;
+        writer.write(&array)?;
+        Ok(())
+    }
+
+    fn get_refs(&self, data: UserSlice) -> Result {
+        let (mut reader, mut writer) = data.reader_writer();
+        let mut array = reader.read::<BinderArray>()?;
+
+        let mut entries = Vec::try_with_capacity(array.max_entries())?;
+        let mut refs = self.node_refs.lock();
+        let infos = refs
+            .by_handle
+            .iter_mut()
+            .skip_while(|(handle, _)| u64::from(**handle) < array.start)
+            .take(entries.capacity());
+        for (handle, info) in infos {
+            let node_ref = info.node_ref();
+            let (strong_count, weak_count) = node_ref.get_count();
+            let _ = entries.try_push(BinderRefEntry {
+                handle: *handle,
+                reserved: 0,
+                debug_id: node_ref.node.debug_id as u64,
+                strong_count: strong_count as u32,
+                weak_count: weak_count as u32,
+            });
+        }
+        drop(refs);
+
+        array.write_entries(&entries)?;
+        writer.write(&array)?;
+        Ok(())
+    }
+
+    fn get_death_notifications(&self, data: UserSlice) -> Result {
+        let (mut reader, mut writer) = data.reader_writer();
+        let mut array = reader.read::<BinderArray>()?;
+
+        let mut entries = Vec::try_with_capacity(array.max_entries())?;
+        let mut refs = self.node_refs.lock();
+        for (handle, info) in refs.by_handle.iter_mut() {
+            if u64::from(*handle) < array.start {
+                continue;
+            }
+            if entries.len() == entries.capacity() {
+                break;
+            }
+            if let Some(death) = info.death() {
+                let _ = entries.try_push(BinderDeathNotificationInfo {
+                    handle: *handle,
+                    reserved: 0,
+                    cookie: death.cookie as u64,
+                });
+            }
+        }
+        drop(refs);
+
+        array.write_entries(&entries)?;
+        writer.write(&array)?;
+        Ok(())
+    }
+
+    /// Counts a transaction or reply that was sent successfully in the activity statistics.
+    pub(crate) fn record_transaction_sent(&self, tr: &bindings::binder_transaction_data) {
+        let mut inner = self.inner.lock();
+        if inner.stats_enabled {
+            inner.stats.activity.transactions += 1;
+            inner.stats.activity.bytes += tr.data_size + tr.offsets_size;
+        }
+    }
+
+    /// Records the outcome of a reply to a transaction serviced by this process.
+    pub(crate) fn record_reply(&self, succeeded: bool) {
+        let mut inner = self.inner.lock();
+        if inner.stats_enabled {
+            if succeeded {
+                inner.stats.replies_succeeded += 1;
+            } else {
+                inner.stats.replies_failed += 1;
+            }
+        }
+    }
+
+    /// Returns the activity statistics and resets them, so that each call reports the activity
+    /// since the previous one.
+    fn get_activity(&self, data: UserSlice) -> Result {
+        let mut inner = self.inner.lock();
+        if !inner.stats_enabled {
+            return Err(EINVAL);
+        }
+        let activity = take(&mut inner.stats.activity);
+        drop(inner);
+        data.writer().write(&activity)
+    }
+
+    /// Records an error encountered when sending a transaction or reply.
+    pub(crate) fn record_transaction_error(&self, command: u32, param: i32, handle: u32, tid: i32) {
+        let mut inner = self.inner.lock();
+        if inner.stats_enabled {
+            inner.stats.activity.errors += 1;
+        }
+        inner.last_error = BinderLastError {
+            seq: inner.last_error.seq + 1,
+            time_ns: Ktime::ktime_get().to_ns() as u64,
+            command,
+            param,
+            handle,
+            tid,
+        };
+    }
+
+    fn get_last_error(&self, data: UserSlice) -> Result {
+        let last_error = self.inner.lock().last_error;
+        data.writer().write(&last_error)?;
+        Ok(())
+    }
+
+    /// Takes a snapshot of the state of this process in the fixed `BinderProcessState` layout.
+    ///
+    /// The process lock and the `node_refs` lock are taken one after the other, so the snapshot is
+    /// not atomic with respect to the reference count.
+    pub(crate) fn export_state(&self) -> BinderProcessState {
+        let mut state = BinderProcessState {
+            version: BINDER_PROCESS_STATE_VERSION,
+            pid: self.task.pid() as u32,
+            ..BinderProcessState::default()
+        };
+
+        let inner = self.inner.lock();
+        for thread in inner.threads.values() {
+            state.threads += 1;
+            if let Some(count) = state.thread_states.get_mut(thread.state() as usize) {
+                *count += 1;
+            }
+        }
+        state.requested_threads = inner.requested_thread_count;
+        state.started_threads = inner.started_thread_count;
+        state.max_threads = inner.max_threads;
+        state.nodes = inner.nodes.iter().count() as u32;
+        state.outstanding_txns = inner.outstanding_txns;
+        state.pending_work = (inner.urgent_work.iter().count() + inner.work.iter().count()) as u32;
+        if let Some(mapping) = &inner.mapping {
+            state.buffer_allocated = mapping.alloc.allocated_size() as u64;
+            state.buffer_size = mapping.alloc.size() as u64;
+        }
+        drop(inner);
+
+        state.refs = self.ref_count_totals().0 as u32;
+        state
+    }
+
+    fn get_buffer_pages(&self, data: UserSlice) -> Result {
+        if !log_enabled(BINDER_LOG_DEBUG) {
+            return Err(EPERM);
+        }
+        let (mut reader, mut writer) = data.reader_writer();
+        let mut pages = reader.read::<BinderBufferPages>()?;
+
+        let inner = self.inner.lock();
+        let mapping = inner.mapping.as_ref().ok_or(EINVAL)?;
+        let offset = (pages.buffer as usize)
+            .checked_sub(mapping.address)
+            .ok_or(EINVAL)?;
+        let layout = mapping.alloc.page_layout(offset)?;
+        drop(inner);
+
+        pages.first_page = layout.first_page as u32;
+        pages.last_page = layout.last_page as u32;
+        pages.flags = 0;
+        if layout.shares_first_page {
+            pages.flags |= BINDER_BUFFER_SHARES_FIRST_PAGE;
+        }
+        if layout.shares_last_page {
+            pages.flags |= BINDER_BUFFER_SHARES_LAST_PAGE;
+        }
+        pages.reserved = 0;
+
+        writer.write(&pages)
+    }
+
+    fn get_stale_nodes(&self, data: UserSlice) -> Result {
+        let (mut reader, mut writer) = data.reader_writer();
+        let mut req = reader.read::<BinderStaleNodes>()?;
+        let min_age_ms = i64::try_from(req.min_age_ms).unwrap_or(i64::MAX);
+
+        let mut entries = Vec::try_with_capacity(req.array.max_entries())?;
+        let inner = self.inner.lock();
+        if !inner.stats_enabled {
+            return Err(EINVAL);
+        }
+        let nodes = inner
+            .nodes
+            .iter()
+            .skip_while(|(ptr, _)| **ptr < req.array.start)
+            .filter(|(_, node)| node.transaction_count(&inner) == 0 && node.age_ms() > min_age_ms)
+            .take(entries.capacity());
+        for (ptr, node) in nodes {
+            let _ = entries.try_push(BinderStaleNode {
+                ptr: *ptr,
+                age_ms: node.age_ms() as u64,
+            });
+        }
+        drop(inner);
+
+        req.array.write_entries(&entries)?;
+        writer.write(&req)?;
+        Ok(())
+    }
+
+    fn get_context_txn_stats(&self, data: UserSlice) -> Result {
+        self.check_context_admin()?;
+        let stats = BinderContextTxnStats {
+            transactions: self.ctx.txn_count.load(Ordering::Relaxed),
+            bytes: self.ctx.txn_bytes.load(Ordering::Relaxed),
+        };
+        data.writer().write(&stats)
+    }
+
+    fn get_handle_stats(&self, data: UserSlice) -> Result {
+        let mut stats = BinderHandleStats::default();
+        {
+            let refs = self.node_refs.lock();
+            let mut keys = refs.by_handle.keys();
+            if let Some(first) = keys.next() {
+                stats.count = 1;
+                stats.min = *first;
+                stats.max = *first;
+                for handle in keys {
+                    stats.count += 1;
+                    stats.max = *handle;
+                }
+                // The keys are sorted and distinct, so every value in the range that isn't a
+                // handle is a gap. The range can hold 2^32 values, so count it in a u64.
+                let span = u64::from(stats.max - stats.min) + 1;
+                let gaps = span - u64::from(stats.count);
+                stats.gaps = u32::try_from(gaps).unwrap_or(u32::MAX);
+            }
+        }
+        data.writer().write(&stats)
+    }
+
+    fn get_manager_info(&self, data: UserSlice) -> Result {
+        self.check_context_admin()?;
+        let node_ref = self.ctx.get_manager_node(false).map_err(|_| ENOENT)?;
+        let (ptr, cookie) = node_ref.node.get_id();
+        let info = BinderManagerInfo {
+            ptr,
+            cookie,
+            flags: node_ref.node.flags,
+            reserved: 0,
+        };
+        drop(node_ref);
+        data.writer().write(&info)
+    }
+
+    fn get_context_name(&self, data: UserSlice) -> Result {
+        let mut out = BinderContextName::default();
+        copy_context_name(&mut out.name, &self.ctx);
+        data.writer().write(&out)
+    }
+
+    fn audit_refs(&self, data: UserSlice) -> Result {
+        let (mut reader, mut writer) = data.reader_writer();
+        let mut audit = reader.read::<BinderRefAudit>()?;
+        if audit.repair != 0 {
+            check_sys_admin()?;
+        }
+
+        let mut refs = self.node_refs.lock();
+        let mut stale = Vec::new();
+        for (gid, handle) in refs.by_node.iter() {
+            let matches = refs
+                .by_handle
+                .get(handle)
+                .map_or(false, |info| info.node_ref2().node.global_id() == *gid);
+            if !matches {
+                stale.try_push(*gid)?;
+            }
+        }
+        let mut missing = Vec::new();
+        for (handle, info) in refs.by_handle.iter() {
+            let gid = info.node_ref2().node.global_id();
+            if refs.by_node.get(&gid) != Some(handle) {
+                missing.try_push((gid, *handle))?;
+            }
+        }
+
+        audit.stale_by_node = stale.len() as u32;
+        audit.missing_by_node = missing.len() as u32;
+        audit.repaired = 0;
+        if audit.repair != 0 {
+            for gid in &stale {
+                if refs.by_node.remove(gid).is_some() {
+                    audit.repaired += 1;
+                }
+            }
+            // A node with several handles can only be indexed by one of them, so only add the
+            // entries that don't replace an existing one.
+            for (gid, handle) in &missing {
+                if refs.by_node.get(gid).is_none() {
+                    refs.by_node.try_create_and_insert(*gid, *handle)?;
+                    audit.repaired += 1;
+                }
+            }
+        }
+        drop(refs);
+
+        if audit.stale_by_node != 0 || audit.missing_by_node != 0 {
+            pr_warn!(
+                "{}: inconsistent handle tables, {} stale and {} missing entries, {} repaired\n",
+                self.task.pid(),
+                audit.stale_by_node,
+                audit.missing_by_node,
+                audit.repaired
+            );
+        }
+
+        writer.write(&audit)
+    }
+
+    fn get_oldest_work_age(&self, data: UserSlice) -> Result {
+        let age = self.inner.lock().oldest_work_age_ms();
+        data.writer().write(&(age.max(0) as u64))
+    }
+
+    fn get_ready_threads(&self, data: UserSlice) -> Result {
+        let (mut reader, mut writer) = data.reader_writer();
+        let mut array = reader.read::<BinderArray>()?;
+
+        // `ready_threads` is not sorted by tid, so keep the smallest tids seen so far in order.
+        // Nothing is pushed beyond the capacity, so this never allocates under the lock.
+        let mut entries = Vec::<BinderReadyThread>::try_with_capacity(array.max_entries())?;
+        let inner = self.inner.lock();
+        for thread in inner.ready_threads.iter() {
+            let tid = thread.id as u32;
+            if (tid as u64) < array.start {
+                continue;
+            }
+            if entries.len() == entries.capacity() {
+                match entries.last() {
+                    Some(last) if tid < last.tid => entries.pop(),
+                    _ => continue,
+                };
+            }
+            let pos = entries.partition_point(|entry| entry.tid < tid);
+            if entries.try_push(BinderReadyThread { tid }).is_ok() {
+                entries[pos..].rotate_right(1);
+            }
+        }
+        drop(inner);
+
+        array.write_entries(&entries)?;
+        writer.write(&array)?;
+        Ok(())
+    }
+
+    fn get_thread_serviced(&self, data: UserSlice) -> Result {
+        let (mut reader, mut writer) = data.reader_writer();
+        let mut array = reader.read::<BinderArray>()?;
+
+        let mut entries = Vec::try_with_capacity(array.max_entries())?;
+        let inner = self.inner.lock();
+        let threads = inner
+            .threads
+            .iter()
+            .skip_while(|(tid, _)| (**tid as u64) < array.start)
+            .take(entries.capacity());
+        for (tid, thread) in threads {
+            let _ = entries.try_push(BinderThreadServiced {
+                tid: *tid as u32,
+                reserved: 0,
+                count: thread.serviced_count(),
+            });
+        }
+        drop(inner);
+
+        array.write_entries(&entries)?;
+        writer.write(&array)?;
+        Ok(())
+    }
+
+    fn get_thread_states(&self, data: UserSlice) -> Result {
+        let (mut reader, mut writer) = data.reader_writer();
+        let mut array = reader.read::<BinderArray>()?;
+
+        let mut entries = Vec::try_with_capacity(array.max_entries())?;
+        // Hold the process lock while taking the snapshot, so that the states are consistent with
+        // each other.
+        let inner = self.inner.lock();
+        let threads = inner
+            .threads
+            .iter()
+            .skip_while(|(tid, _)| (**tid as u64) < array.start)
+            .take(entries.capacity());
+        for (tid, thread) in threads {
+            let _ = entries.try_push(BinderThreadState {
+                tid: *tid as u32,
+                state: thread.state(),
+            });
+        }
+        drop(inner);
+
+        array.write_entries(&entries)?;
+        writer.write(&array)?;
+        Ok(())
+    }
+
+    fn set_node_uid_filter(&self, info: BinderNodeUidFilter) -> Result {
+        let filter = match info.mode {
+            BINDER_UID_FILTER_NONE => None,
+            BINDER_UID_FILTER_ALLOW | BINDER_UID_FILTER_DENY => {
+                let count = info.count as usize;
+                if count > BINDER_UID_FILTER_MAX_UIDS {
+                    return Err(EINVAL);
+                }
+                let mut reader = UserSlice::new(info.uids as _, count * size_of::<u32>()).reader();
+                let mut uids = Vec::try_with_capacity(count)?;
+                for _ in 0..count {
+                    // Uids are taken as global uids, so that a sender in a user namespace can't
+                    // map its way past the filter.
+                    let uid = reader.read::<u32>()?;
+                    uids.try_push(Kuid::from_raw(bindings::kuid_t { val: uid }))?;
+                }
+                Some(UidFilter::new(info.mode == BINDER_UID_FILTER_ALLOW, uids))
+            }
+            _ => return Err(EINVAL),
+        };
+
+        let mut inner = self.inner.lock();
+        let node = inner
+            .get_existing_node(info.ptr, info.cookie)?
+            .ok_or(ENOENT)?;
+        node.set_uid_filter(filter, &mut inner);
+        drop(inner);
+        drop(node);
+        Ok(())
+    }
+
+    fn set_handle_base(&self, base: u32) -> Result {
+        // Handle zero is reserved for the context manager.
+        if base == 0 {
+            return Err(EINVAL);
+        }
+        let mut refs = self.node_refs.lock();
+        if refs.handle_count != 0 {
+            return Err(EBUSY);
+        }
+        refs.handle_base = base;
+        Ok(())
+    }
+
+    fn set_strict_handles(&self, enabled: u32) {
+        self.inner.lock().strict_handles = enabled != 0;
+    }
+
+    fn set_strict_mmap(&self, enabled: u32) {
+        self.inner.lock().strict_mmap = enabled != 0;
+    }
+
+    fn set_dead_reply_errors(&self, enabled: u32) {
+        self.inner.lock().dead_reply_errors = enabled != 0;
+    }
+
+    pub(crate) fn dead_reply_errors(&self) -> bool {
+        self.inner.lock().dead_reply_errors
+    }
+
+    fn set_deferred_free_bytes(&self, bytes: u32) {
+        let mut inner = self.inner.lock();
+        inner.deferred_free_bytes = bytes;
+        self.release_deferred_pages(&mut inner);
+    }
+
+    fn set_node_creation_rate(&self, rate: u32) {
+        self.inner.lock().node_creation_limit = NodeCreationLimit {
+            rate,
+            ..NodeCreationLimit::default()
+        };
+    }
+
+    fn set_flush_drain_timeout(&self, timeout_ms: u32) {
+        self.inner.lock().flush_drain_ms = timeout_ms;
+    }
+
+    fn set_buffer_checksums_enabled(&self, enabled: u32) {
+        self.buffer_checksums.store(enabled != 0, Ordering::Relaxed);
+    }
+
+    fn set_buffer_wait_timeout(&self, timeout_ms: u32) {
+        self.buffer_wait_ms.store(timeout_ms, Ordering::Relaxed);
+    }
+
+    fn set_ioctl_log_enabled(&self, enabled: u32) -> Result {
+        check_sys_admin()?;
+        self.ioctl_log.store(enabled != 0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn set_stats_enabled(&self, enabled: u32) {
+        let mut inner = self.inner.lock();
+        if enabled != 0 && !inner.stats_enabled {
+            inner.stats = ProcessStats::default();
+            self.inner_locks.store(0, Ordering::Relaxed);
+            self.inner_contended.store(0, Ordering::Relaxed);
+        }
+        inner.stats_enabled = enabled != 0;
+        self.lock_stats.store(enabled != 0, Ordering::Relaxed);
+    }
+
+    fn get_size_histogram(&self, data: UserSlice) -> Result {
+        let (mut reader, mut writer) = data.reader_writer();
+        let mut histogram = reader.read::<BinderSizeHistogram>()?;
+
+        let mut inner = self.inner.lock();
+        if !inner.stats_enabled {
+            return Err(EINVAL);
+        }
+        histogram.buckets = inner.stats.size_histogram;
+        if histogram.reset != 0 {
+            inner.stats.size_histogram = [0; BINDER_SIZE_HISTOGRAM_BUCKETS];
+        }
+        drop(inner);
+
+        writer.write(&histogram)
+    }
+
+    /// Checks that the caller may change the settings of this context. This requires being the
+    /// context manager or having `CAP_SYS_ADMIN`.
+    fn check_context_admin(&self) -> Result {
+        if self.inner.lock().is_manager {
+            return Ok(());
+        }
+        check_sys_admin()
+    }
+
+    fn set_large_transaction_warn_size(&self, size: u32) -> Result {
+        self.check_context_admin()?;
+        self.ctx
+            .large_txn_warn_size
+            .store(size as usize, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn set_handle_warn_count(&self, count: u32) -> Result {
+        self.check_context_admin()?;
+        self.ctx.handle_warn_count.store(count, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn set_default_max_threads(&self, max: u32) -> Result {
+        self.check_context_admin()?;
+        self.ctx.default_max_threads.store(max, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn set_allowed_node_flags(&self, flags: u32) -> Result {
+        self.check_context_admin()?;
+        self.ctx.allowed_node_flags.store(flags, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn set_mmap_requires_max_threads(&self, enabled: u32) -> Result {
+        self.check_context_admin()?;
+        self.ctx
+            .mmap_requires_max_threads
+            .store(enabled != 0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn set_cap_async_priority(&self, enabled: u32) -> Result {
+        self.check_context_admin()?;
+        self.ctx
+            .cap_async_priority
+            .store(enabled != 0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn set_max_chain_len(&self, max: u32) -> Result {
+        self.check_context_admin()?;
+        self.ctx.max_chain_len.store(max, Ordering::Relaxed);
+        Ok(())
     }
 
-    pub(crate) fn register_thread(&self) -> bool {
-        self.inner.lock().register_thread()
+    fn set_underflow_policy(&self, policy: u32) -> Result {
+        check_sys_admin()?;
+        match policy {
+            BINDER_UNDERFLOW_WARN | BINDER_UNDERFLOW_IGNORE => {}
+            // Only debug kernels may stop or slow down on an underflow.
+            BINDER_UNDERFLOW_BACKTRACE | BINDER_UNDERFLOW_BUG if cfg!(CONFIG_DEBUG_KERNEL) => {}
+            _ => return Err(EINVAL),
+        }
+        self.ctx.underflow_policy.store(policy, Ordering::Relaxed);
+        Ok(())
     }
 
-    fn remove_thread(&self, thread: Arc<Thread>) {
-        self.inner.lock().threads.remove(&thread.id);
-        thread.release();
+    fn set_max_buffer_depth(&self, max: u32) -> Result {
+        self.check_context_admin()?;
+        self.ctx.max_buffer_depth.store(max, Ordering::Relaxed);
+        Ok(())
     }
 
-    fn set_max_threads(&self, max: u32) {
-        self.inner.lock().max_threads = max;
+    fn set_max_procs(&self, max: u32) -> Result {
+        self.check_context_admin()?;
+        self.ctx.max_procs.store(max, Ordering::Relaxed);
+        Ok(())
     }
 
-    fn set_oneway_spam_detection_enabled(&self, enabled: u32) {
-        self.inner.lock().oneway_spam_detection_enabled = enabled != 0;
+    fn set_context_memory_limit(&self, bytes: u64) -> Result {
+        self.check_context_admin()?;
+        let bytes = usize::try_from(bytes).map_err(|_| EINVAL)?;
+        self.ctx.max_mapped_bytes.store(bytes, Ordering::Relaxed);
+        Ok(())
     }
 
-    pub(crate) fn is_oneway_spam_detection_enabled(&self) -> bool {
-        self.inner.lock().oneway_spam_detection_enabled
+    fn set_buffer_pressure_threshold(&self, percent: u32) -> Result {
+        if percent > 100 {
+            return Err(EINVAL);
+        }
+        let mut inner = self.inner.lock();
+        inner.buffer_pressure_threshold = percent;
+        inner.update_buffer_pressure();
+        Ok(())
     }
 
     fn get_node_debug_info(&self, data: UserSlice) -> Result {
@@ -1029,6 +2582,97 @@ fn get_node_debug_info(&self, data: UserSlice) -> Result {
         writer.write(&out)
     }
 
+    /// Looks up the node of `handle` for the node info queries of the context manager.
+    fn get_node_for_info(&self, handle: u32) -> Result<NodeRef> {
+        // The manager has no handle to its own node, so handle 0 refers to it directly. A weak
+        // reference is enough to keep the node alive while reading its counts.
+        if handle == 0 {
+            self.ctx.get_manager_node(false).or(Err(ENOENT))
+        } else {
+            self.get_node_from_handle(handle, true)
+        }
+    }
+
+    fn get_node_info_for_refs(&self, data: UserSlice) -> Result {
+        let header = data.reader().read::<BinderRefNodeInfoArray>()?;
+        if header.reserved != 0 || header.count > BINDER_ARRAY_MAX_ENTRIES {
+            return Err(EINVAL);
+        }
+
+        // Only the context manager is allowed to use this ioctl.
+        if !self.inner.lock().is_manager {
+            return Err(EPERM);
+        }
+
+        let count = header.count as usize;
+        let len = count * size_of::<BinderRefNodeInfo>();
+        let (mut reader, mut writer) = UserSlice::new(header.buffer as _, len).reader_writer();
+        let mut entries = Vec::try_with_capacity(count)?;
+        for _ in 0..count {
+            let entry = reader.read::<BinderRefNodeInfo>()?;
+            if entry.strong_count != 0 || entry.weak_count != 0 || entry.flags != 0 {
+                return Err(EINVAL);
+            }
+            entries.try_push(entry)?;
+        }
+
+        for entry in &mut entries {
+            match self.get_node_for_info(entry.handle) {
+                Ok(node_ref) => {
+                    let owner_inner = node_ref.node.owner.inner.lock();
+                    (entry.strong_count, entry.weak_count) = node_ref.node.counts(&owner_inner);
+                    drop(owner_inner);
+                    drop(node_ref);
+                }
+                Err(_) => entry.flags = BINDER_REF_NODE_INFO_NOT_FOUND,
+            }
+        }
+
+        for entry in &entries {
+            writer.write(entry)?;
+        }
+        Ok(())
+    }
+
+    fn get_node_fanout(&self, data: UserSlice) -> Result {
+        let (mut reader, mut writer) = data.reader_writer();
+        let mut fanout = reader.read::<BinderNodeFanout>()?;
+
+        let node_ref = self.get_node_from_handle(fanout.handle, false)?;
+        let owner_inner = node_ref.node.owner.inner.lock();
+        fanout.processes = node_ref.node.ref_process_count(&owner_inner) as u32;
+        (fanout.strong_count, fanout.weak_count) = node_ref.node.counts(&owner_inner);
+        drop(owner_inner);
+        drop(node_ref);
+
+        writer.write(&fanout)
+    }
+
+    fn compare_handles(&self, data: UserSlice) -> Result {
+        let (mut reader, mut writer) = data.reader_writer();
+        let mut cmp = reader.read::<BinderCompareHandles>()?;
+
+        let ref1 = self.get_node_from_handle(cmp.handle1, false)?;
+        let ref2 = self.get_node_from_handle(cmp.handle2, false)?;
+        cmp.same = (ref1.node.global_id() == ref2.node.global_id()) as u32;
+        cmp.reserved = 0;
+        drop(ref1);
+        drop(ref2);
+
+        writer.write(&cmp)
+    }
+
+    fn get_handle_status(&self, data: UserSlice) -> Result {
+        let (mut reader, mut writer) = data.reader_writer();
+        let mut status = reader.read::<BinderHandleStatus>()?;
+
+        let node_ref = self.get_node_from_handle(status.handle, false)?;
+        status.dead = node_ref.node.owner.inner.lock().is_dead as u32;
+        drop(node_ref);
+
+        writer.write(&status)
+    }
+
     fn get_node_info_from_ref(&self, data: UserSlice) -> Result {
         let (mut reader, mut writer) = data.reader_writer();
         let mut out = reader.read::<BinderNodeInfoForRef>()?;
@@ -1047,9 +2691,7 @@ fn get_node_info_from_ref(&self, data: UserSlice) -> Result {
             return Err(EPERM);
         }
 
-        let node_ref = self
-            .get_node_from_handle(out.handle, true)
-            .or(Err(EINVAL))?;
+        let node_ref = self.get_node_for_info(out.handle).or(Err(EINVAL))?;
         // Get the counts from the node.
         {
             let owner_inner = node_ref.node.owner.inner.lock();
@@ -1062,8 +2704,9 @@ fn get_node_info_from_ref(&self, data: UserSlice) -> Result {
 
     pub(crate) fn needs_thread(&self) -> bool {
         let mut inner = self.inner.lock();
+        let spare = inner.spare_threads as usize;
         let ret = inner.requested_thread_count == 0
-            && inner.ready_threads.is_empty()
+            && inner.ready_threads.iter().take(spare + 1).count() <= spare
             && inner.started_thread_count < inner.max_threads;
         if ret {
             inner.requested_thread_count += 1
@@ -1148,11 +2791,45 @@ pub(crate) fn clear_death(&self, reader: &mut UserSliceReader, thread: &Thread)
     }
 
     pub(crate) fn dead_binder_done(&self, cookie: usize, thread: &Thread) {
-        if let Some(death) = self.inner.lock().pull_delivered_death(cookie) {
+        let death = self.inner.lock().pull_delivered_death(cookie);
+        if let Some(death) = death {
+            let latency = death.ms_since_dead();
+            let mut inner = self.inner.lock();
+            if inner.stats_enabled {
+                inner.stats.deaths_acknowledged += 1;
+                if let Some(latency) = latency {
+                    inner.stats.record_death_latency(latency);
+                }
+            }
+            drop(inner);
             death.set_notification_done(thread);
         }
     }
 
+    /// Waits for the transactions in flight to complete before a flush makes the loopers exit,
+    /// for at most `flush_drain_ms`.
+    ///
+    /// This runs in the context of the caller of `flush`, so that it doesn't hold up the deferred
+    /// work of the process, which also handles its release.
+    fn drain_for_flush(&self) {
+        let mut inner = self.inner.lock();
+        if inner.flush_drain_ms == 0 {
+            return;
+        }
+        inner.flush_draining += 1;
+        let mut jiffies = kernel::time::msecs_to_jiffies(inner.flush_drain_ms);
+        while jiffies > 0 && inner.outstanding_txns > 0 && !inner.is_dead {
+            match self
+                .freeze_wait
+                .wait_interruptible_timeout(&mut inner, jiffies)
+            {
+                CondVarTimeoutResult::Woken { jiffies: remaining } => jiffies = remaining,
+                CondVarTimeoutResult::Signal { .. } | CondVarTimeoutResult::Timeout => break,
+            }
+        }
+        inner.flush_draining -= 1;
+    }
+
     fn deferred_flush(&self) {
         let inner = self.inner.lock();
         for thread in inner.threads.values() {
@@ -1161,6 +2838,10 @@ fn deferred_flush(&self) {
     }
 
     fn deferred_release(self: Arc<Self>) {
+        if log_enabled(BINDER_LOG_INFO) {
+            pr_info!("{}: release\n", self.task.pid());
+        }
+
         let is_manager = {
             let mut inner = self.inner.lock();
             inner.is_dead = true;
@@ -1169,6 +2850,8 @@ fn deferred_release(self: Arc<Self>) {
             inner.async_recv = false;
             inner.is_manager
         };
+        // Fail the transactions waiting for buffer space.
+        self.buffer_wait.notify_all();
 
         if is_manager {
             self.ctx.unset_manager_node();
@@ -1194,6 +2877,7 @@ fn deferred_release(self: Arc<Self>) {
         // Free any resources kept alive by allocated buffers.
         let omapping = self.inner.lock().mapping.take();
         if let Some(mut mapping) = omapping {
+            self.ctx.release_mapped_bytes(mapping.alloc.size());
             let address = mapping.address;
             let oneway_spam_detected = mapping.alloc.oneway_spam_detected;
             mapping.alloc.take_for_each(|offset, size, odata| {
@@ -1209,8 +2893,13 @@ fn deferred_release(self: Arc<Self>) {
 
         // Drop all references. We do this dance with `swap` to avoid destroying the references
         // while holding the lock.
+        //
+        // New references can only be inserted by checking `is_dead` under the `node_refs` lock,
+        // so the refs taken here are the last ones.
+        debug_assert!(self.inner.lock().is_dead);
         let mut refs = self.node_refs.lock();
         let mut node_refs = take(&mut refs.by_handle);
+        refs.handle_count = 0;
         drop(refs);
         for info in node_refs.values_mut() {
             // SAFETY: We are removing the `NodeRefInfo` from the right node.
@@ -1225,6 +2914,7 @@ fn deferred_release(self: Arc<Self>) {
             death.set_cleared(false);
         }
         drop(node_refs);
+        debug_assert!(self.node_refs.lock().by_handle.iter().next().is_none());
 
         // Do similar dance for the state lock.
         let mut inner = self.inner.lock();
@@ -1251,17 +2941,21 @@ fn deferred_release(self: Arc<Self>) {
                 death.set_dead();
             }
         }
+
+        // Since `is_dead` is set, nothing may have been pushed after the work list was drained.
+        debug_assert!(!self.inner.lock().has_queued_work());
     }
 
     pub(crate) fn drop_outstanding_txn(&self) {
         let wake = {
             let mut inner = self.inner.lock();
             if inner.outstanding_txns == 0 {
-                pr_err!("outstanding_txns underflow");
+                drop(inner);
+                self.report_outstanding_txn_underflow();
                 return;
             }
             inner.outstanding_txns -= 1;
-            inner.is_frozen && inner.outstanding_txns == 0
+            (inner.is_frozen || inner.flush_draining > 0) && inner.outstanding_txns == 0
         };
 
         if wake {
@@ -1269,12 +2963,114 @@ pub(crate) fn drop_outstanding_txn(&self) {
         }
     }
 
+    /// Reports an underflow of `outstanding_txns` according to the underflow policy of the context.
+    ///
+    /// An underflow means that a transaction was accounted for twice. The count stays at zero, but
+    /// the caller responsible can be reported. Must be called without holding the inner lock.
+    fn report_outstanding_txn_underflow(&self) {
+        match self.ctx.underflow_policy.load(Ordering::Relaxed) {
+            BINDER_UNDERFLOW_IGNORE => {}
+            BINDER_UNDERFLOW_BACKTRACE => {
+                pr_err!("{}: outstanding_txns underflow\n", self.task.pid());
+                // SAFETY: `dump_stack` may be called from any context.
+                unsafe { bindings::dump_stack() };
+            }
+            BINDER_UNDERFLOW_BUG => panic!("{}: outstanding_txns underflow", self.task.pid()),
+            _ => pr_err!("{}: outstanding_txns underflow\n", self.task.pid()),
+        }
+    }
+
+    /// Counts a `TF_DROP_IF_FULL` transaction that was dropped because this process had no buffer
+    /// space for it.
+    pub(crate) fn count_dropped_oneway(&self) {
+        let mut inner = self.inner.lock();
+        if inner.stats_enabled {
+            inner.stats.oneway_dropped += 1;
+        }
+    }
+
+    pub(crate) fn drop_sync_txn(&self) {
+        let mut inner = self.inner.lock();
+        if inner.sync_txns == 0 {
+            pr_err!("sync_txns underflow");
+            return;
+        }
+        inner.sync_txns -= 1;
+    }
+
+    fn reset_node_stats(&self, ptr: u64) -> Result {
+        let mut inner = self.inner.lock();
+        let node = inner.nodes.get(&ptr).ok_or(EINVAL)?.clone();
+        node.reset_stats(&mut inner);
+        drop(inner);
+        drop(node);
+        Ok(())
+    }
+
+    fn set_delivery_paused(&self, paused: u32) {
+        let mut inner = self.inner.lock();
+        if paused != 0 {
+            inner.delivery_paused = true;
+        } else if inner.delivery_paused {
+            inner.resume_delivery();
+        }
+    }
+
+    fn set_log_level(level: u32) -> Result {
+        check_sys_admin()?;
+        crate::set_log_level(level)
+    }
+
+    fn set_circuit_breaker(&self, config: BinderCircuitBreaker) {
+        self.inner.lock().circuit_breaker = CircuitBreaker {
+            threshold: config.failures,
+            cooldown_ms: config.cooldown_ms,
+            failures: 0,
+            opened: None,
+        };
+    }
+
+    fn set_max_sync_txns(&self, max: u32) {
+        self.inner.lock().max_sync_txns = max;
+    }
+
+    fn set_txn_storm_threshold(&self, threshold: u32) {
+        self.inner.lock().txn_storms = StormDetector {
+            threshold,
+            ..StormDetector::default()
+        };
+    }
+
+    fn set_max_delivered_deaths(&self, max: u32) {
+        self.inner.lock().max_delivered_deaths = max;
+    }
+
+    /// Freezes the process if it has no pending transactions, without waiting for them. Returns
+    /// whether the process was already frozen.
+    fn freeze_if_idle(&self) -> Result<bool> {
+        let mut inner = self.inner.lock();
+        if inner.is_frozen {
+            return Ok(true);
+        }
+        if inner.txns_pending_locked() {
+            return Err(EAGAIN);
+        }
+        inner.sync_recv = false;
+        inner.async_recv = false;
+        inner.is_frozen = true;
+        Ok(false)
+    }
+
+    fn unfreeze(&self) {
+        let mut inner = self.inner.lock();
+        inner.sync_recv = false;
+        inner.async_recv = false;
+        inner.is_frozen = false;
+    }
+
     pub(crate) fn ioctl_freeze(&self, info: &BinderFreezeInfo) -> Result {
         if info.enable == 0 {
-            let mut inner = self.inner.lock();
-            inner.sync_recv = false;
-            inner.async_recv = false;
-            inner.is_frozen = false;
+            self.unfreeze();
             return Ok(());
         }
 
@@ -1284,7 +3080,11 @@ pub(crate) fn ioctl_freeze(&self, info: &BinderFreezeInfo) -> Result {
         inner.is_frozen = true;
 
         if info.timeout_ms > 0 {
+            let start = Ktime::ktime_get();
+            inner.freeze_wait_start = Some(start);
+
             let mut jiffies = kernel::time::msecs_to_jiffies(info.timeout_ms);
+            let mut signal = false;
             while jiffies > 0 {
                 if inner.outstanding_txns == 0 {
                     break;
@@ -1295,8 +3095,8 @@ pub(crate) fn ioctl_freeze(&self, info: &BinderFreezeInfo) -> Result {
                     .wait_interruptible_timeout(&mut inner, jiffies)
                 {
                     CondVarTimeoutResult::Signal { .. } => {
-                        inner.is_frozen = false;
-                        return Err(ERESTARTSYS);
+                        signal = true;
+                        break;
                     }
                     CondVarTimeoutResult::Woken { jiffies: remaining } => {
                         jiffies = remaining;
@@ -1306,6 +3106,22 @@ pub(crate) fn ioctl_freeze(&self, info: &BinderFreezeInfo) -> Result {
                     }
                 }
             }
+
+            inner.freeze_wait_start = None;
+            let waited = ktime_ms_delta(Ktime::ktime_get(), start);
+            if waited > FREEZE_WAIT_WARN_MS && FREEZE_WAIT_RATELIMIT.allow() {
+                pr_warn!(
+                    "{}: waited {}ms in freeze, {} outstanding transactions\n",
+                    self.task.pid(),
+                    waited,
+                    inner.outstanding_txns
+                );
+            }
+
+            if signal {
+                inner.is_frozen = false;
+                return Err(ERESTARTSYS);
+            }
         }
 
         if inner.txns_pending_locked() {
@@ -1346,8 +3162,188 @@ fn get_frozen_status(data: UserSlice) -> Result {
     }
 }
 
-fn ioctl_freeze(reader: &mut UserSliceReader) -> Result {
+fn get_outstanding_txns(data: UserSlice) -> Result {
+    check_sys_admin()?;
+    let (mut reader, mut writer) = data.reader_writer();
+
+    let mut info = reader.read::<BinderOutstandingTxns>()?;
+    info.count = 0;
+    let mut found = false;
+
+    for ctx in crate::context::get_all_contexts()? {
+        ctx.for_each_proc(|proc| {
+            if proc.task.pid() == info.pid as _ {
+                found = true;
+                info.count += proc.inner.lock().outstanding_txns;
+            }
+        });
+    }
+
+    if found {
+        writer.write(&info)?;
+        Ok(())
+    } else {
+        Err(EINVAL)
+    }
+}
+
+fn dump_node(data: UserSlice) -> Result {
+    check_sys_admin()?;
+    let (mut reader, mut writer) = data.reader_writer();
+
+    let debug_id = reader.read::<BinderNodeDump>()?.debug_id;
+    let mut dump = BinderNodeDump {
+        debug_id,
+        ..BinderNodeDump::default()
+    };
+
+    for ctx in crate::context::get_all_contexts()? {
+        for proc in ctx.get_all_procs()? {
+            let inner = proc.inner.lock();
+            let node = inner
+                .nodes
+                .values()
+                .find(|node| node.debug_id as u64 == debug_id);
+            if let Some(node) = node {
+                dump.owner_pid = proc.task.pid_in_current_ns() as u32;
+                node.dump(&mut dump, &inner);
+                drop(inner);
+                return writer.write(&dump);
+            }
+        }
+    }
+
+    Err(ENOENT)
+}
+
+fn get_pid_stats(data: UserSlice) -> Result {
+    check_sys_admin()?;
+    let (mut reader, mut writer) = data.reader_writer();
+
+    let pid = reader.read::<BinderPidStats>()?.pid;
+    let mut stats = BinderPidStats {
+        pid,
+        ..BinderPidStats::default()
+    };
+
+    for ctx in crate::context::get_all_contexts()? {
+        for proc in ctx.get_procs_with_pid(pid as i32)? {
+            let state = proc.export_state();
+            stats.processes += 1;
+            stats.threads += state.threads;
+            stats.nodes += state.nodes;
+            stats.refs += state.refs;
+            stats.outstanding_txns += state.outstanding_txns;
+            stats.pending_work += state.pending_work;
+            stats.buffer_allocated += state.buffer_allocated;
+            stats.buffer_size += state.buffer_size;
+        }
+    }
+
+    if stats.processes == 0 {
+        return Err(EINVAL);
+    }
+    writer.write(&stats)
+}
+
+fn get_pid_presence(data: UserSlice) -> Result {
+    check_sys_admin()?;
+    let (mut reader, mut writer) = data.reader_writer();
+
+    let mut info = reader.read::<BinderPidPresence>()?;
+    info.num_contexts = 0;
+    info.names = Default::default();
+
+    for ctx in crate::context::get_all_contexts()? {
+        if ctx.get_procs_with_pid(info.pid)?.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = info.names.get_mut(info.num_contexts as usize) {
+            copy_context_name(name, &ctx);
+        }
+        info.num_contexts += 1;
+    }
+
+    writer.write(&info)?;
+    Ok(())
+}
+
+/// Copies the name of `ctx` into `name`, truncating it so that it stays nul-terminated.
+fn copy_context_name(name: &mut [u8; BINDER_CONTEXT_NAME_MAX], ctx: &Context) {
+    // Leave room for the nul terminator.
+    let src = ctx.name.as_bytes();
+    let len = usize::min(src.len(), name.len() - 1);
+    name[..len].copy_from_slice(&src[..len]);
+}
+
+/// Checks that the caller has `CAP_SYS_ADMIN`.
+fn check_sys_admin() -> Result {
+    // SAFETY: Calling `capable` is always safe.
+    if unsafe { bindings::capable(bindings::CAP_SYS_ADMIN as _) } {
+        Ok(())
+    } else {
+        Err(EPERM)
+    }
+}
+
+fn get_memory_summary(data: UserSlice) -> Result {
+    check_sys_admin()?;
+
+    let mut summary = BinderMemorySummary::default();
+    for ctx in crate::context::get_all_contexts()? {
+        ctx.for_each_proc(|proc| {
+            let inner = proc.inner.lock();
+            if let Some(mapping) = &inner.mapping {
+                summary.mapped_bytes += mapping.alloc.size() as u64;
+                summary.allocated_bytes += mapping.alloc.allocated_size() as u64;
+                summary.pages_in_use += mapping.alloc.pages_in_use() as u64;
+            }
+        });
+    }
+
+    data.writer().write(&summary)?;
+    Ok(())
+}
+
+/// Returns whether `cred` has `CAP_SYS_ADMIN` in the initial user namespace.
+fn cred_has_sys_admin(cred: &Credential) -> bool {
+    let cred: *const Credential = cred;
+    // SAFETY: `Credential` is a transparent wrapper around `struct cred`, which is valid for the
+    // duration of this call, and `init_user_ns` is always valid.
+    unsafe {
+        bindings::security_capable(
+            cred.cast(),
+            core::ptr::addr_of_mut!(bindings::init_user_ns),
+            bindings::CAP_SYS_ADMIN as _,
+            bindings::CAP_OPT_NONE,
+        ) == 0
+    }
+}
+
+fn set_strict_freeze(enabled: u32) -> Result {
+    check_sys_admin()?;
+    STRICT_FREEZE.store(enabled != 0, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Checks that `caller` may freeze or unfreeze the processes of `pid`.
+fn check_freeze_permission(caller: &Process, pid: u32) -> Result {
+    // Freezing is not limited to the context of the caller, so in strict mode only privileged
+    // callers may freeze a process other than their own. The credentials of the caller are the
+    // ones it opened the device with, like for the other binder security checks.
+    if STRICT_FREEZE.load(Ordering::Relaxed)
+        && pid as i32 != caller.task.pid()
+        && !cred_has_sys_admin(&caller.cred)
+    {
+        return Err(EPERM);
+    }
+    Ok(())
+}
+
+fn ioctl_freeze(caller: &Process, reader: &mut UserSliceReader) -> Result {
     let info = reader.read::<BinderFreezeInfo>()?;
+    check_freeze_permission(caller, info.pid)?;
 
     // Very unlikely for there to be more than 3, since a process normally uses at most binder and
     // hwbinder.
@@ -1366,6 +3362,139 @@ fn ioctl_freeze(reader: &mut UserSliceReader) -> Result {
     Ok(())
 }
 
+fn ioctl_freeze_if_idle(caller: &Process, pid: u32) -> Result {
+    check_freeze_permission(caller, pid)?;
+
+    let mut procs = Vec::try_with_capacity(3)?;
+    for ctx in crate::context::get_all_contexts()? {
+        for proc in ctx.get_procs_with_pid(pid as i32)? {
+            procs.try_push(proc)?;
+        }
+    }
+
+    // The processes frozen by this call, which are unfrozen again if any of the others is busy.
+    let mut frozen = Vec::try_with_capacity(procs.len())?;
+    for proc in &procs {
+        match proc.freeze_if_idle() {
+            Ok(true) => {}
+            Ok(false) => frozen.try_push(proc)?,
+            Err(err) => {
+                for proc in frozen {
+                    proc.unfreeze();
+                }
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn ioctl_wake_thread(tid: u32) -> Result {
+    check_sys_admin()?;
+
+    let mut thread = None;
+    'outer: for ctx in crate::context::get_all_contexts()? {
+        for proc in ctx.get_all_procs()? {
+            if let Some(t) = proc.inner.lock().threads.get(&(tid as i32)) {
+                thread = Some(t.clone());
+                break 'outer;
+            }
+        }
+    }
+    let thread = thread.ok_or(ENOENT)?;
+
+    let work = DTRWrap::arc_try_new(DeliverCode::new(BR_NOOP))?;
+    match thread.push_work(work) {
+        PushWorkRes::Ok => Ok(()),
+        PushWorkRes::FailedDead(_) => Err(ESRCH),
+    }
+}
+
+/// Returns the name of an ioctl command, for logging.
+fn ioctl_name(cmd: u32) -> &'static str {
+    match cmd {
+        bindings::BINDER_WRITE_READ => "BINDER_WRITE_READ",
+        bindings::BINDER_SET_MAX_THREADS => "BINDER_SET_MAX_THREADS",
+        bindings::BINDER_THREAD_EXIT => "BINDER_THREAD_EXIT",
+        bindings::BINDER_SET_CONTEXT_MGR => "BINDER_SET_CONTEXT_MGR",
+        bindings::BINDER_SET_CONTEXT_MGR_EXT => "BINDER_SET_CONTEXT_MGR_EXT",
+        bindings::BINDER_ENABLE_ONEWAY_SPAM_DETECTION => "BINDER_ENABLE_ONEWAY_SPAM_DETECTION",
+        bindings::BINDER_FREEZE => "BINDER_FREEZE",
+        bindings::BINDER_GET_NODE_DEBUG_INFO => "BINDER_GET_NODE_DEBUG_INFO",
+        bindings::BINDER_GET_NODE_INFO_FOR_REF => "BINDER_GET_NODE_INFO_FOR_REF",
+        bindings::BINDER_VERSION => "BINDER_VERSION",
+        bindings::BINDER_GET_FROZEN_INFO => "BINDER_GET_FROZEN_INFO",
+        bindings::BINDER_GET_EXTENDED_ERROR => "BINDER_GET_EXTENDED_ERROR",
+        BINDER_SET_BUFFER_PRESSURE_THRESHOLD => "BINDER_SET_BUFFER_PRESSURE_THRESHOLD",
+        BINDER_GET_PID_PRESENCE => "BINDER_GET_PID_PRESENCE",
+        BINDER_GET_MEMORY_SUMMARY => "BINDER_GET_MEMORY_SUMMARY",
+        BINDER_SET_LARGE_TRANSACTION_WARN_SIZE => "BINDER_SET_LARGE_TRANSACTION_WARN_SIZE",
+        BINDER_ENABLE_STATS => "BINDER_ENABLE_STATS",
+        BINDER_GET_NODE_DEBUG_IDS => "BINDER_GET_NODE_DEBUG_IDS",
+        BINDER_SET_HANDLE_WARN_COUNT => "BINDER_SET_HANDLE_WARN_COUNT",
+        BINDER_GET_DEATH_NOTIFICATIONS => "BINDER_GET_DEATH_NOTIFICATIONS",
+        BINDER_ENABLE_IOCTL_LOG => "BINDER_ENABLE_IOCTL_LOG",
+        BINDER_SET_STRICT_HANDLES => "BINDER_SET_STRICT_HANDLES",
+        BINDER_GET_THREAD_STATES => "BINDER_GET_THREAD_STATES",
+        BINDER_SET_HANDLE_BASE => "BINDER_SET_HANDLE_BASE",
+        BINDER_GET_LAST_ERROR => "BINDER_GET_LAST_ERROR",
+        BINDER_SET_MAX_PROCS => "BINDER_SET_MAX_PROCS",
+        BINDER_GET_SIZE_HISTOGRAM => "BINDER_GET_SIZE_HISTOGRAM",
+        BINDER_FREEZE_IF_IDLE => "BINDER_FREEZE_IF_IDLE",
+        BINDER_SET_STRICT_FREEZE => "BINDER_SET_STRICT_FREEZE",
+        BINDER_SET_SPARE_THREADS => "BINDER_SET_SPARE_THREADS",
+        BINDER_GET_OUTSTANDING_TXNS => "BINDER_GET_OUTSTANDING_TXNS",
+        BINDER_SET_DEFAULT_MAX_THREADS => "BINDER_SET_DEFAULT_MAX_THREADS",
+        BINDER_GET_HANDLE_STATUS => "BINDER_GET_HANDLE_STATUS",
+        BINDER_EXPORT_STATE => "BINDER_EXPORT_STATE",
+        BINDER_SET_NODE_UID_FILTER => "BINDER_SET_NODE_UID_FILTER",
+        BINDER_GET_NODE_INFO_FOR_REFS => "BINDER_GET_NODE_INFO_FOR_REFS",
+        BINDER_SET_MAX_SYNC_TXNS => "BINDER_SET_MAX_SYNC_TXNS",
+        BINDER_PAUSE_DELIVERY => "BINDER_PAUSE_DELIVERY",
+        BINDER_RESET_NODE_STATS => "BINDER_RESET_NODE_STATS",
+        BINDER_GET_NODE_FANOUT => "BINDER_GET_NODE_FANOUT",
+        BINDER_SET_LOG_LEVEL => "BINDER_SET_LOG_LEVEL",
+        BINDER_GET_THREAD_SERVICED => "BINDER_GET_THREAD_SERVICED",
+        BINDER_GET_PID_STATS => "BINDER_GET_PID_STATS",
+        BINDER_GET_STALE_NODES => "BINDER_GET_STALE_NODES",
+        BINDER_GET_BUFFER_PAGES => "BINDER_GET_BUFFER_PAGES",
+        BINDER_SET_CIRCUIT_BREAKER => "BINDER_SET_CIRCUIT_BREAKER",
+        BINDER_GET_RECEIVED_SEQ => "BINDER_GET_RECEIVED_SEQ",
+        BINDER_SET_ALLOWED_NODE_FLAGS => "BINDER_SET_ALLOWED_NODE_FLAGS",
+        BINDER_SET_MMAP_REQUIRES_MAX_THREADS => "BINDER_SET_MMAP_REQUIRES_MAX_THREADS",
+        BINDER_GET_READY_THREADS => "BINDER_GET_READY_THREADS",
+        BINDER_ENABLE_DEAD_REPLY_ERRORS => "BINDER_ENABLE_DEAD_REPLY_ERRORS",
+        BINDER_GET_OLDEST_WORK_AGE => "BINDER_GET_OLDEST_WORK_AGE",
+        BINDER_SET_DEFERRED_FREE => "BINDER_SET_DEFERRED_FREE",
+        BINDER_AUDIT_REFS => "BINDER_AUDIT_REFS",
+        BINDER_GET_CONTEXT_TXN_STATS => "BINDER_GET_CONTEXT_TXN_STATS",
+        BINDER_SET_NODE_CREATION_RATE => "BINDER_SET_NODE_CREATION_RATE",
+        BINDER_COMPARE_HANDLES => "BINDER_COMPARE_HANDLES",
+        BINDER_SET_FLUSH_DRAIN_TIMEOUT => "BINDER_SET_FLUSH_DRAIN_TIMEOUT",
+        BINDER_DUMP_NODE => "BINDER_DUMP_NODE",
+        BINDER_SET_ASYNC_PRIORITY_CAP => "BINDER_SET_ASYNC_PRIORITY_CAP",
+        BINDER_GET_HANDLE_STATS => "BINDER_GET_HANDLE_STATS",
+        BINDER_GET_CONTEXT_NAME => "BINDER_GET_CONTEXT_NAME",
+        BINDER_SET_MAX_DELIVERED_DEATHS => "BINDER_SET_MAX_DELIVERED_DEATHS",
+        BINDER_GET_ACTIVITY => "BINDER_GET_ACTIVITY",
+        BINDER_SET_BUFFER_WAIT_TIMEOUT => "BINDER_SET_BUFFER_WAIT_TIMEOUT",
+        BINDER_SET_TXN_STORM_THRESHOLD => "BINDER_SET_TXN_STORM_THRESHOLD",
+        BINDER_GET_MANAGER_INFO => "BINDER_GET_MANAGER_INFO",
+        BINDER_ENABLE_BUFFER_CHECKSUMS => "BINDER_ENABLE_BUFFER_CHECKSUMS",
+        BINDER_GET_REFS => "BINDER_GET_REFS",
+        BINDER_SET_MAX_CHAIN_LEN => "BINDER_SET_MAX_CHAIN_LEN",
+        BINDER_GET_CONFIG => "BINDER_GET_CONFIG",
+        BINDER_SET_CONFIG => "BINDER_SET_CONFIG",
+        BINDER_GET_MAPPING_INFO => "BINDER_GET_MAPPING_INFO",
+        BINDER_SET_STRICT_MMAP => "BINDER_SET_STRICT_MMAP",
+        BINDER_WAKE_THREAD => "BINDER_WAKE_THREAD",
+        BINDER_SET_UNDERFLOW_POLICY => "BINDER_SET_UNDERFLOW_POLICY",
+        BINDER_SET_CONTEXT_MEMORY_LIMIT => "BINDER_SET_CONTEXT_MEMORY_LIMIT",
+        BINDER_SET_MAX_BUFFER_DEPTH => "BINDER_SET_MAX_BUFFER_DEPTH",
+        _ => "unknown",
+    }
+}
+
 /// The ioctl handler.
 impl Process {
     /// Ioctls that are write-only from the perspective of userspace.
@@ -1380,6 +3509,14 @@ fn ioctl_write_only(
         let thread = this.get_current_thread()?;
         match cmd {
             bindings::BINDER_SET_MAX_THREADS => this.set_max_threads(reader.read()?),
+            BINDER_SET_SPARE_THREADS => this.set_spare_threads(reader.read()?),
+            BINDER_SET_MAX_SYNC_TXNS => this.set_max_sync_txns(reader.read()?),
+            BINDER_SET_TXN_STORM_THRESHOLD => this.set_txn_storm_threshold(reader.read()?),
+            BINDER_SET_MAX_DELIVERED_DEATHS => this.set_max_delivered_deaths(reader.read()?),
+            BINDER_SET_CIRCUIT_BREAKER => this.set_circuit_breaker(reader.read()?),
+            BINDER_PAUSE_DELIVERY => this.set_delivery_paused(reader.read()?),
+            BINDER_RESET_NODE_STATS => this.reset_node_stats(reader.read()?)?,
+            BINDER_SET_LOG_LEVEL => Self::set_log_level(reader.read()?)?,
             bindings::BINDER_THREAD_EXIT => this.remove_thread(thread),
             bindings::BINDER_SET_CONTEXT_MGR => this.set_as_manager(None, &thread)?,
             bindings::BINDER_SET_CONTEXT_MGR_EXT => {
@@ -1388,7 +3525,41 @@ fn ioctl_write_only(
             bindings::BINDER_ENABLE_ONEWAY_SPAM_DETECTION => {
                 this.set_oneway_spam_detection_enabled(reader.read()?)
             }
-            bindings::BINDER_FREEZE => ioctl_freeze(reader)?,
+            bindings::BINDER_FREEZE => ioctl_freeze(&this, reader)?,
+            BINDER_FREEZE_IF_IDLE => ioctl_freeze_if_idle(&this, reader.read()?)?,
+            BINDER_SET_STRICT_FREEZE => set_strict_freeze(reader.read()?)?,
+            BINDER_SET_BUFFER_PRESSURE_THRESHOLD => {
+                this.set_buffer_pressure_threshold(reader.read()?)?
+            }
+            BINDER_ENABLE_STATS => this.set_stats_enabled(reader.read()?),
+            BINDER_SET_NODE_CREATION_RATE => this.set_node_creation_rate(reader.read()?),
+            BINDER_SET_FLUSH_DRAIN_TIMEOUT => this.set_flush_drain_timeout(reader.read()?),
+            BINDER_SET_BUFFER_WAIT_TIMEOUT => this.set_buffer_wait_timeout(reader.read()?),
+            BINDER_ENABLE_BUFFER_CHECKSUMS => this.set_buffer_checksums_enabled(reader.read()?),
+            BINDER_SET_DEFERRED_FREE => this.set_deferred_free_bytes(reader.read()?),
+            BINDER_ENABLE_IOCTL_LOG => this.set_ioctl_log_enabled(reader.read()?)?,
+            BINDER_SET_STRICT_HANDLES => this.set_strict_handles(reader.read()?),
+            BINDER_SET_STRICT_MMAP => this.set_strict_mmap(reader.read()?),
+            BINDER_ENABLE_DEAD_REPLY_ERRORS => this.set_dead_reply_errors(reader.read()?),
+            BINDER_WAKE_THREAD => ioctl_wake_thread(reader.read()?)?,
+            BINDER_SET_HANDLE_BASE => this.set_handle_base(reader.read()?)?,
+            BINDER_SET_LARGE_TRANSACTION_WARN_SIZE => {
+                this.set_large_transaction_warn_size(reader.read()?)?
+            }
+            BINDER_SET_HANDLE_WARN_COUNT => this.set_handle_warn_count(reader.read()?)?,
+            BINDER_SET_MAX_PROCS => this.set_max_procs(reader.read()?)?,
+            BINDER_SET_UNDERFLOW_POLICY => this.set_underflow_policy(reader.read()?)?,
+            BINDER_SET_CONTEXT_MEMORY_LIMIT => this.set_context_memory_limit(reader.read()?)?,
+            BINDER_SET_MAX_CHAIN_LEN => this.set_max_chain_len(reader.read()?)?,
+            BINDER_SET_MAX_BUFFER_DEPTH => this.set_max_buffer_depth(reader.read()?)?,
+            BINDER_SET_CONFIG => this.set_config(reader.read()?)?,
+            BINDER_SET_ALLOWED_NODE_FLAGS => this.set_allowed_node_flags(reader.read()?)?,
+            BINDER_SET_ASYNC_PRIORITY_CAP => this.set_cap_async_priority(reader.read()?)?,
+            BINDER_SET_MMAP_REQUIRES_MAX_THREADS => {
+                this.set_mmap_requires_max_threads(reader.read()?)?
+            }
+            BINDER_SET_DEFAULT_MAX_THREADS => this.set_default_max_threads(reader.read()?)?,
+            BINDER_SET_NODE_UID_FILTER => this.set_node_uid_filter(reader.read()?)?,
             _ => return Err(EINVAL),
         }
         Ok(0)
@@ -1412,6 +3583,36 @@ fn ioctl_write_read(
             bindings::BINDER_VERSION => this.version(data)?,
             bindings::BINDER_GET_FROZEN_INFO => get_frozen_status(data)?,
             bindings::BINDER_GET_EXTENDED_ERROR => thread.get_extended_error(data)?,
+            BINDER_GET_RECEIVED_SEQ => thread.get_received_seq(data)?,
+            BINDER_GET_PID_PRESENCE => get_pid_presence(data)?,
+            BINDER_GET_OUTSTANDING_TXNS => get_outstanding_txns(data)?,
+            BINDER_GET_PID_STATS => get_pid_stats(data)?,
+            BINDER_DUMP_NODE => dump_node(data)?,
+            BINDER_GET_HANDLE_STATUS => this.get_handle_status(data)?,
+            BINDER_EXPORT_STATE => data.writer().write(&this.export_state())?,
+            BINDER_GET_NODE_INFO_FOR_REFS => this.get_node_info_for_refs(data)?,
+            BINDER_GET_NODE_FANOUT => this.get_node_fanout(data)?,
+            BINDER_GET_THREAD_SERVICED => this.get_thread_serviced(data)?,
+            BINDER_GET_READY_THREADS => this.get_ready_threads(data)?,
+            BINDER_GET_OLDEST_WORK_AGE => this.get_oldest_work_age(data)?,
+            BINDER_AUDIT_REFS => this.audit_refs(data)?,
+            BINDER_GET_HANDLE_STATS => this.get_handle_stats(data)?,
+            BINDER_GET_CONTEXT_NAME => this.get_context_name(data)?,
+            BINDER_GET_MANAGER_INFO => this.get_manager_info(data)?,
+            BINDER_GET_CONTEXT_TXN_STATS => this.get_context_txn_stats(data)?,
+            BINDER_COMPARE_HANDLES => this.compare_handles(data)?,
+            BINDER_GET_STALE_NODES => this.get_stale_nodes(data)?,
+            BINDER_GET_BUFFER_PAGES => this.get_buffer_pages(data)?,
+            BINDER_GET_MEMORY_SUMMARY => get_memory_summary(data)?,
+            BINDER_GET_NODE_DEBUG_IDS => this.get_node_debug_ids(data)?,
+            BINDER_GET_DEATH_NOTIFICATIONS => this.get_death_notifications(data)?,
+            BINDER_GET_REFS => this.get_refs(data)?,
+            BINDER_GET_CONFIG => this.get_config(data)?,
+            BINDER_GET_MAPPING_INFO => this.get_mapping_info(data)?,
+            BINDER_GET_THREAD_STATES => this.get_thread_states(data)?,
+            BINDER_GET_LAST_ERROR => this.get_last_error(data)?,
+            BINDER_GET_ACTIVITY => this.get_activity(data)?,
+            BINDER_GET_SIZE_HISTOGRAM => this.get_size_histogram(data)?,
             _ => return Err(EINVAL),
         }
         Ok(0)
@@ -1440,6 +3641,8 @@ pub(crate) fn release(this: Arc<Process>, _file: &File) {
     }
 
     pub(crate) fn flush(this: ArcBorrow<'_, Process>) -> Result {
+        this.drain_for_flush();
+
         let should_schedule;
         {
             let mut inner = this.inner.lock();
@@ -1468,11 +3671,23 @@ pub(crate) fn ioctl(
 
         const _IOC_READ_WRITE: u32 = _IOC_READ | _IOC_WRITE;
 
-        match _IOC_DIR(cmd) {
+        let res = match _IOC_DIR(cmd) {
             _IOC_WRITE => Self::ioctl_write_only(this, file, cmd, &mut user_slice.reader()),
             _IOC_READ_WRITE => Self::ioctl_write_read(this, file, cmd, user_slice),
             _ => Err(EINVAL),
+        };
+
+        if this.ioctl_log.load(Ordering::Relaxed) && IOCTL_LOG_RATELIMIT.allow() {
+            pr_info!(
+                "{}:{} ioctl {} ({:#x}) returned {:?}\n",
+                this.task.pid(),
+                kernel::current!().pid(),
+                ioctl_name(cmd),
+                cmd,
+                res
+            );
         }
+        res
     }
 
     pub(crate) fn compat_ioctl(
@@ -1515,9 +3730,13 @@ pub(crate) fn poll(
     ) -> Result<u32> {
         let thread = this.get_current_thread()?;
         let (from_proc, mut mask) = thread.poll(file, table);
-        if mask == 0 && from_proc && !this.inner.lock().work.is_empty() {
+        let inner = this.inner.lock();
+        if mask == 0 && from_proc && inner.has_queued_work() {
             mask |= bindings::POLLIN;
         }
+        if inner.buffer_pressure {
+            mask |= bindings::POLLPRI;
+        }
         Ok(mask)
     }
 }
@@ -1538,6 +3757,9 @@ fn new(
         // INVARIANT: We are pushing this thread to the right `ready_threads` list.
         if let Ok(list_arc) = ListArc::try_from_arc(thread.clone()) {
             guard.ready_threads.push_front(list_arc);
+            if guard.stats_enabled {
+                guard.stats.ready_registrations += 1;
+            }
         } else {
             // It is an error to hit this branch, and it should not be reachable. We try to do
             // something reasonable when the failure path happens. Most likely, the thread in
@@ -1554,6 +3776,12 @@ fn drop(&mut self) {
         // SAFETY: The thread has the invariant that we never push it to any other linked list than
         // the `ready_threads` list of its parent process. Therefore, the thread is either in that
         // list, or in no list.
-        unsafe { inner.ready_threads.remove(self.thread) };
+        let removed = unsafe { inner.ready_threads.remove(self.thread) };
+        // A thread that is still in the list was not handed any work while it waited.
+        if removed.is_some() && inner.stats_enabled {
+            inner.stats.idle_deregistrations += 1;
+        }
+        drop(inner);
+        drop(removed);
     }
 }
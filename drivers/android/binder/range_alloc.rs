@@ -20,17 +20,29 @@ pub(crate) struct RangeAllocator<T> {
     tree: RBTree<usize, Descriptor<T>>,
     free_tree: RBTree<FreeKey, ()>,
     size: usize,
+    /// The number of bytes currently reserved or allocated.
+    allocated_size: usize,
     free_oneway_space: usize,
     pub(crate) oneway_spam_detected: bool,
 }
 
 /// Represents a range of pages that have just become completely free.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default)]
 pub(crate) struct FreedRange {
     pub(crate) start_page_idx: usize,
     pub(crate) end_page_idx: usize,
 }
 
+/// The pages spanned by a range, as returned by `RangeAllocator::page_layout`.
+pub(crate) struct PageLayout {
+    pub(crate) first_page: usize,
+    pub(crate) last_page: usize,
+    /// Whether another reserved or allocated range uses the first page.
+    pub(crate) shares_first_page: bool,
+    /// Whether another reserved or allocated range uses the last page.
+    pub(crate) shares_last_page: bool,
+}
+
 impl FreedRange {
     fn interior_pages(offset: usize, size: usize) -> FreedRange {
         FreedRange {
@@ -54,9 +66,71 @@ pub(crate) fn new(size: usize) -> Result<Self> {
             free_tree,
             oneway_spam_detected: false,
             size,
+            allocated_size: 0,
         })
     }
 
+    /// The total size of the range managed by this allocator.
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The number of bytes that are currently reserved or allocated.
+    pub(crate) fn allocated_size(&self) -> usize {
+        self.allocated_size
+    }
+
+    /// The number of pages that overlap with at least one reserved or allocated range.
+    pub(crate) fn pages_in_use(&self) -> usize {
+        let mut count = 0;
+        // The end of the last page that has been counted. The tree is sorted by offset, so ranges
+        // can only overlap with the pages of the previous range.
+        let mut counted_end = 0;
+        for desc in self.tree.values() {
+            if desc.state.is_none() {
+                continue;
+            }
+            let start = usize::max(desc.offset / PAGE_SIZE, counted_end);
+            let end = (desc.offset + desc.size + (PAGE_SIZE - 1)) / PAGE_SIZE;
+            if end > start {
+                count += end - start;
+                counted_end = end;
+            }
+        }
+        count
+    }
+
+    /// Describes how the reserved or allocated range at `offset` maps onto pages.
+    pub(crate) fn page_layout(&self, offset: usize) -> Result<PageLayout> {
+        let desc = self.tree.get(&offset).ok_or(ENOENT)?;
+        if desc.state.is_none() {
+            return Err(ENOENT);
+        }
+        let first_page = desc.offset / PAGE_SIZE;
+        let last_page = (desc.offset + desc.size - 1) / PAGE_SIZE;
+
+        let mut layout = PageLayout {
+            first_page,
+            last_page,
+            shares_first_page: false,
+            shares_last_page: false,
+        };
+        for other in self.tree.values() {
+            if other.state.is_none() || other.offset == desc.offset {
+                continue;
+            }
+            let other_first = other.offset / PAGE_SIZE;
+            let other_last = (other.offset + other.size - 1) / PAGE_SIZE;
+            if other_first <= first_page && first_page <= other_last {
+                layout.shares_first_page = true;
+            }
+            if other_first <= last_page && last_page <= other_last {
+                layout.shares_last_page = true;
+            }
+        }
+        Ok(layout)
+    }
+
     pub(crate) fn debug_print(&self, m: &mut SeqFile) -> Result<()> {
         for desc in self.tree.values() {
             let state = match &desc.state {
@@ -137,6 +211,7 @@ pub(crate) fn reserve_new(
             }
         };
         self.free_oneway_space = new_oneway_space;
+        self.allocated_size += size;
         self.free_tree.remove(&(found_size, found_off));
 
         if found_size != size {
@@ -189,6 +264,7 @@ pub(crate) fn reservation_abort(&mut self, offset: usize) -> Result<FreedRange>
         let free_oneway_space_add = if reservation.is_oneway { size } else { 0 };
 
         self.free_oneway_space += free_oneway_space_add;
+        self.allocated_size -= size;
 
         let mut freed_range = FreedRange::interior_pages(offset, size);
         // Compute how large the next free region needs to be to include one more page in
@@ -274,26 +350,14 @@ pub(crate) fn reservation_commit(&mut self, offset: usize, data: Option<T>) -> R
     ///
     /// Returns the size of the existing entry and the data associated with it.
     pub(crate) fn reserve_existing(&mut self, offset: usize) -> Result<(usize, Option<T>)> {
-        let desc = self.tree.get_mut(&offset).ok_or_else(|| {
-            pr_warn!(
-                "ENOENT from range_alloc.reserve_existing - offset: {}",
-                offset
-            );
-            ENOENT
-        })?;
+        let desc = self.tree.get_mut(&offset).ok_or(ENOENT)?;
 
         let data = desc.try_change_state(|state| match state {
             Some(DescriptorState::Allocated(allocation)) => {
                 let (reservation, data) = allocation.deallocate();
                 (Some(DescriptorState::Reserved(reservation)), Ok(data))
             }
-            other => {
-                pr_warn!(
-                    "ENOENT from range_alloc.reserve_existing - offset: {}",
-                    offset
-                );
-                (other, Err(ENOENT))
-            }
+            other => (other, Err(ENOENT)),
         })?;
 
         Ok((desc.size, data))
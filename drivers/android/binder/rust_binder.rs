@@ -17,13 +17,14 @@
     seq_print,
     sync::poll::PollTable,
     sync::Arc,
+    time::Ktime,
     types::ForeignOwnable,
     uaccess::UserSliceWriter,
 };
 
 use crate::{context::Context, process::Process, thread::Thread};
 
-use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicUsize, Ordering};
 
 mod allocation;
 mod context;
@@ -34,6 +35,7 @@
 mod process;
 mod range_alloc;
 mod thread;
+mod trace;
 mod transaction;
 
 module! {
@@ -50,6 +52,64 @@ fn next_debug_id() -> usize {
     NEXT_DEBUG_ID.fetch_add(1, Ordering::Relaxed)
 }
 
+/// Limits how often a warning is printed to at most `burst` messages per interval.
+pub(crate) struct RateLimit {
+    interval_ms: i64,
+    burst: u32,
+    /// The time at which the current interval started, or `i64::MIN` if there was none.
+    begin_ms: AtomicI64,
+    /// The number of messages allowed in the current interval.
+    printed: AtomicU32,
+}
+
+impl RateLimit {
+    pub(crate) const fn new(interval_ms: i64, burst: u32) -> Self {
+        Self {
+            interval_ms,
+            burst,
+            begin_ms: AtomicI64::new(i64::MIN),
+            printed: AtomicU32::new(0),
+        }
+    }
+
+    /// Returns whether a message may be printed now. If this returns true, the caller is expected
+    /// to print the message.
+    ///
+    /// This is not exact when called concurrently, which is fine for limiting log output.
+    pub(crate) fn allow(&self) -> bool {
+        let now = Ktime::ktime_get().to_ms();
+        let begin = self.begin_ms.load(Ordering::Relaxed);
+        if begin == i64::MIN || now.saturating_sub(begin) >= self.interval_ms {
+            // If several threads race here, only one of them starts the new interval.
+            if self
+                .begin_ms
+                .compare_exchange(begin, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            {
+                self.printed.store(1, Ordering::Relaxed);
+                return true;
+            }
+        }
+        self.printed.fetch_add(1, Ordering::Relaxed) < self.burst
+    }
+}
+
+/// The verbosity of optional log messages. One of the `BINDER_LOG_*` levels.
+static LOG_LEVEL: AtomicU32 = AtomicU32::new(defs::BINDER_LOG_QUIET);
+
+/// Returns whether optional log messages of the given `BINDER_LOG_*` level should be printed.
+pub(crate) fn log_enabled(level: u32) -> bool {
+    LOG_LEVEL.load(Ordering::Relaxed) >= level
+}
+
+pub(crate) fn set_log_level(level: u32) -> Result {
+    if level > defs::BINDER_LOG_DEBUG {
+        return Err(EINVAL);
+    }
+    LOG_LEVEL.store(level, Ordering::Relaxed);
+    Ok(())
+}
+
 /// Specifies how a type should be delivered to the read part of a BINDER_WRITE_READ ioctl.
 ///
 /// When a value is pushed to the todo list for a process or thread, it is stored as a trait object
@@ -76,6 +136,11 @@ trait DeliverToRead: ListArcSafe + Send + Sync {
     /// Generally only set to true for non-oneway transactions.
     fn should_sync_wakeup(&self) -> bool;
 
+    /// Whether this work item is delivered before the normal work queued on the process.
+    fn is_high_priority(&self) -> bool {
+        false
+    }
+
     fn debug_print(&self, m: &mut SeqFile, prefix: &str, transaction_prefix: &str) -> Result<()>;
 }
 
@@ -84,6 +149,8 @@ trait DeliverToRead: ListArcSafe + Send + Sync {
 struct DTRWrap<T: ?Sized> {
     #[pin]
     links: ListLinksSelfPtr<DTRWrap<dyn DeliverToRead>>,
+    /// When the work item was last queued on the work list of a process, in nanoseconds.
+    queued_ns: AtomicI64,
     #[pin]
     wrapped: T,
 }
@@ -110,6 +177,20 @@ fn deref(&self) -> &T {
 
 impl<T: ?Sized> core::ops::Receiver for DTRWrap<T> {}
 
+impl<T: ?Sized> DTRWrap<T> {
+    /// Records that the work item is being queued on the work list of a process.
+    fn mark_queued(&self) {
+        self.queued_ns
+            .store(Ktime::ktime_get().to_ns(), Ordering::Relaxed);
+    }
+
+    /// Returns how long ago the work item was queued on the work list of a process.
+    fn ms_since_queued(&self) -> i64 {
+        let now = Ktime::ktime_get().to_ns();
+        (now - self.queued_ns.load(Ordering::Relaxed)) / 1_000_000
+    }
+}
+
 type DArc<T> = kernel::sync::Arc<DTRWrap<T>>;
 type DLArc<T> = kernel::list::ListArc<DTRWrap<T>>;
 
@@ -117,6 +198,7 @@ impl<T: ListArcSafe> DTRWrap<T> {
     fn new(val: impl PinInit<T>) -> impl PinInit<Self> {
         pin_init!(Self {
             links <- ListLinksSelfPtr::new(),
+            queued_ns: AtomicI64::new(0),
             wrapped <- val,
         })
     }
@@ -124,6 +206,7 @@ fn new(val: impl PinInit<T>) -> impl PinInit<Self> {
     fn arc_try_new(val: T) -> Result<DLArc<T>, alloc::alloc::AllocError> {
         ListArc::pin_init(pin_init!(Self {
             links <- ListLinksSelfPtr::new(),
+            queued_ns: AtomicI64::new(0),
             wrapped: val,
         }))
         .map_err(|_| alloc::alloc::AllocError)
@@ -132,6 +215,7 @@ fn arc_try_new(val: T) -> Result<DLArc<T>, alloc::alloc::AllocError> {
     fn arc_pin_init(init: impl PinInit<T>) -> Result<DLArc<T>, kernel::error::Error> {
         ListArc::pin_init(pin_init!(Self {
             links <- ListLinksSelfPtr::new(),
+            queued_ns: AtomicI64::new(0),
             wrapped <- init,
         }))
     }
@@ -192,9 +276,13 @@ fn debug_print(&self, m: &mut SeqFile, prefix: &str, _tprefix: &str) -> Result<(
     }
 }
 
-const fn ptr_align(value: usize) -> usize {
+/// Rounds `value` up to a multiple of the pointer size. Returns `None` on overflow.
+const fn ptr_align(value: usize) -> Option<usize> {
     let size = core::mem::size_of::<usize>() - 1;
-    (value + size) & !size
+    match value.checked_add(size) {
+        Some(sum) => Some(sum & !size),
+        None => None,
+    }
 }
 
 // SAFETY: We call register in `init`.
@@ -390,9 +478,15 @@ unsafe impl<T> Sync for AssertSync<T> {}
 
 #[no_mangle]
 unsafe extern "C" fn rust_binder_stats_show(
-    _: *mut seq_file,
+    ptr: *mut seq_file,
     _: *mut core::ffi::c_void,
 ) -> core::ffi::c_int {
+    // SAFETY: The caller ensures that the pointer is valid and exclusive for the duration in which
+    // this method is called.
+    let m = unsafe { SeqFile::from_raw(ptr) };
+    if let Err(err) = rust_binder_stats_show_impl(m) {
+        seq_print!(m, "failed to generate stats: {:?}\n", err);
+    }
     0
 }
 
@@ -426,6 +520,19 @@ unsafe impl<T> Sync for AssertSync<T> {}
     0
 }
 
+fn rust_binder_stats_show_impl(m: &mut SeqFile) -> Result<()> {
+    seq_print!(m, "binder stats:\n");
+    let contexts = context::get_all_contexts()?;
+    for ctx in contexts {
+        let procs = ctx.get_all_procs()?;
+        for proc in procs {
+            proc.debug_print_stats(m, &ctx)?;
+            seq_print!(m, "\n");
+        }
+    }
+    Ok(())
+}
+
 fn rust_binder_state_show_impl(m: &mut SeqFile) -> Result<()> {
     seq_print!(m, "binder state:\n");
     let contexts = context::get_all_contexts()?;
@@ -0,0 +1,78 @@
+/* SPDX-License-Identifier: GPL-2.0 */
+/*
+ * Copyright (C) 2024 Google LLC.
+ */
+
+#undef TRACE_SYSTEM
+#undef TRACE_INCLUDE_FILE
+#undef TRACE_INCLUDE_PATH
+#define TRACE_SYSTEM rust_binder
+#define TRACE_INCLUDE_FILE rust_binder_events
+#define TRACE_INCLUDE_PATH binder
+
+#if !defined(_RUST_BINDER_TRACE_H) || defined(TRACE_HEADER_MULTI_READ)
+#define _RUST_BINDER_TRACE_H
+
+#include <linux/tracepoint.h>
+
+TRACE_EVENT(rust_binder_transaction,
+	TP_PROTO(bool reply, int debug_id, int from_pid, int to_pid,
+		 int node_debug_id, size_t data_size, unsigned int flags),
+	TP_ARGS(reply, debug_id, from_pid, to_pid, node_debug_id, data_size, flags),
+	TP_STRUCT__entry(
+		__field(bool, reply)
+		__field(int, debug_id)
+		__field(int, from_pid)
+		__field(int, to_pid)
+		__field(int, node_debug_id)
+		__field(size_t, data_size)
+		__field(unsigned int, flags)
+	),
+	TP_fast_assign(
+		__entry->reply = reply;
+		__entry->debug_id = debug_id;
+		__entry->from_pid = from_pid;
+		__entry->to_pid = to_pid;
+		__entry->node_debug_id = node_debug_id;
+		__entry->data_size = data_size;
+		__entry->flags = flags;
+	),
+	TP_printk("transaction=%d from_pid=%d dest_pid=%d dest_node=%d reply=%d size=%zu flags=0x%x",
+		  __entry->debug_id, __entry->from_pid, __entry->to_pid,
+		  __entry->node_debug_id, __entry->reply, __entry->data_size,
+		  __entry->flags)
+);
+
+TRACE_EVENT(rust_binder_transaction_received,
+	TP_PROTO(int debug_id),
+	TP_ARGS(debug_id),
+	TP_STRUCT__entry(
+		__field(int, debug_id)
+	),
+	TP_fast_assign(
+		__entry->debug_id = debug_id;
+	),
+	TP_printk("transaction=%d", __entry->debug_id)
+);
+
+TRACE_EVENT(rust_binder_buffer_release,
+	TP_PROTO(int pid, size_t offset, size_t size),
+	TP_ARGS(pid, offset, size),
+	TP_STRUCT__entry(
+		__field(int, pid)
+		__field(size_t, offset)
+		__field(size_t, size)
+	),
+	TP_fast_assign(
+		__entry->pid = pid;
+		__entry->offset = offset;
+		__entry->size = size;
+	),
+	TP_printk("pid=%d offset=%zu size=%zu",
+		  __entry->pid, __entry->offset, __entry->size)
+);
+
+#endif /* _RUST_BINDER_TRACE_H */
+
+/* This part must be outside protection */
+#include <trace/define_trace.h>
@@ -28,12 +28,13 @@
 use crate::{
     allocation::{Allocation, AllocationView, BinderObject, BinderObjectRef},
     defs::*,
-    error::BinderResult,
+    error::{BinderError, BinderResult},
+    log_enabled,
     prio::{self, BinderPriority, PriorityState},
     process::Process,
     ptr_align,
     transaction::Transaction,
-    DArc, DLArc, DTRWrap, DeliverCode, DeliverToRead,
+    DArc, DLArc, DTRWrap, DeliverCode, DeliverToRead, RateLimit,
 };
 
 use core::{
@@ -41,6 +42,12 @@
     sync::atomic::{AtomicU32, Ordering},
 };
 
+static FREE_BUFFER_RATELIMIT: RateLimit = RateLimit::new(5000, 10);
+
+static NODE_FLAGS_RATELIMIT: RateLimit = RateLimit::new(5000, 1);
+
+static BUFFER_DEPTH_RATELIMIT: RateLimit = RateLimit::new(5000, 1);
+
 /// Stores the layout of the scatter-gather entries. This is used during the `translate_objects`
 /// call and is discarded when it returns.
 struct ScatterGatherState {
@@ -206,7 +213,7 @@ impl UnusedBufferSpace {
     /// into the buffer is returned.
     fn claim_next(&mut self, size: usize) -> Result<usize> {
         // We require every chunk to be aligned.
-        let size = ptr_align(size);
+        let size = ptr_align(size).ok_or(EINVAL)?;
         let new_offset = self.offset.checked_add(size).ok_or(EINVAL)?;
 
         if new_offset <= self.limit {
@@ -262,6 +269,10 @@ struct InnerThread {
 
     /// Extended error information for this thread.
     extended_error: ExtendedError,
+    /// The number of incoming transactions that this thread has picked up.
+    serviced_count: u64,
+    /// The sequence number of the last transaction delivered to this thread.
+    received_seq: u64,
 }
 
 const LOOPER_REGISTERED: u32 = 0x01;
@@ -289,6 +300,8 @@ fn next_err_id() -> u32 {
             work_list: List::new(),
             current_transaction: None,
             extended_error: ExtendedError::new(next_err_id(), BR_OK, 0),
+            serviced_count: 0,
+            received_seq: 0,
         })
     }
 
@@ -469,15 +482,16 @@ pub(crate) fn new(id: i32, process: Arc<Process>) -> Result<Arc<Self>> {
     }
 
     #[inline(never)]
-    pub(crate) fn debug_print(self: &Arc<Self>, m: &mut SeqFile) {
+    pub(crate) fn debug_print(self: &Arc<Self>, m: &mut SeqFile) -> Result<()> {
         let inner = self.inner.lock();
 
         seq_print!(
             m,
-            "  thread {}: l {:02x} need_return {}\n",
+            "  thread {}: l {:02x} need_return {} serviced {}\n",
             self.id,
             inner.looper_flags,
-            inner.looper_need_return
+            inner.looper_need_return,
+            inner.serviced_count,
         );
 
         let mut t_opt = inner.current_transaction.clone();
@@ -493,6 +507,11 @@ pub(crate) fn debug_print(self: &Arc<Self>, m: &mut SeqFile) {
                 t_opt = None;
             }
         }
+
+        for work in &inner.work_list {
+            work.debug_print(m, "    ", "    pending transaction ")?;
+        }
+        Ok(())
     }
 
     pub(crate) fn get_extended_error(&self, data: UserSlice) -> Result {
@@ -506,10 +525,49 @@ pub(crate) fn set_current_transaction(&self, transaction: DArc<Transaction>) {
         self.inner.lock().current_transaction = Some(transaction);
     }
 
+    /// Counts an incoming transaction picked up by this thread.
+    pub(crate) fn count_serviced(&self) {
+        self.inner.lock().serviced_count += 1;
+    }
+
+    pub(crate) fn serviced_count(&self) -> u64 {
+        self.inner.lock().serviced_count
+    }
+
+    pub(crate) fn set_received_seq(&self, seq: u64) {
+        self.inner.lock().received_seq = seq;
+    }
+
+    pub(crate) fn get_received_seq(&self, data: UserSlice) -> Result {
+        let seq = self.inner.lock().received_seq;
+        data.writer().write(&seq)
+    }
+
     pub(crate) fn has_current_transaction(&self) -> bool {
         self.inner.lock().current_transaction.is_some()
     }
 
+    /// Returns what the thread is currently doing, as one of the `BINDER_THREAD_STATE_*`
+    /// constants.
+    pub(crate) fn state(&self) -> u32 {
+        let inner = self.inner.lock();
+        if let Some(t) = &inner.current_transaction {
+            if core::ptr::eq(self, t.from.as_ref()) {
+                BINDER_THREAD_STATE_AWAITING_REPLY
+            } else {
+                BINDER_THREAD_STATE_SERVICING
+            }
+        } else if inner.looper_flags & LOOPER_WAITING_PROC != 0 {
+            BINDER_THREAD_STATE_IDLE
+        } else if inner.looper_flags & LOOPER_WAITING != 0 {
+            BINDER_THREAD_STATE_WAITING
+        } else if inner.looper_flags & LOOPER_POLL != 0 {
+            BINDER_THREAD_STATE_POLL
+        } else {
+            BINDER_THREAD_STATE_RUNNING
+        }
+    }
+
     /// Attempts to fetch a work item from the thread-local queue. The behaviour if the queue is
     /// empty depends on `wait`: if it is true, the function waits for some work to be queued (or a
     /// signal); otherwise it returns indicating that none is available.
@@ -749,6 +807,17 @@ fn translate_object(
                 let ptr = unsafe { obj.__bindgen_anon_1.binder } as _;
                 let cookie = obj.cookie as _;
                 let flags = obj.flags as _;
+                let allowed = self.process.ctx.allowed_node_flags.load(Ordering::Relaxed);
+                if flags & !allowed != 0 {
+                    if NODE_FLAGS_RATELIMIT.allow() {
+                        pr_warn!(
+                            "Binder object flags {:#x} not allowed in context {}",
+                            flags,
+                            &*self.process.ctx.name
+                        );
+                    }
+                    return Err(EPERM.into());
+                }
                 let node = self
                     .process
                     .as_arc_borrow()
@@ -834,6 +903,17 @@ fn translate_object(
                         size_of::<u64>(),
                     )?;
 
+                    let max_depth = self.process.ctx.max_buffer_depth.load(Ordering::Relaxed);
+                    if max_depth != 0 && info.num_ancestors >= max_depth as usize {
+                        if BUFFER_DEPTH_RATELIMIT.allow() {
+                            pr_warn!(
+                                "Buffer object nested deeper than the limit of {}.",
+                                max_depth
+                            );
+                        }
+                        return Err(EINVAL.into());
+                    }
+
                     sg_state.ancestors.truncate(info.num_ancestors);
                     sg_state.ancestors.try_push(sg_state_idx)?;
 
@@ -1024,15 +1104,32 @@ pub(crate) fn copy_transaction_data(
         };
 
         let data_size = trd.data_size.try_into().map_err(|_| EINVAL)?;
-        let aligned_data_size = ptr_align(data_size);
-        let offsets_size = trd.offsets_size.try_into().map_err(|_| EINVAL)?;
-        let aligned_offsets_size = ptr_align(offsets_size);
-        let buffers_size = tr.buffers_size.try_into().map_err(|_| EINVAL)?;
-        let aligned_buffers_size = ptr_align(buffers_size);
-        let aligned_secctx_size = secctx
-            .as_ref()
-            .map(|(_, ctx)| ptr_align(ctx.len()))
-            .unwrap_or(0);
+        let aligned_data_size = ptr_align(data_size).ok_or(EINVAL)?;
+        let offsets_size: usize = trd.offsets_size.try_into().map_err(|_| EINVAL)?;
+        let aligned_offsets_size = ptr_align(offsets_size).ok_or(EINVAL)?;
+        let buffers_size: usize = tr.buffers_size.try_into().map_err(|_| EINVAL)?;
+        let aligned_buffers_size = ptr_align(buffers_size).ok_or(EINVAL)?;
+        let aligned_secctx_size = match secctx.as_ref() {
+            Some((_, ctx)) => ptr_align(ctx.len()).ok_or(EINVAL)?,
+            None => 0,
+        };
+
+        if offsets_size % size_of::<usize>() != 0 {
+            pr_warn!(
+                "Got transaction with offsets_size {} not a multiple of {}.",
+                offsets_size,
+                size_of::<usize>()
+            );
+            return Err(EINVAL.into());
+        }
+        if buffers_size % size_of::<u64>() != 0 {
+            pr_warn!(
+                "Got transaction with buffers_size {} not a multiple of {}.",
+                buffers_size,
+                size_of::<u64>()
+            );
+            return Err(EINVAL.into());
+        }
 
         // This guarantees that at least `sizeof(usize)` bytes will be allocated.
         let len = usize::max(
@@ -1044,8 +1141,18 @@ pub(crate) fn copy_transaction_data(
             size_of::<usize>(),
         );
         let secctx_off = aligned_data_size + aligned_offsets_size + aligned_buffers_size;
-        let mut alloc = match to_process.buffer_alloc(len, is_oneway, self.process.task.pid()) {
+        // Transactions that are dropped when the buffer is full never wait for space.
+        let wait_ms = if trd.flags & TF_DROP_IF_FULL != 0 {
+            0
+        } else {
+            self.process.buffer_wait_ms()
+        };
+        let from_pid = self.process.task.pid();
+        let mut alloc = match to_process.buffer_alloc(len, is_oneway, from_pid, wait_ms) {
             Ok(alloc) => alloc,
+            Err(err) if is_oneway && err.is_out_of_space() && trd.flags & TF_DROP_IF_FULL != 0 => {
+                return Err(err);
+            }
             Err(err) => {
                 pr_warn!(
                     "Failed to allocate buffer. len:{}, is_oneway:{}",
@@ -1055,6 +1162,11 @@ pub(crate) fn copy_transaction_data(
                 return Err(err);
             }
         };
+        if trd.flags & TF_CLEAR_BUF != 0 {
+            // Set this before copying anything into the buffer, so that the buffer is also cleared
+            // if the copy fails partway through.
+            alloc.set_info_clear_on_drop();
+        }
 
         // SAFETY: This accesses a union field, but it's okay because the field's type is valid for
         // all bit-patterns.
@@ -1089,10 +1201,18 @@ pub(crate) fn copy_transaction_data(
                 .step_by(size_of::<usize>())
                 .enumerate()
             {
-                let offset = view.alloc.read(index_offset)?;
+                let offset: usize = view.alloc.read(index_offset)?;
 
-                if offset < end_of_previous_object {
-                    pr_warn!("Got transaction with invalid offset.");
+                if offset < end_of_previous_object
+                    || offset > data_size
+                    || offset % size_of::<u32>() != 0
+                {
+                    pr_warn!(
+                        "Got transaction with invalid offset {} (data_size {}, previous end {}).",
+                        offset,
+                        data_size,
+                        end_of_previous_object
+                    );
                     return Err(EINVAL.into());
                 }
 
@@ -1115,7 +1235,10 @@ pub(crate) fn copy_transaction_data(
                     allow_fds,
                     sg_state,
                 ) {
-                    Ok(()) => end_of_previous_object = offset + object.size(),
+                    Ok(()) => {
+                        // The object was read from `buffer_reader`, so it lies within `data_size`.
+                        end_of_previous_object = offset.checked_add(object.size()).ok_or(EINVAL)?;
+                    }
                     Err(err) => {
                         pr_warn!("Error while translating object.");
                         return Err(err);
@@ -1150,6 +1273,10 @@ pub(crate) fn copy_transaction_data(
             }
             **off_out = secctx_off;
         }
+
+        if to_process.buffer_checksums_enabled() {
+            alloc.set_info_checksum()?;
+        }
         Ok(alloc)
     }
 
@@ -1168,14 +1295,20 @@ fn unwind_transaction_stack(self: &Arc<Self>) {
         }
     }
 
+    /// Delivers a reply to the thread that started a transaction, and unwinds the transaction
+    /// stack if that thread is dead.
+    ///
+    /// Returns whether the thread is dead, in which case the reply was discarded.
     pub(crate) fn deliver_reply(
         &self,
         reply: Result<DLArc<Transaction>, u32>,
         transaction: &DArc<Transaction>,
-    ) {
-        if self.deliver_single_reply(reply, transaction) {
+    ) -> bool {
+        let dead = self.deliver_single_reply(reply, transaction);
+        if dead {
             transaction.from.unwind_transaction_stack();
         }
+        dead
     }
 
     /// Delivers a reply to the thread that started a transaction. The reply can either be a
@@ -1199,6 +1332,16 @@ fn deliver_single_reply(
             }
 
             if inner.is_dead {
+                drop(inner);
+                // The thread exited while waiting for the reply. The reply is dropped when this
+                // returns, which frees the buffer that was allocated for it.
+                if reply.is_ok() && log_enabled(BINDER_LOG_INFO) {
+                    pr_info!(
+                        "{}:{} dropping reply to exited thread\n",
+                        self.process.task.pid(),
+                        self.id
+                    );
+                }
                 return true;
             }
 
@@ -1245,18 +1388,36 @@ fn transaction<T>(self: &Arc<Self>, tr: &BinderTransactionDataSg, inner: T)
         T: FnOnce(&Arc<Self>, &BinderTransactionDataSg) -> BinderResult,
     {
         if let Err(err) = inner(self, tr) {
+            let param = if err.has_source() { err.as_errno() } else { 0 };
+            if err.has_source() {
+                let mut inner = self.inner.lock();
+                inner.extended_error.command = err.reply;
+                inner.extended_error.param = param;
+            }
+            // SAFETY: This accesses a union field, but it's okay because the field's type is valid
+            // for all bit-patterns.
+            let handle = unsafe { tr.transaction_data.target.handle };
+            self.process
+                .record_transaction_error(err.reply, param, handle, self.id);
             if err.should_pr_warn() {
-                let mut ee = self.inner.lock().extended_error;
-                ee.command = err.reply;
-                ee.param = err.as_errno();
                 pr_warn!(
                     "Transaction failed: {:?} my_pid:{}",
                     err,
                     self.process.task.pid_in_current_ns()
                 );
+            } else if log_enabled(BINDER_LOG_DEBUG) {
+                pr_info!(
+                    "{}:{} transaction to handle {} failed: {:?}\n",
+                    self.process.task.pid(),
+                    self.id,
+                    handle,
+                    err
+                );
             }
 
             self.push_return_work(err.reply);
+        } else {
+            self.process.record_transaction_sent(&tr.transaction_data);
         }
     }
 
@@ -1310,9 +1471,15 @@ fn reply_inner(self: &Arc<Self>, tr: &BinderTransactionDataSg) -> BinderResult {
             let process = orig.from.process.clone();
             let allow_fds = orig.flags & TF_ACCEPT_FDS != 0;
             let reply = Transaction::new_reply(self, process, tr, allow_fds)?;
+            reply.trace_sent();
+            let dead = orig.from.deliver_reply(Ok(reply), &orig);
+            if dead && self.process.dead_reply_errors() {
+                // The caller exited before the reply could be delivered. Report this instead of
+                // the completion.
+                return Ok(false);
+            }
             self.inner.lock().push_work(completion);
-            orig.from.deliver_reply(Ok(reply), &orig);
-            Ok(())
+            Ok(true)
         })()
         .map_err(|mut err| {
             // At this point we only return `BR_TRANSACTION_COMPLETE` to the caller, and we must let
@@ -1325,8 +1492,17 @@ fn reply_inner(self: &Arc<Self>, tr: &BinderTransactionDataSg) -> BinderResult {
             orig.from.deliver_reply(reply, &orig);
             err.reply = BR_TRANSACTION_COMPLETE;
             err
+        })
+        .and_then(|delivered| {
+            if delivered {
+                Ok(())
+            } else {
+                Err(BinderError::new_dead())
+            }
         });
 
+        self.process.record_reply(out.is_ok());
+
         // Restore the priority even on failure.
         self.restore_priority(&orig.saved_priority());
         out
@@ -1338,7 +1514,19 @@ fn oneway_transaction_inner(self: &Arc<Self>, tr: &BinderTransactionDataSg) -> B
         let handle = unsafe { tr.transaction_data.target.handle };
         let node_ref = self.process.get_transaction_node(handle)?;
         security::binder_transaction(&self.process.cred, &node_ref.node.owner.cred)?;
-        let transaction = Transaction::new(node_ref, None, self, tr)?;
+        let to = node_ref.node.owner.clone();
+        let drop_if_full = tr.transaction_data.flags & TF_DROP_IF_FULL != 0;
+        let transaction = match Transaction::new(node_ref, None, self, tr) {
+            Ok(transaction) => transaction,
+            Err(err) if drop_if_full && err.is_out_of_space() => {
+                // Pretend that the transaction was delivered, as requested by the sender.
+                to.count_dropped_oneway();
+                let completion = DTRWrap::arc_try_new(DeliverCode::new(BR_TRANSACTION_COMPLETE))?;
+                self.inner.lock().push_work(completion);
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
         let code = if self.process.is_oneway_spam_detection_enabled()
             && transaction.oneway_spam_detected
         {
@@ -1392,13 +1580,30 @@ fn write(self: &Arc<Self>, req: &mut BinderWriteRead) -> Result {
                     self.transaction(&tr, Self::reply_inner)
                 }
                 BC_FREE_BUFFER => {
-                    let buffer = self.process.buffer_get(reader.read()?);
-                    if let Some(buffer) = &buffer {
-                        if buffer.looper_need_return_on_free() {
-                            self.inner.lock().looper_need_return = true;
+                    let ptr: usize = reader.read()?;
+                    match self.process.buffer_get(ptr) {
+                        Ok(buffer) => {
+                            if buffer.looper_need_return_on_free() {
+                                self.inner.lock().looper_need_return = true;
+                            }
+                            drop(buffer);
                         }
+                        Err(_) if !FREE_BUFFER_RATELIMIT.allow() => {}
+                        Err(err) if err == ENOENT => pr_warn!(
+                            "{}:{} BC_FREE_BUFFER {:x} does not match an allocated buffer, \
+                             possibly freed twice\n",
+                            self.process.task.pid(),
+                            self.id,
+                            ptr
+                        ),
+                        Err(err) => pr_warn!(
+                            "{}:{} BC_FREE_BUFFER {:x} failed: {:?}\n",
+                            self.process.task.pid(),
+                            self.id,
+                            ptr,
+                            err
+                        ),
                     }
-                    drop(buffer);
                 }
                 BC_INCREFS => {
                     self.process
@@ -1582,6 +1787,15 @@ pub(crate) fn notify_if_poll_ready(&self, sync: bool) {
         }
     }
 
+    /// Wakes up the thread if it uses the poll interface, regardless of whether it could take work
+    /// from the process queue. Used for poll events that are not work items.
+    pub(crate) fn notify_if_polling(&self) {
+        let notify = self.inner.lock().looper_flags & LOOPER_POLL != 0;
+        if notify {
+            self.work_condvar.notify_one();
+        }
+    }
+
     pub(crate) fn release(self: &Arc<Self>) {
         self.inner.lock().is_dead = true;
 
@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: GPL-2.0
+
+// Copyright (C) 2024 Google LLC.
+
+//! Tracepoints for the binder driver. The events are defined in `rust_binder_events.h`.
+
+use core::ffi::{c_int, c_uint};
+use kernel::tracepoint::declare_trace;
+
+declare_trace! {
+    unsafe fn rust_binder_transaction(
+        reply: bool,
+        debug_id: c_int,
+        from_pid: c_int,
+        to_pid: c_int,
+        node_debug_id: c_int,
+        data_size: usize,
+        flags: c_uint,
+    );
+    unsafe fn rust_binder_transaction_received(debug_id: c_int);
+    unsafe fn rust_binder_buffer_release(pid: c_int, offset: usize, size: usize);
+}
+
+/// A transaction or reply was sent. `node_debug_id` is -1 for replies.
+#[inline]
+pub(crate) fn trace_transaction(
+    reply: bool,
+    debug_id: usize,
+    from_pid: i32,
+    to_pid: i32,
+    node_debug_id: Option<usize>,
+    data_size: usize,
+    flags: u32,
+) {
+    let node_debug_id = node_debug_id.map_or(-1, |id| id as c_int);
+    // SAFETY: Always safe to call.
+    unsafe {
+        rust_binder_transaction(
+            reply,
+            debug_id as c_int,
+            from_pid,
+            to_pid,
+            node_debug_id,
+            data_size,
+            flags,
+        )
+    }
+}
+
+/// A transaction or reply was delivered to a thread.
+#[inline]
+pub(crate) fn trace_transaction_received(debug_id: usize) {
+    // SAFETY: Always safe to call.
+    unsafe { rust_binder_transaction_received(debug_id as c_int) }
+}
+
+/// A buffer was freed.
+#[inline]
+pub(crate) fn trace_buffer_release(pid: i32, offset: usize, size: usize) {
+    // SAFETY: Always safe to call.
+    unsafe { rust_binder_buffer_release(pid, offset, size) }
+}
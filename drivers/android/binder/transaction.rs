@@ -19,26 +19,38 @@
     allocation::{Allocation, TranslatedFds},
     defs::*,
     error::{BinderError, BinderResult},
+    log_enabled,
     node::{Node, NodeRef},
     prio::{self, BinderPriority, PriorityState},
     process::{Process, ProcessInner},
     ptr_align,
     thread::{PushWorkRes, Thread},
-    DArc, DLArc, DTRWrap, DeliverToRead,
+    trace, DArc, DLArc, DTRWrap, DeliverToRead, RateLimit,
 };
 
+static TXN_STORM_RATELIMIT: RateLimit = RateLimit::new(5000, 1);
+
 #[pin_data(PinnedDrop)]
 pub(crate) struct Transaction {
     debug_id: usize,
     target_node: Option<DArc<Node>>,
     pub(crate) from_parent: Option<DArc<Transaction>>,
+    /// The number of transactions in the chain that ends with this one, including this one.
+    chain_len: u32,
     pub(crate) from: Arc<Thread>,
     pub(crate) to: Arc<Process>,
     #[pin]
     allocation: SpinLock<Option<Allocation>>,
     is_outstanding: AtomicBool,
+    /// Whether this transaction is counted in the `sync_txns` of `to`.
+    is_sync_counted: AtomicBool,
+    /// The sequence number of this transaction among those sent through the same handle of the
+    /// sender process. Zero if the handle has no reference entry, as for the context manager.
+    seq: u64,
     set_priority_called: AtomicBool,
     priority: BinderPriority,
+    /// The highest priority that the receiving thread may run at, if it is limited.
+    priority_cap: Option<BinderPriority>,
     #[pin]
     saved_priority: SpinLock<BinderPriority>,
     code: u32,
@@ -64,22 +76,56 @@ pub(crate) fn new(
         tr: &BinderTransactionDataSg,
     ) -> BinderResult<DLArc<Self>> {
         let trd = &tr.transaction_data;
+        if trd.flags & TF_ONE_WAY != 0 && node_ref.node.is_sync_only() {
+            pr_warn!("Oneway transaction to a node that only accepts synchronous transactions.");
+            return Err(EINVAL.into());
+        }
+        if !node_ref.node.accepts_uid(from.process.cred.euid()) {
+            return Err(EPERM.into());
+        }
         let allow_fds = node_ref.node.flags & FLAT_BINDER_FLAG_ACCEPTS_FDS != 0;
         let txn_security_ctx = node_ref.node.flags & FLAT_BINDER_FLAG_TXN_SECURITY_CTX != 0;
         let mut txn_security_ctx_off = if txn_security_ctx { Some(0) } else { None };
         let to = node_ref.node.owner.clone();
-        let mut alloc = match from.copy_transaction_data(
-            to.clone(),
-            tr,
-            allow_fds,
-            txn_security_ctx_off.as_mut(),
-        ) {
-            Ok(alloc) => alloc,
-            Err(err) => {
-                if !err.is_dead() {
-                    pr_warn!("Failure in copy_transaction_data: {:?}", err);
+        let chain_len = from_parent
+            .as_ref()
+            .map_or(1, |parent| parent.chain_len.saturating_add(1));
+        let max_chain_len = to.ctx.max_chain_len.load(Ordering::Relaxed);
+        if max_chain_len != 0 && chain_len > max_chain_len {
+            if log_enabled(BINDER_LOG_DEBUG) {
+                pr_info!(
+                    "{}: transaction chain of {} exceeds the limit of {}\n",
+                    from.process.task.pid(),
+                    chain_len,
+                    max_chain_len
+                );
+            }
+            return Err(ELOOP.into());
+        }
+        let drop_oldest = trd.flags & TF_ONE_WAY != 0 && node_ref.node.drops_oldest_oneway();
+        let mut alloc = loop {
+            match from.copy_transaction_data(
+                to.clone(),
+                tr,
+                allow_fds,
+                txn_security_ctx_off.as_mut(),
+            ) {
+                Ok(alloc) => break alloc,
+                Err(err) if drop_oldest && err.is_out_of_space() => {
+                    // The node prefers fresh oneway transactions over old ones. Drop the oldest
+                    // queued transaction to free up its buffer, and try again.
+                    match node_ref.node.take_oldest_oneway() {
+                        Some(oldest) => oldest.discard_queued_oneway(),
+                        None => return Err(err),
+                    }
+                }
+                Err(err) => {
+                    let dropped = err.is_out_of_space() && trd.flags & TF_DROP_IF_FULL != 0;
+                    if !err.is_dead() && !dropped {
+                        pr_warn!("Failure in copy_transaction_data: {:?}", err);
+                    }
+                    return Err(err);
                 }
-                return Err(err);
             }
         };
         let oneway_spam_detected = alloc.oneway_spam_detected;
@@ -90,27 +136,44 @@ pub(crate) fn new(
             }
             alloc.set_info_oneway_node(node_ref.node.clone());
         }
-        if trd.flags & TF_CLEAR_BUF != 0 {
-            alloc.set_info_clear_on_drop();
-        }
         let target_node = node_ref.node.clone();
         alloc.set_info_target_node(node_ref);
         let data_address = alloc.ptr;
 
-        let priority =
-            if (trd.flags & TF_ONE_WAY == 0) && prio::is_supported_policy(from.task.policy()) {
-                BinderPriority {
-                    sched_policy: from.task.policy(),
-                    prio: from.task.normal_prio(),
-                }
-            } else {
-                from.process.default_priority
-            };
+        let priority = if (trd.flags & (TF_ONE_WAY | TF_NO_PRIO_INHERIT) == 0)
+            && prio::is_supported_policy(from.task.policy())
+        {
+            BinderPriority {
+                sched_policy: from.task.policy(),
+                prio: from.task.normal_prio(),
+            }
+        } else {
+            from.process.default_priority
+        };
+
+        // With async priority caps, a oneway transaction never runs at a higher priority than the
+        // thread that sent it.
+        let priority_cap = if trd.flags & TF_ONE_WAY != 0
+            && to.ctx.cap_async_priority.load(Ordering::Relaxed)
+            && prio::is_supported_policy(from.task.policy())
+        {
+            Some(BinderPriority {
+                sched_policy: from.task.policy(),
+                prio: from.task.normal_prio(),
+            })
+        } else {
+            None
+        };
+
+        // SAFETY: This accesses a union field, but it's okay because the field's type is valid for
+        // all bit-patterns.
+        let seq = from.process.next_txn_seq(unsafe { trd.target.handle });
 
         Ok(DTRWrap::arc_pin_init(pin_init!(Transaction {
             debug_id: super::next_debug_id(),
             target_node: Some(target_node),
             from_parent,
+            chain_len,
             sender_euid: from.process.cred.euid(),
             from: from.clone(),
             to,
@@ -121,7 +184,10 @@ pub(crate) fn new(
             data_address,
             allocation <- kernel::new_spinlock!(Some(alloc), "Transaction::new"),
             is_outstanding: AtomicBool::new(false),
+            is_sync_counted: AtomicBool::new(false),
+            seq,
             priority,
+            priority_cap,
             saved_priority <- kernel::new_spinlock!(BinderPriority::default(), "Transaction::saved_priority"),
             set_priority_called: AtomicBool::new(false),
             txn_security_ctx_off,
@@ -137,7 +203,7 @@ pub(crate) fn new_reply(
         allow_fds: bool,
     ) -> BinderResult<DLArc<Self>> {
         let trd = &tr.transaction_data;
-        let mut alloc = match from.copy_transaction_data(to.clone(), tr, allow_fds, None) {
+        let alloc = match from.copy_transaction_data(to.clone(), tr, allow_fds, None) {
             Ok(alloc) => alloc,
             Err(err) => {
                 pr_warn!("Failure in copy_transaction_data: {:?}", err);
@@ -145,13 +211,11 @@ pub(crate) fn new_reply(
             }
         };
         let oneway_spam_detected = alloc.oneway_spam_detected;
-        if trd.flags & TF_CLEAR_BUF != 0 {
-            alloc.set_info_clear_on_drop();
-        }
         Ok(DTRWrap::arc_pin_init(pin_init!(Transaction {
             debug_id: super::next_debug_id(),
             target_node: None,
             from_parent: None,
+            chain_len: 0,
             sender_euid: from.process.task.euid(),
             from: from.clone(),
             to,
@@ -162,7 +226,10 @@ pub(crate) fn new_reply(
             data_address: alloc.ptr,
             allocation <- kernel::new_spinlock!(Some(alloc), "Transaction::new"),
             is_outstanding: AtomicBool::new(false),
+            is_sync_counted: AtomicBool::new(false),
+            seq: 0,
             priority: BinderPriority::default(),
+            priority_cap: None,
             saved_priority <- kernel::new_spinlock!(BinderPriority::default(), "Transaction::saved_priority"),
             set_priority_called: AtomicBool::new(false),
             txn_security_ctx_off: None,
@@ -197,6 +264,16 @@ pub(crate) fn saved_priority(&self) -> BinderPriority {
         *self.saved_priority.lock()
     }
 
+    /// Frees the buffer of a oneway transaction that was removed from the queue of its node
+    /// before delivery. Unlike dropping the allocation, this does not start the next queued
+    /// transaction, since the one currently in progress on the node is still running.
+    pub(crate) fn discard_queued_oneway(&self) {
+        let alloc = self.allocation.lock().take();
+        if let Some(mut alloc) = alloc {
+            alloc.clear_info_oneway_node();
+        }
+    }
+
     /// Determines if the transaction is stacked on top of the given transaction.
     pub(crate) fn is_stacked_on(&self, onext: &Option<DArc<Self>>) -> bool {
         match (&self.from_parent, onext) {
@@ -214,6 +291,9 @@ pub(crate) fn clone_next(&self) -> Option<DArc<Self>> {
     /// Searches in the transaction stack for a thread that belongs to the target process. This is
     /// useful when finding a target for a new transaction: if the node belongs to a process that
     /// is already part of the transaction stack, we reuse the thread.
+    ///
+    /// That thread is blocked waiting for a reply, so queuing on the process instead could
+    /// deadlock if no other thread of the process is free to handle the nested call.
     fn find_target_thread(&self) -> Option<Arc<Thread>> {
         let mut it = &self.from_parent;
         while let Some(transaction) = it {
@@ -258,20 +338,65 @@ fn drop_outstanding_txn(&self) {
         }
     }
 
+    /// Decrement `sync_txns` in `to` if this transaction was counted there.
+    fn drop_sync_txn(&self) {
+        // Like `drop_outstanding_txn`, this is called at most twice, and one of the calls is in the
+        // destructor.
+        if self.is_sync_counted.load(Ordering::Relaxed) {
+            self.is_sync_counted.store(false, Ordering::Relaxed);
+            self.to.drop_sync_txn();
+        }
+    }
+
+    /// Emits the trace event for sending this transaction or reply.
+    pub(crate) fn trace_sent(&self) {
+        trace::trace_transaction(
+            self.target_node.is_none(),
+            self.debug_id,
+            self.from.process.task.pid(),
+            self.to.task.pid(),
+            self.target_node.as_ref().map(|node| node.debug_id),
+            self.data_size,
+            self.flags,
+        );
+    }
+
     /// Submits the transaction to a work queue. Uses a thread if there is one in the transaction
     /// stack, otherwise uses the destination process.
     ///
     /// Not used for replies.
     pub(crate) fn submit(self: DLArc<Self>) -> BinderResult {
+        self.trace_sent();
+
+        let ctx = &self.to.ctx;
+        ctx.txn_count.fetch_add(1, Ordering::Relaxed);
+        ctx.txn_bytes.fetch_add(
+            (self.data_size + self.offsets_size) as u64,
+            Ordering::Relaxed,
+        );
+
         // Defined before `process_inner` so that the destructor runs after releasing the lock.
         let mut _t_outdated = None;
 
         let oneway = self.flags & TF_ONE_WAY != 0;
         let process = self.to.clone();
-        let mut process_inner = process.inner.lock();
+        let mut process_inner = process.lock_inner();
 
         self.set_outstanding(&mut process_inner);
 
+        if !process_inner.allows_delivery() {
+            return Err(EAGAIN.into());
+        }
+
+        let from_pid = self.from.process.task.pid();
+        if process_inner.record_sender(from_pid) && TXN_STORM_RATELIMIT.allow() {
+            pr_warn!(
+                "{}: transaction storm from {}\n",
+                process.task.pid(),
+                from_pid
+            );
+        }
+
         if oneway {
             if let Some(target_node) = self.target_node.clone() {
                 if process_inner.is_frozen {
@@ -306,10 +431,24 @@ pub(crate) fn submit(self: DLArc<Self>) -> BinderResult {
             return Err(BinderError::new_frozen());
         }
 
+        if !process_inner.try_add_sync_txn() {
+            return Err(EAGAIN.into());
+        }
+        self.is_sync_counted.store(true, Ordering::Relaxed);
+        if let Some(target_node) = &self.target_node {
+            target_node.count_transaction(&mut process_inner);
+        }
+
         let res = if let Some(thread) = self.find_target_thread() {
             match thread.push_work(self) {
-                PushWorkRes::Ok => Ok(()),
-                PushWorkRes::FailedDead(me) => Err((BinderError::new_dead(), me)),
+                PushWorkRes::Ok => {
+                    process_inner.record_delivery(true);
+                    Ok(())
+                }
+                PushWorkRes::FailedDead(me) => {
+                    process_inner.record_delivery(false);
+                    Err((BinderError::new_dead(), me))
+                }
             }
         } else {
             process_inner.push_work(self)
@@ -364,6 +503,8 @@ fn prepare_file_list(&self) -> Result<TranslatedFds> {
 
 impl DeliverToRead for Transaction {
     fn do_work(self: DArc<Self>, thread: &Thread, writer: &mut UserSliceWriter) -> Result<bool> {
+        trace::trace_transaction_received(self.debug_id);
+
         let send_failed_reply = ScopeGuard::new(|| {
             if self.target_node.is_some() && self.flags & TF_ONE_WAY == 0 {
                 let reply = Err(BR_FAILED_REPLY);
@@ -397,7 +538,9 @@ fn do_work(self: DArc<Self>, thread: &Thread, writer: &mut UserSliceWriter) -> R
         tr.data.ptr.buffer = self.data_address as _;
         tr.offsets_size = self.offsets_size as _;
         if tr.offsets_size > 0 {
-            tr.data.ptr.offsets = (self.data_address + ptr_align(self.data_size)) as _;
+            // The data size was checked when the buffer was allocated, so this can't overflow.
+            let aligned_data_size = ptr_align(self.data_size).unwrap_or(self.data_size);
+            tr.data.ptr.offsets = (self.data_address + aligned_data_size) as _;
         }
         tr.sender_euid = self.sender_euid.into_uid_in_current_ns();
         tr.sender_pid = 0;
@@ -437,6 +580,11 @@ fn do_work(self: DArc<Self>, thread: &Thread, writer: &mut UserSliceWriter) -> R
 
         self.drop_outstanding_txn();
 
+        if self.target_node.is_some() {
+            thread.set_received_seq(self.seq);
+            thread.count_serviced();
+        }
+
         // When this is not a reply and not a oneway transaction, update `current_transaction`. If
         // it's a reply, `current_transaction` has already been updated appropriately.
         if self.target_node.is_some() && tr_sec.transaction_data.flags & TF_ONE_WAY == 0 {
@@ -456,6 +604,7 @@ fn cancel(self: DArc<Self>) {
         }
 
         self.drop_outstanding_txn();
+        self.drop_sync_txn();
     }
 
     fn on_thread_selected(&self, to_thread: &Thread) {
@@ -505,6 +654,14 @@ fn on_thread_selected(&self, to_thread: &Thread) {
         }
         drop(prio_state);
 
+        if let Some(cap) = self.priority_cap {
+            desired = prio::cap_priority(desired, cap);
+        }
+
+        if self.flags & TF_KEEP_SCHED_POLICY != 0 {
+            desired = prio::keep_policy(*self.saved_priority.lock(), desired);
+        }
+
         to_thread.set_priority(&desired);
     }
 
@@ -512,6 +669,12 @@ fn should_sync_wakeup(&self) -> bool {
         self.flags & TF_ONE_WAY == 0
     }
 
+    fn is_high_priority(&self) -> bool {
+        self.target_node
+            .as_ref()
+            .map_or(false, |node| node.is_high_priority())
+    }
+
     fn debug_print(&self, m: &mut SeqFile, _prefix: &str, tprefix: &str) -> Result<()> {
         self.debug_print_inner(m, tprefix);
         Ok(())
@@ -522,5 +685,6 @@ fn debug_print(&self, m: &mut SeqFile, _prefix: &str, tprefix: &str) -> Result<(
 impl PinnedDrop for Transaction {
     fn drop(self: Pin<&mut Self>) {
         self.drop_outstanding_txn();
+        self.drop_sync_txn();
     }
 }
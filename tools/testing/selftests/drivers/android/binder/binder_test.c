@@ -0,0 +1,1761 @@
+// SPDX-License-Identifier: GPL-2.0
+
+/*
+ * Tests for the extensions of the Rust binder driver. Every test gets a new
+ * binder device in a private binderfs instance, so the settings of one test
+ * don't leak into the next.
+ */
+
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <pthread.h>
+#include <sched.h>
+#include <signal.h>
+#include <stddef.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+#include <unistd.h>
+#include <sys/mman.h>
+#include <sys/resource.h>
+#include <sys/stat.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <linux/android/binder.h>
+#include <linux/capability.h>
+
+#include "../../../kselftest_harness.h"
+#include "binder_util.h"
+
+/* The size of the oneway transactions used to fill the async space. */
+#define ONEWAY_SIZE	4096
+
+FIXTURE(binder) {
+	struct binder_fs fs;
+	char dev[PATH_MAX];
+};
+
+FIXTURE_SETUP(binder)
+{
+	int ret;
+
+	if (geteuid())
+		SKIP(return, "binder tests must be run as root");
+
+	ret = binder_fs_mount(&self->fs);
+	if (ret == -ENODEV)
+		SKIP(return, "binderfs is not available");
+	ASSERT_EQ(ret, 0);
+	ASSERT_EQ(binder_fs_add_device(&self->fs, "binder", self->dev, sizeof(self->dev)), 0);
+
+	if (!binder_has_extensions(self->dev)) {
+		binder_fs_umount(&self->fs);
+		SKIP(return, "the binder driver has no extensions");
+	}
+}
+
+FIXTURE_TEARDOWN(binder)
+{
+	binder_fs_umount(&self->fs);
+}
+
+/*
+ * Sends oneway transactions with increasing codes to the context manager, which
+ * doesn't read them, until one fails. Returns the number that were sent.
+ */
+static int fill_async_space(struct __test_metadata *_metadata, struct binder_conn *conn,
+			    int max, __u32 *result)
+{
+	static char data[ONEWAY_SIZE];
+	struct binder_txn txn = {
+		.flags = TF_ONE_WAY,
+		.data = data,
+		.data_size = sizeof(data),
+	};
+	int i;
+
+	for (i = 0; i < max; i++) {
+		txn.code = i;
+		EXPECT_EQ(binder_transact(conn, &txn, result), 0);
+		if (*result != BR_TRANSACTION_COMPLETE)
+			break;
+	}
+	return i;
+}
+
+TEST_F(binder, oneway_full_fails_new)
+{
+	struct binder_conn mgr, client;
+	__u32 result;
+	int sent;
+
+	ASSERT_EQ(binder_open(&mgr, self->dev, 64 * 1024, 0), 0);
+	ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+	ASSERT_EQ(binder_open(&client, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	/* Half of the mapping is available to oneway transactions. */
+	sent = fill_async_space(_metadata, &client, 64, &result);
+	EXPECT_GT(sent, 0);
+	EXPECT_LT(sent, 64);
+	EXPECT_EQ(result, BR_FAILED_REPLY);
+
+	binder_close(&client);
+	binder_close(&mgr);
+}
+
+TEST_F(binder, oneway_full_drops_oldest)
+{
+	struct binder_transaction_data tr;
+	struct binder_conn mgr, client;
+	int sent, received = 0, last = -1, ret;
+	__u32 result, cmd;
+
+	ASSERT_EQ(binder_open(&mgr, self->dev, 64 * 1024, O_NONBLOCK), 0);
+	ASSERT_EQ(binder_become_manager(&mgr, FLAT_BINDER_FLAG_ONEWAY_DROP_OLDEST), 0);
+	ASSERT_EQ(binder_open(&client, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	sent = fill_async_space(_metadata, &client, 64, &result);
+	ASSERT_EQ(sent, 64);
+
+	/*
+	 * The first transaction was handed to the process before the space ran
+	 * out, so it is kept. The others that are left are the newest ones.
+	 */
+	ASSERT_EQ(binder_enter_looper(&mgr), 0);
+	while (!(ret = binder_wait(&mgr, &cmd, &tr, sizeof(tr)))) {
+		ASSERT_EQ(cmd, BR_TRANSACTION);
+		if (!received) {
+			EXPECT_EQ(tr.code, 0);
+		} else {
+			EXPECT_GT((int)tr.code, last);
+		}
+		last = tr.code;
+		received++;
+		ASSERT_EQ(binder_free_buffer(&mgr, tr.data.ptr.buffer), 0);
+	}
+	EXPECT_EQ(ret, -EAGAIN);
+	EXPECT_EQ(last, sent - 1);
+	EXPECT_LT(received, sent);
+
+	binder_close(&client);
+	binder_close(&mgr);
+}
+
+static bool is_zero(const void *buf, size_t size)
+{
+	const char *p = buf;
+
+	while (size--) {
+		if (*p++)
+			return false;
+	}
+	return true;
+}
+
+/* The driver zeroes TF_CLEAR_BUF buffers when they are freed, and only those. */
+TEST_F(binder, clear_buf_on_free)
+{
+	struct binder_transaction_data tr;
+	struct binder_conn mgr, client;
+	char data[64];
+	struct binder_txn txn = {
+		.data = data,
+		.data_size = sizeof(data),
+	};
+	__u32 result, cmd;
+	const void *buf;
+	int clear;
+
+	memset(data, 0xaa, sizeof(data));
+	ASSERT_EQ(binder_open(&mgr, self->dev, BINDER_TEST_MAP_SIZE, O_NONBLOCK), 0);
+	ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+	ASSERT_EQ(binder_enter_looper(&mgr), 0);
+	ASSERT_EQ(binder_open(&client, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	for (clear = 1; clear >= 0; clear--) {
+		txn.flags = TF_ONE_WAY | (clear ? TF_CLEAR_BUF : 0);
+		ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+		ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+
+		ASSERT_EQ(binder_wait(&mgr, &cmd, &tr, sizeof(tr)), 0);
+		ASSERT_EQ(cmd, BR_TRANSACTION);
+		buf = (const void *)tr.data.ptr.buffer;
+		ASSERT_EQ(memcmp(buf, data, sizeof(data)), 0);
+		ASSERT_EQ(binder_free_buffer(&mgr, tr.data.ptr.buffer), 0);
+		EXPECT_EQ(is_zero(buf, sizeof(data)), clear);
+	}
+
+	binder_close(&client);
+	binder_close(&mgr);
+}
+
+/*
+ * The buffer is also zeroed when the transaction fails after part of it was
+ * copied. The data before an invalid object is copied before the object is
+ * rejected, and the failed buffer is the first one in the mapping.
+ */
+TEST_F(binder, clear_buf_on_failure)
+{
+	struct binder_conn mgr, client;
+	struct {
+		char pattern[64];
+		struct flat_binder_object obj;
+	} data = {
+		.obj.hdr.type = 0xdeadbeef,
+	};
+	binder_size_t offset = offsetof(typeof(data), obj);
+	struct binder_txn txn = {
+		.data = &data,
+		.data_size = sizeof(data),
+		.offsets = &offset,
+		.offsets_count = 1,
+	};
+	__u32 result;
+	int clear;
+
+	memset(data.pattern, 0xaa, sizeof(data.pattern));
+	ASSERT_EQ(binder_open(&mgr, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+	ASSERT_EQ(binder_open(&client, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	for (clear = 1; clear >= 0; clear--) {
+		txn.flags = TF_ONE_WAY | (clear ? TF_CLEAR_BUF : 0);
+		ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+		ASSERT_EQ(result, BR_FAILED_REPLY);
+		EXPECT_EQ(is_zero(mgr.map, sizeof(data.pattern)), clear);
+	}
+
+	binder_close(&client);
+	binder_close(&mgr);
+}
+
+#define REENTRANT_NODE_PTR	0x3000
+
+/* Calls back into the node that came with the transaction before replying. */
+static int reentrant_server(struct binder_conn *conn, void *arg)
+{
+	struct binder_transaction_data tr, reply;
+	const struct flat_binder_object *obj;
+	struct binder_txn txn = {};
+	__u32 cmd, result;
+
+	if (binder_wait(conn, &cmd, &tr, sizeof(tr)) || cmd != BR_TRANSACTION)
+		return -1;
+	if (tr.data_size != sizeof(*obj))
+		return -1;
+	obj = (const void *)tr.data.ptr.buffer;
+	if (obj->hdr.type != BINDER_TYPE_HANDLE)
+		return -1;
+
+	txn.handle = obj->handle;
+	txn.code = 2;
+	if (binder_call(conn, &txn, &reply, &result) || result != BR_REPLY)
+		return -1;
+	if (binder_free_buffer(conn, reply.data.ptr.buffer))
+		return -1;
+
+	if (binder_reply(conn, &tr, NULL, 0, &result) || result != BR_TRANSACTION_COMPLETE)
+		return -1;
+	return 0;
+}
+
+/*
+ * A calls B, which calls back into A. The nested call must be delivered to
+ * the thread of A that is waiting for the reply of B, since A has no other
+ * thread that could handle it.
+ */
+TEST_F(binder, reentrant_call_completes)
+{
+	struct flat_binder_object obj = {
+		.hdr.type = BINDER_TYPE_BINDER,
+		.binder = REENTRANT_NODE_PTR,
+	};
+	binder_size_t offset = 0;
+	struct binder_txn txn = {
+		.code = 1,
+		.data = &obj,
+		.data_size = sizeof(obj),
+		.offsets = &offset,
+		.offsets_count = 1,
+	};
+	struct binder_transaction_data tr;
+	struct binder_conn conn;
+	__u32 result, cmd;
+	pid_t pid;
+
+	pid = binder_start_server(self->dev, 0, reentrant_server, NULL);
+	ASSERT_GT(pid, 0);
+	ASSERT_EQ(binder_open(&conn, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	ASSERT_EQ(binder_transact(&conn, &txn, &result), 0);
+	ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+
+	/* This thread isn't a looper, so only a nested call can reach it. */
+	ASSERT_EQ(binder_wait(&conn, &cmd, &tr, sizeof(tr)), 0);
+	ASSERT_EQ(cmd, BR_TRANSACTION);
+	EXPECT_EQ(tr.code, 2);
+	EXPECT_EQ(tr.target.ptr, REENTRANT_NODE_PTR);
+	ASSERT_EQ(binder_reply(&conn, &tr, NULL, 0, &result), 0);
+	ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+
+	ASSERT_EQ(binder_wait(&conn, &cmd, &tr, sizeof(tr)), 0);
+	ASSERT_EQ(cmd, BR_REPLY);
+	EXPECT_EQ(binder_free_buffer(&conn, tr.data.ptr.buffer), 0);
+
+	EXPECT_EQ(binder_wait_server(pid), 0);
+	binder_close(&conn);
+}
+
+/* Replies to each transaction with the nice value it was handled at. */
+static int nice_server(struct binder_conn *conn, void *arg)
+{
+	int i, count = *(int *)arg, nice;
+	struct binder_transaction_data tr;
+	__u32 cmd, result;
+
+	for (i = 0; i < count; i++) {
+		if (binder_wait(conn, &cmd, &tr, sizeof(tr)) || cmd != BR_TRANSACTION)
+			return -1;
+		errno = 0;
+		nice = getpriority(PRIO_PROCESS, 0);
+		if (errno)
+			return -1;
+		if (binder_reply(conn, &tr, &nice, sizeof(nice), &result) ||
+		    result != BR_TRANSACTION_COMPLETE)
+			return -1;
+	}
+	return 0;
+}
+
+/*
+ * Without TF_NO_PRIO_INHERIT the server runs at the nice value of the caller.
+ * With it, the server runs at the default priority of the calling process,
+ * which is the nice value it had when it opened the device.
+ */
+TEST_F(binder, no_prio_inherit)
+{
+	struct binder_transaction_data reply;
+	struct binder_txn txn = {};
+	struct binder_conn conn;
+	int count = 2, nice;
+	__u32 result;
+	pid_t pid;
+
+	ASSERT_EQ(setpriority(PRIO_PROCESS, 0, 0), 0);
+	pid = binder_start_server(self->dev, 0, nice_server, &count);
+	ASSERT_GT(pid, 0);
+	ASSERT_EQ(binder_open(&conn, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(setpriority(PRIO_PROCESS, 0, -10), 0);
+
+	ASSERT_EQ(binder_call(&conn, &txn, &reply, &result), 0);
+	ASSERT_EQ(result, BR_REPLY);
+	ASSERT_EQ(reply.data_size, sizeof(nice));
+	memcpy(&nice, (const void *)reply.data.ptr.buffer, sizeof(nice));
+	EXPECT_EQ(nice, -10);
+	ASSERT_EQ(binder_free_buffer(&conn, reply.data.ptr.buffer), 0);
+
+	txn.flags = TF_NO_PRIO_INHERIT;
+	ASSERT_EQ(binder_call(&conn, &txn, &reply, &result), 0);
+	ASSERT_EQ(result, BR_REPLY);
+	ASSERT_EQ(reply.data_size, sizeof(nice));
+	memcpy(&nice, (const void *)reply.data.ptr.buffer, sizeof(nice));
+	EXPECT_EQ(nice, 0);
+	ASSERT_EQ(binder_free_buffer(&conn, reply.data.ptr.buffer), 0);
+
+	EXPECT_EQ(binder_wait_server(pid), 0);
+	binder_close(&conn);
+}
+
+/* Replies without data to the given number of transactions. */
+static int reply_server(struct binder_conn *conn, void *arg)
+{
+	int i, count = *(int *)arg;
+	struct binder_transaction_data tr;
+	__u32 cmd, result;
+
+	for (i = 0; i < count; i++) {
+		if (binder_wait(conn, &cmd, &tr, sizeof(tr)) || cmd != BR_TRANSACTION)
+			return -1;
+		if (binder_reply(conn, &tr, NULL, 0, &result) || result != BR_TRANSACTION_COMPLETE)
+			return -1;
+	}
+	return 0;
+}
+
+TEST_F(binder, sync_only_node)
+{
+	struct binder_transaction_data reply;
+	struct binder_txn txn = {};
+	struct binder_conn conn;
+	__u32 result;
+	int count = 1;
+	pid_t pid;
+
+	pid = binder_start_server(self->dev, FLAT_BINDER_FLAG_SYNC_ONLY, reply_server, &count);
+	ASSERT_GT(pid, 0);
+	ASSERT_EQ(binder_open(&conn, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	txn.flags = TF_ONE_WAY;
+	ASSERT_EQ(binder_transact(&conn, &txn, &result), 0);
+	EXPECT_EQ(result, BR_FAILED_REPLY);
+
+	txn.flags = 0;
+	ASSERT_EQ(binder_call(&conn, &txn, &reply, &result), 0);
+	ASSERT_EQ(result, BR_REPLY);
+	EXPECT_EQ(binder_free_buffer(&conn, reply.data.ptr.buffer), 0);
+
+	EXPECT_EQ(binder_wait_server(pid), 0);
+	binder_close(&conn);
+}
+
+TEST_F(binder, max_procs)
+{
+	struct binder_conn a, b, c;
+	__u32 max = 2;
+	int i, ret;
+
+	ASSERT_EQ(binder_open(&a, self->dev, 0, 0), 0);
+	ASSERT_EQ(ioctl(a.fd, BINDER_SET_MAX_PROCS, &max), 0);
+	ASSERT_EQ(binder_open(&b, self->dev, 0, 0), 0);
+	EXPECT_EQ(binder_open(&c, self->dev, 0, 0), -EBUSY);
+
+	/* Closing one of them makes room for another once it has been released. */
+	binder_close(&b);
+	for (i = 0; i < 100; i++) {
+		ret = binder_open(&c, self->dev, 0, 0);
+		if (ret != -EBUSY)
+			break;
+		usleep(10000);
+	}
+	ASSERT_EQ(ret, 0);
+
+	binder_close(&c);
+	binder_close(&a);
+}
+
+/*
+ * The mapping is rounded up to whole pages, so any size can be used. Each size
+ * gets its own device, since the context manager is released asynchronously.
+ */
+TEST_F(binder, mmap_unaligned_size)
+{
+	size_t page_size = sysconf(_SC_PAGESIZE);
+	size_t sizes[] = { 1, page_size + 100 };
+	struct binder_transaction_data tr;
+	struct binder_conn mgr, client;
+	char data[64] = "unaligned";
+	char name[32], dev[PATH_MAX];
+	struct binder_txn txn = {
+		.flags = TF_ONE_WAY,
+		.data = data,
+		.data_size = sizeof(data),
+	};
+	__u32 result, cmd;
+	size_t i;
+
+	for (i = 0; i < sizeof(sizes) / sizeof(sizes[0]); i++) {
+		snprintf(name, sizeof(name), "binder-%zu", sizes[i]);
+		ASSERT_EQ(binder_fs_add_device(&self->fs, name, dev, sizeof(dev)), 0);
+		ASSERT_EQ(binder_open(&mgr, dev, sizes[i], O_NONBLOCK), 0);
+		ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+		ASSERT_EQ(binder_enter_looper(&mgr), 0);
+		ASSERT_EQ(binder_open(&client, dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+		ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+		ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+		ASSERT_EQ(binder_wait(&mgr, &cmd, &tr, sizeof(tr)), 0);
+		ASSERT_EQ(cmd, BR_TRANSACTION);
+		EXPECT_EQ(memcmp((const void *)tr.data.ptr.buffer, data, sizeof(data)), 0);
+		EXPECT_EQ(binder_free_buffer(&mgr, tr.data.ptr.buffer), 0);
+
+		binder_close(&client);
+		binder_close(&mgr);
+	}
+}
+
+TEST_F(binder, mmap_rejected)
+{
+	struct binder_conn conn;
+	void *map;
+
+	ASSERT_EQ(binder_open(&conn, self->dev, 0, 0), 0);
+
+	map = mmap(NULL, BINDER_TEST_MAP_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE, conn.fd, 0);
+	EXPECT_EQ(map, MAP_FAILED);
+	EXPECT_EQ(errno, EPERM);
+
+	map = mmap(NULL, BINDER_TEST_MAP_SIZE, PROT_READ, MAP_PRIVATE, conn.fd, 0);
+	ASSERT_NE(map, MAP_FAILED);
+	conn.map = map;
+	conn.map_size = BINDER_TEST_MAP_SIZE;
+
+	/* Only one mapping is allowed per open. */
+	map = mmap(NULL, BINDER_TEST_MAP_SIZE, PROT_READ, MAP_PRIVATE, conn.fd, 0);
+	EXPECT_EQ(map, MAP_FAILED);
+	EXPECT_EQ(errno, EBUSY);
+
+	binder_close(&conn);
+}
+
+/* Blocks without reading any transaction until a byte arrives on the pipe. */
+static int blocked_server(struct binder_conn *conn, void *arg)
+{
+	char c;
+
+	return read(*(int *)arg, &c, 1) == 1 ? 0 : -1;
+}
+
+static int set_frozen(int fd, pid_t pid, bool enable)
+{
+	struct binder_freeze_info info = {
+		.pid = pid,
+		.enable = enable,
+	};
+
+	return ioctl(fd, BINDER_FREEZE, &info) ? -errno : 0;
+}
+
+TEST_F(binder, freeze_if_idle)
+{
+	struct binder_transaction_data reply;
+	struct binder_txn txn = {};
+	struct binder_conn conn;
+	__u32 result, pid;
+	int pipefd[2];
+	char c = 0;
+
+	ASSERT_EQ(pipe(pipefd), 0);
+	pid = binder_start_server(self->dev, 0, blocked_server, &pipefd[0]);
+	ASSERT_GT((pid_t)pid, 0);
+	ASSERT_EQ(binder_open(&conn, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	/* The server is idle, so it is frozen. */
+	ASSERT_EQ(ioctl(conn.fd, BINDER_FREEZE_IF_IDLE, &pid), 0);
+	ASSERT_EQ(binder_call(&conn, &txn, &reply, &result), 0);
+	EXPECT_EQ(result, BR_FROZEN_REPLY);
+	/* Freezing a frozen process succeeds. */
+	EXPECT_EQ(ioctl(conn.fd, BINDER_FREEZE_IF_IDLE, &pid), 0);
+	ASSERT_EQ(set_frozen(conn.fd, pid, false), 0);
+
+	/* An unread oneway transaction makes it busy, and it isn't frozen. */
+	txn.flags = TF_ONE_WAY;
+	ASSERT_EQ(binder_transact(&conn, &txn, &result), 0);
+	ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+	EXPECT_EQ(ioctl(conn.fd, BINDER_FREEZE_IF_IDLE, &pid), -1);
+	EXPECT_EQ(errno, EAGAIN);
+	ASSERT_EQ(binder_transact(&conn, &txn, &result), 0);
+	EXPECT_EQ(result, BR_TRANSACTION_COMPLETE);
+
+	ASSERT_EQ(write(pipefd[1], &c, 1), 1);
+	EXPECT_EQ(binder_wait_server(pid), 0);
+	binder_close(&conn);
+	close(pipefd[0]);
+	close(pipefd[1]);
+}
+
+/*
+ * A failed freeze only unfreezes the processes that it froze itself. All
+ * binder fds of this test share its pid, and BINDER_FREEZE visits them in the
+ * order of their devices and then of their opens. It freezes the manager of
+ * the first device and then fails on the busy manager of the second one.
+ */
+TEST_F(binder, freeze_if_idle_keeps_frozen)
+{
+	struct binder_conn frozen, busy, client;
+	struct binder_txn txn = {
+		.flags = TF_ONE_WAY,
+	};
+	__u32 result, pid = getpid();
+	char dev[PATH_MAX];
+
+	ASSERT_EQ(binder_fs_add_device(&self->fs, "binder-busy", dev, sizeof(dev)), 0);
+	ASSERT_EQ(binder_open(&frozen, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(binder_become_manager(&frozen, 0), 0);
+	ASSERT_EQ(binder_open(&busy, dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(binder_become_manager(&busy, 0), 0);
+	ASSERT_EQ(binder_open(&client, dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+	ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+	EXPECT_EQ(set_frozen(client.fd, pid, true), -EAGAIN);
+
+	EXPECT_EQ(ioctl(client.fd, BINDER_FREEZE_IF_IDLE, &pid), -1);
+	EXPECT_EQ(errno, EAGAIN);
+
+	/* A new client isn't frozen, and sees that the first manager still is. */
+	binder_close(&client);
+	ASSERT_EQ(binder_open(&client, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+	EXPECT_EQ(result, BR_TRANSACTION_PENDING_FROZEN);
+
+	binder_close(&client);
+	binder_close(&busy);
+	binder_close(&frozen);
+}
+
+/*
+ * A process that may start threads is asked to with BR_SPAWN_LOOPER when its
+ * looper picks up work. Each case uses its own device, since the default only
+ * applies to processes that open the device after it was set.
+ */
+TEST_F(binder, default_max_threads)
+{
+	static const struct {
+		__u32 default_max;
+		bool set_max;
+		bool spawn;
+	} cases[] = {
+		{ 0, false, false },
+		{ 1, false, true },
+		{ 1, true, false },
+	};
+	struct binder_conn admin, mgr, client;
+	struct binder_transaction_data tr;
+	struct binder_txn txn = {
+		.flags = TF_ONE_WAY,
+	};
+	char name[32], dev[PATH_MAX];
+	__u32 result, cmd, max = 0;
+	size_t i;
+
+	for (i = 0; i < sizeof(cases) / sizeof(cases[0]); i++) {
+		snprintf(name, sizeof(name), "binder-%zu", i);
+		ASSERT_EQ(binder_fs_add_device(&self->fs, name, dev, sizeof(dev)), 0);
+		ASSERT_EQ(binder_open(&admin, dev, 0, 0), 0);
+		ASSERT_EQ(ioctl(admin.fd, BINDER_SET_DEFAULT_MAX_THREADS, &cases[i].default_max), 0);
+
+		ASSERT_EQ(binder_open(&mgr, dev, BINDER_TEST_MAP_SIZE, 0), 0);
+		if (cases[i].set_max) {
+			ASSERT_EQ(ioctl(mgr.fd, BINDER_SET_MAX_THREADS, &max), 0);
+		}
+		ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+		ASSERT_EQ(binder_enter_looper(&mgr), 0);
+		ASSERT_EQ(binder_open(&client, dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+		ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+		ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+		ASSERT_EQ(binder_wait(&mgr, &cmd, &tr, sizeof(tr)), 0);
+		ASSERT_EQ(cmd, BR_TRANSACTION);
+		EXPECT_EQ(mgr.spawn_looper, cases[i].spawn);
+		EXPECT_EQ(binder_free_buffer(&mgr, tr.data.ptr.buffer), 0);
+
+		binder_close(&client);
+		binder_close(&mgr);
+		binder_close(&admin);
+	}
+}
+
+/* Sends a oneway transaction to the context manager with raw sizes. */
+static int send_raw(struct binder_conn *conn, const void *data, __u64 data_size,
+		    const binder_size_t *offsets, __u64 offsets_size, __u32 *result)
+{
+	struct binder_transaction_data tr = {
+		.flags = TF_ONE_WAY,
+		.data_size = data_size,
+		.offsets_size = offsets_size,
+		.data.ptr.buffer = (binder_uintptr_t)data,
+		.data.ptr.offsets = (binder_uintptr_t)offsets,
+	};
+	int ret;
+
+	ret = binder_write_cmd(conn, BC_TRANSACTION, &tr, sizeof(tr));
+	if (ret)
+		return ret;
+	return binder_wait(conn, result, NULL, 0);
+}
+
+TEST_F(binder, object_offsets)
+{
+	struct {
+		struct flat_binder_object obj;
+		__u32 pad;
+	} data = {
+		.obj.hdr.type = BINDER_TYPE_BINDER,
+		.obj.binder = 0x3000,
+	};
+	static const struct {
+		const char *name;
+		__u64 data_size;
+		binder_size_t offset;
+		__u64 offsets_size;
+		__u32 result;
+	} cases[] = {
+		{ "valid", sizeof(data), 0, sizeof(binder_size_t), BR_TRANSACTION_COMPLETE },
+		{ "past the end", sizeof(data), sizeof(data) + 8, sizeof(binder_size_t),
+		  BR_FAILED_REPLY },
+		{ "object crosses the end", sizeof(data), 8, sizeof(binder_size_t),
+		  BR_FAILED_REPLY },
+		{ "misaligned", sizeof(data), 2, sizeof(binder_size_t), BR_FAILED_REPLY },
+		{ "offset overflows", sizeof(data), ~(binder_size_t)3, sizeof(binder_size_t),
+		  BR_FAILED_REPLY },
+		{ "partial offset", sizeof(data), 0, sizeof(binder_size_t) - 1, BR_FAILED_REPLY },
+		{ "data size overflows", ~(__u64)0 - 2, 0, sizeof(binder_size_t), BR_FAILED_REPLY },
+		{ "offsets size overflows", sizeof(data), 0, ~(__u64)0 - 2, BR_FAILED_REPLY },
+	};
+	struct binder_conn mgr, client;
+	__u32 result;
+	size_t i;
+
+	ASSERT_EQ(binder_open(&mgr, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+	ASSERT_EQ(binder_open(&client, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	for (i = 0; i < sizeof(cases) / sizeof(cases[0]); i++) {
+		ASSERT_EQ(send_raw(&client, &data, cases[i].data_size, &cases[i].offset,
+				   cases[i].offsets_size, &result), 0);
+		EXPECT_EQ(result, cases[i].result) {
+			TH_LOG("%s", cases[i].name);
+		}
+	}
+
+	binder_close(&client);
+	binder_close(&mgr);
+}
+
+#define FILTER_UID	1000
+
+/* The filter uses the euid of the sending process when it opened the device. */
+TEST_F(binder, node_uid_filter)
+{
+	static const struct {
+		__u32 mode;
+		__u32 root_result;
+		__u32 user_result;
+	} cases[] = {
+		{ BINDER_UID_FILTER_DENY, BR_TRANSACTION_COMPLETE, BR_FAILED_REPLY },
+		{ BINDER_UID_FILTER_ALLOW, BR_FAILED_REPLY, BR_TRANSACTION_COMPLETE },
+		{ BINDER_UID_FILTER_NONE, BR_TRANSACTION_COMPLETE, BR_TRANSACTION_COMPLETE },
+	};
+	__u32 uid = FILTER_UID, result;
+	struct binder_node_uid_filter filter = {
+		.ptr = BINDER_TEST_MGR_PTR,
+		.cookie = BINDER_TEST_MGR_COOKIE,
+		.count = 1,
+		.uids = (__u64)(uintptr_t)&uid,
+	};
+	struct binder_conn mgr, root, user;
+	struct binder_txn txn = {
+		.flags = TF_ONE_WAY,
+	};
+	size_t i;
+
+	ASSERT_EQ(chmod(self->dev, 0666), 0);
+	ASSERT_EQ(binder_open(&mgr, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+	ASSERT_EQ(binder_open(&root, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(seteuid(FILTER_UID), 0);
+	ASSERT_EQ(binder_open(&user, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(seteuid(0), 0);
+
+	for (i = 0; i < sizeof(cases) / sizeof(cases[0]); i++) {
+		filter.mode = cases[i].mode;
+		ASSERT_EQ(ioctl(mgr.fd, BINDER_SET_NODE_UID_FILTER, &filter), 0);
+
+		ASSERT_EQ(binder_transact(&root, &txn, &result), 0);
+		EXPECT_EQ(result, cases[i].root_result);
+		ASSERT_EQ(binder_transact(&user, &txn, &result), 0);
+		EXPECT_EQ(result, cases[i].user_result);
+	}
+
+	/* Only nodes of the calling process can be configured. */
+	filter.mode = BINDER_UID_FILTER_DENY;
+	EXPECT_EQ(ioctl(root.fd, BINDER_SET_NODE_UID_FILTER, &filter), -1);
+	EXPECT_EQ(errno, ENOENT);
+
+	binder_close(&user);
+	binder_close(&root);
+	binder_close(&mgr);
+}
+
+/* Handles the next transaction of the manager and delivers its reply to @client. */
+static void reply_to_next(struct __test_metadata *_metadata, struct binder_conn *mgr,
+			  struct binder_conn *client)
+{
+	struct binder_transaction_data tr;
+	__u32 cmd, result;
+
+	ASSERT_EQ(binder_wait(mgr, &cmd, &tr, sizeof(tr)), 0);
+	ASSERT_EQ(cmd, BR_TRANSACTION);
+	ASSERT_EQ(binder_reply(mgr, &tr, NULL, 0, &result), 0);
+	ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+
+	ASSERT_EQ(binder_wait(client, &cmd, &tr, sizeof(tr)), 0);
+	ASSERT_EQ(cmd, BR_REPLY);
+	EXPECT_EQ(binder_free_buffer(client, tr.data.ptr.buffer), 0);
+}
+
+/*
+ * Each client is a separate process sending one synchronous transaction, so
+ * this thread can wait for the replies after the manager has sent them.
+ */
+TEST_F(binder, max_sync_txns)
+{
+	struct binder_conn mgr, clients[3];
+	struct binder_transaction_data tr;
+	struct binder_txn txn = {};
+	__u32 max = 2, result, cmd;
+	int i;
+
+	ASSERT_EQ(binder_open(&mgr, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+	ASSERT_EQ(binder_enter_looper(&mgr), 0);
+	ASSERT_EQ(ioctl(mgr.fd, BINDER_SET_MAX_SYNC_TXNS, &max), 0);
+	for (i = 0; i < 3; i++) {
+		ASSERT_EQ(binder_open(&clients[i], self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	}
+
+	ASSERT_EQ(binder_transact(&clients[0], &txn, &result), 0);
+	EXPECT_EQ(result, BR_TRANSACTION_COMPLETE);
+	ASSERT_EQ(binder_transact(&clients[1], &txn, &result), 0);
+	EXPECT_EQ(result, BR_TRANSACTION_COMPLETE);
+	ASSERT_EQ(binder_transact(&clients[2], &txn, &result), 0);
+	EXPECT_EQ(result, BR_FAILED_REPLY);
+
+	/* Oneway transactions aren't counted. */
+	txn.flags = TF_ONE_WAY;
+	ASSERT_EQ(binder_transact(&clients[2], &txn, &result), 0);
+	EXPECT_EQ(result, BR_TRANSACTION_COMPLETE);
+	txn.flags = 0;
+
+	/* Replying makes room for another one. */
+	reply_to_next(_metadata, &mgr, &clients[0]);
+	ASSERT_EQ(binder_transact(&clients[2], &txn, &result), 0);
+	EXPECT_EQ(result, BR_TRANSACTION_COMPLETE);
+
+	reply_to_next(_metadata, &mgr, &clients[1]);
+	ASSERT_EQ(binder_wait(&mgr, &cmd, &tr, sizeof(tr)), 0);
+	ASSERT_EQ(cmd, BR_TRANSACTION);
+	EXPECT_EQ(tr.flags & TF_ONE_WAY, TF_ONE_WAY);
+	ASSERT_EQ(binder_free_buffer(&mgr, tr.data.ptr.buffer), 0);
+	reply_to_next(_metadata, &mgr, &clients[2]);
+
+	for (i = 0; i < 3; i++)
+		binder_close(&clients[i]);
+	binder_close(&mgr);
+}
+
+TEST_F(binder, pause_delivery)
+{
+	struct binder_transaction_data tr;
+	struct binder_conn mgr, client;
+	struct binder_txn txn = {
+		.flags = TF_ONE_WAY,
+	};
+	__u32 paused = 1, result, cmd;
+	int i;
+
+	ASSERT_EQ(binder_open(&mgr, self->dev, BINDER_TEST_MAP_SIZE, O_NONBLOCK), 0);
+	ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+	ASSERT_EQ(binder_enter_looper(&mgr), 0);
+	ASSERT_EQ(binder_open(&client, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	ASSERT_EQ(ioctl(mgr.fd, BINDER_PAUSE_DELIVERY, &paused), 0);
+	for (i = 0; i < 3; i++) {
+		txn.code = i;
+		ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+		ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+	}
+	EXPECT_EQ(binder_wait(&mgr, &cmd, &tr, sizeof(tr)), -EAGAIN);
+
+	/* Everything that was queued is delivered in order after resuming. */
+	paused = 0;
+	ASSERT_EQ(ioctl(mgr.fd, BINDER_PAUSE_DELIVERY, &paused), 0);
+	for (i = 0; i < 3; i++) {
+		ASSERT_EQ(binder_wait(&mgr, &cmd, &tr, sizeof(tr)), 0);
+		ASSERT_EQ(cmd, BR_TRANSACTION);
+		EXPECT_EQ(tr.code, i);
+		ASSERT_EQ(binder_free_buffer(&mgr, tr.data.ptr.buffer), 0);
+	}
+	EXPECT_EQ(binder_wait(&mgr, &cmd, &tr, sizeof(tr)), -EAGAIN);
+
+	binder_close(&client);
+	binder_close(&mgr);
+}
+
+/* Handles transactions on a second looper thread until the fd goes away. */
+static void *release_race_looper(void *arg)
+{
+	struct binder_conn *conn = arg;
+	struct binder_transaction_data tr;
+	__u32 cmd, result;
+
+	if (binder_enter_looper(conn))
+		return NULL;
+	while (!binder_wait(conn, &cmd, &tr, sizeof(tr))) {
+		if (cmd != BR_TRANSACTION)
+			continue;
+		if (tr.flags & TF_ONE_WAY) {
+			if (binder_free_buffer(conn, tr.data.ptr.buffer))
+				break;
+		} else if (binder_reply(conn, &tr, NULL, 0, &result)) {
+			break;
+		}
+	}
+	return NULL;
+}
+
+/* Closes the binder fd after the given delay while the looper is busy. */
+static int release_race_server(struct binder_conn *conn, void *arg)
+{
+	pthread_t thread;
+
+	if (pthread_create(&thread, NULL, release_race_looper, conn))
+		return -1;
+	usleep(*(int *)arg);
+	close(conn->fd);
+	pthread_join(thread, NULL);
+	return 0;
+}
+
+/*
+ * Work that is queued when the server releases its fd must be cancelled, not
+ * handed to one of its threads. Every synchronous call must end with a reply
+ * or a dead reply, and the test would hang on a call that got neither.
+ */
+TEST_F_TIMEOUT(binder, release_races_delivery, 120)
+{
+	struct binder_transaction_data reply;
+	struct binder_txn txn = {};
+	struct binder_conn client;
+	char name[32], dev[PATH_MAX];
+	int i, delay;
+	__u32 result;
+	pid_t pid;
+
+	srand(getpid());
+	for (i = 0; i < 20; i++) {
+		snprintf(name, sizeof(name), "binder-%d", i);
+		ASSERT_EQ(binder_fs_add_device(&self->fs, name, dev, sizeof(dev)), 0);
+		delay = rand() % 5000;
+		pid = binder_start_server(dev, 0, release_race_server, &delay);
+		ASSERT_GT(pid, 0);
+		ASSERT_EQ(binder_open(&client, dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+		do {
+			txn.flags = TF_ONE_WAY;
+			ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+			if (result != BR_TRANSACTION_COMPLETE)
+				break;
+			txn.flags = 0;
+			ASSERT_EQ(binder_call(&client, &txn, &reply, &result), 0);
+			if (result == BR_REPLY) {
+				ASSERT_EQ(binder_free_buffer(&client, reply.data.ptr.buffer), 0);
+			}
+		} while (result == BR_REPLY);
+		EXPECT_TRUE(result == BR_DEAD_REPLY || result == BR_FAILED_REPLY);
+
+		EXPECT_EQ(binder_wait_server(pid), 0);
+		binder_close(&client);
+	}
+}
+
+TEST_F(binder, allowed_node_flags)
+{
+	static const struct {
+		__u32 flags;
+		__u32 result;
+	} cases[] = {
+		{ 0, BR_TRANSACTION_COMPLETE },
+		{ FLAT_BINDER_FLAG_ACCEPTS_FDS, BR_TRANSACTION_COMPLETE },
+		{ FLAT_BINDER_FLAG_TXN_SECURITY_CTX, BR_FAILED_REPLY },
+		{ FLAT_BINDER_FLAG_ACCEPTS_FDS | FLAT_BINDER_FLAG_TXN_SECURITY_CTX,
+		  BR_FAILED_REPLY },
+	};
+	struct flat_binder_object obj = {
+		.hdr.type = BINDER_TYPE_BINDER,
+	};
+	binder_size_t offset = 0;
+	struct binder_txn txn = {
+		.flags = TF_ONE_WAY,
+		.data = &obj,
+		.data_size = sizeof(obj),
+		.offsets = &offset,
+		.offsets_count = 1,
+	};
+	__u32 allowed = FLAT_BINDER_FLAG_ACCEPTS_FDS, result;
+	struct binder_conn mgr, client;
+	size_t i;
+
+	ASSERT_EQ(binder_open(&mgr, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+	ASSERT_EQ(ioctl(mgr.fd, BINDER_SET_ALLOWED_NODE_FLAGS, &allowed), 0);
+	ASSERT_EQ(binder_open(&client, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	for (i = 0; i < sizeof(cases) / sizeof(cases[0]); i++) {
+		/* Use a new node each time, as the flags are only set on creation. */
+		obj.binder = 0x3000 + i * 0x10;
+		obj.flags = cases[i].flags;
+		ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+		EXPECT_EQ(result, cases[i].result);
+	}
+
+	binder_close(&client);
+	binder_close(&mgr);
+}
+
+TEST_F(binder, mmap_requires_max_threads)
+{
+	struct binder_conn admin, conn;
+	__u32 enable, max = 1;
+
+	/* Disabled by default. */
+	ASSERT_EQ(binder_open(&conn, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	binder_close(&conn);
+
+	ASSERT_EQ(binder_open(&admin, self->dev, 0, 0), 0);
+	enable = 1;
+	ASSERT_EQ(ioctl(admin.fd, BINDER_SET_MMAP_REQUIRES_MAX_THREADS, &enable), 0);
+
+	EXPECT_EQ(binder_open(&conn, self->dev, BINDER_TEST_MAP_SIZE, 0), -EINVAL);
+
+	ASSERT_EQ(binder_open(&conn, self->dev, 0, 0), 0);
+	ASSERT_EQ(ioctl(conn.fd, BINDER_SET_MAX_THREADS, &max), 0);
+	conn.map = mmap(NULL, BINDER_TEST_MAP_SIZE, PROT_READ, MAP_PRIVATE, conn.fd, 0);
+	ASSERT_NE(conn.map, MAP_FAILED);
+	conn.map_size = BINDER_TEST_MAP_SIZE;
+	binder_close(&conn);
+
+	enable = 0;
+	ASSERT_EQ(ioctl(admin.fd, BINDER_SET_MMAP_REQUIRES_MAX_THREADS, &enable), 0);
+	EXPECT_EQ(binder_open(&conn, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	binder_close(&conn);
+
+	binder_close(&admin);
+}
+
+/* Replies to each transaction with the scheduler policy it was handled at. */
+static int policy_server(struct binder_conn *conn, void *arg)
+{
+	int i, count = *(int *)arg, policy;
+	struct binder_transaction_data tr;
+	__u32 cmd, result;
+
+	for (i = 0; i < count; i++) {
+		if (binder_wait(conn, &cmd, &tr, sizeof(tr)) || cmd != BR_TRANSACTION)
+			return -1;
+		policy = sched_getscheduler(0);
+		if (policy < 0)
+			return -1;
+		if (binder_reply(conn, &tr, &policy, sizeof(policy), &result) ||
+		    result != BR_TRANSACTION_COMPLETE)
+			return -1;
+	}
+	return 0;
+}
+
+/*
+ * A SCHED_FIFO server called from a SCHED_OTHER thread normally runs the call
+ * as SCHED_OTHER, and stays SCHED_FIFO with TF_KEEP_SCHED_POLICY.
+ */
+TEST_F(binder, keep_sched_policy)
+{
+	struct sched_param param = { .sched_priority = 1 };
+	struct binder_transaction_data reply;
+	struct binder_txn txn = {};
+	struct binder_conn conn;
+	int count = 2, policy;
+	__u32 result;
+	pid_t pid;
+
+	/* The server inherits the policy across fork. */
+	if (sched_setscheduler(0, SCHED_FIFO, &param))
+		SKIP(return, "SCHED_FIFO is not available");
+	pid = binder_start_server(self->dev, 0, policy_server, &count);
+	param.sched_priority = 0;
+	ASSERT_EQ(sched_setscheduler(0, SCHED_OTHER, &param), 0);
+	ASSERT_GT(pid, 0);
+	ASSERT_EQ(binder_open(&conn, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	ASSERT_EQ(binder_call(&conn, &txn, &reply, &result), 0);
+	ASSERT_EQ(result, BR_REPLY);
+	ASSERT_EQ(reply.data_size, sizeof(policy));
+	memcpy(&policy, (const void *)reply.data.ptr.buffer, sizeof(policy));
+	EXPECT_EQ(policy, SCHED_OTHER);
+	ASSERT_EQ(binder_free_buffer(&conn, reply.data.ptr.buffer), 0);
+
+	txn.flags = TF_KEEP_SCHED_POLICY;
+	ASSERT_EQ(binder_call(&conn, &txn, &reply, &result), 0);
+	ASSERT_EQ(result, BR_REPLY);
+	ASSERT_EQ(reply.data_size, sizeof(policy));
+	memcpy(&policy, (const void *)reply.data.ptr.buffer, sizeof(policy));
+	EXPECT_EQ(policy, SCHED_FIFO);
+	ASSERT_EQ(binder_free_buffer(&conn, reply.data.ptr.buffer), 0);
+
+	EXPECT_EQ(binder_wait_server(pid), 0);
+	binder_close(&conn);
+}
+
+/*
+ * The caller thread exits before the reply arrives. The reply is dropped and
+ * its buffer freed. With dead reply errors, the replying thread is told that
+ * the reply went nowhere. A reply that fails to allocate completes instead,
+ * and the replies are too large to fit twice in the mapping of the caller, so
+ * a leaked buffer shows up in the next iteration.
+ */
+TEST_F(binder, reply_to_exited_thread)
+{
+	static char data[BINDER_TEST_MAP_SIZE * 3 / 4];
+	struct binder_transaction_data tr;
+	struct binder_conn mgr, client;
+	struct binder_txn txn = {};
+	__u32 enable, result, cmd;
+	int i;
+
+	ASSERT_EQ(binder_open(&mgr, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+	ASSERT_EQ(binder_enter_looper(&mgr), 0);
+	ASSERT_EQ(binder_open(&client, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	for (i = 0; i < 3; i++) {
+		enable = i > 0;
+		ASSERT_EQ(ioctl(mgr.fd, BINDER_ENABLE_DEAD_REPLY_ERRORS, &enable), 0);
+
+		ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+		ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+		ASSERT_EQ(ioctl(client.fd, BINDER_THREAD_EXIT, 0), 0);
+
+		ASSERT_EQ(binder_wait(&mgr, &cmd, &tr, sizeof(tr)), 0);
+		ASSERT_EQ(cmd, BR_TRANSACTION);
+		ASSERT_EQ(binder_reply(&mgr, &tr, data, sizeof(data), &result), 0);
+		EXPECT_EQ(result, enable ? BR_DEAD_REPLY : BR_TRANSACTION_COMPLETE);
+	}
+
+	binder_close(&client);
+	binder_close(&mgr);
+}
+
+/* Sends a oneway transaction to the context manager carrying the node @ptr. */
+static int send_node(struct binder_conn *conn, binder_uintptr_t ptr, __u32 *result)
+{
+	struct flat_binder_object obj = {
+		.hdr.type = BINDER_TYPE_BINDER,
+		.binder = ptr,
+	};
+	binder_size_t offset = 0;
+	struct binder_txn txn = {
+		.flags = TF_ONE_WAY,
+		.data = &obj,
+		.data_size = sizeof(obj),
+		.offsets = &offset,
+		.offsets_count = 1,
+	};
+
+	return binder_transact(conn, &txn, result);
+}
+
+TEST_F(binder, node_creation_rate)
+{
+	struct binder_conn mgr, client;
+	__u32 rate = 3, result;
+	int i;
+
+	ASSERT_EQ(binder_open(&mgr, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+	ASSERT_EQ(binder_open(&client, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(ioctl(client.fd, BINDER_SET_NODE_CREATION_RATE, &rate), 0);
+
+	/* A burst of one second worth of nodes is allowed. */
+	for (i = 0; i < 3; i++) {
+		ASSERT_EQ(send_node(&client, 0x3000 + i * 0x10, &result), 0);
+		EXPECT_EQ(result, BR_TRANSACTION_COMPLETE);
+	}
+	ASSERT_EQ(send_node(&client, 0x3000 + i * 0x10, &result), 0);
+	EXPECT_EQ(result, BR_FAILED_REPLY);
+
+	/* Sending an existing node doesn't create one. */
+	ASSERT_EQ(send_node(&client, 0x3000, &result), 0);
+	EXPECT_EQ(result, BR_TRANSACTION_COMPLETE);
+
+	/* The limit refills over time. */
+	usleep(1100 * 1000);
+	ASSERT_EQ(send_node(&client, 0x3000 + i * 0x10, &result), 0);
+	EXPECT_EQ(result, BR_TRANSACTION_COMPLETE);
+
+	binder_close(&client);
+	binder_close(&mgr);
+}
+
+static long now_ms(void)
+{
+	struct timespec ts;
+
+	clock_gettime(CLOCK_MONOTONIC, &ts);
+	return ts.tv_sec * 1000 + ts.tv_nsec / 1000000;
+}
+
+/* Handles the queued oneway transaction of the manager after 300ms. */
+static void *delayed_reader(void *arg)
+{
+	struct binder_conn *conn = arg;
+	struct binder_transaction_data tr;
+	__u32 cmd;
+
+	usleep(300 * 1000);
+	if (binder_enter_looper(conn))
+		return NULL;
+	while (!binder_wait(conn, &cmd, &tr, sizeof(tr))) {
+		if (cmd == BR_TRANSACTION) {
+			binder_free_buffer(conn, tr.data.ptr.buffer);
+			break;
+		}
+	}
+	return NULL;
+}
+
+/*
+ * Closing a dup of the fd flushes the process without releasing it. With a
+ * drain timeout, the flush waits for the queued transaction to be read, or
+ * for the timeout if nothing reads it.
+ */
+TEST_F(binder, flush_drain_timeout)
+{
+	static const struct {
+		__u32 timeout;
+		bool read;
+		long min_ms, max_ms;
+	} cases[] = {
+		{ 0, true, 0, 250 },
+		{ 5000, true, 250, 5000 },
+		{ 500, false, 450, 5000 },
+	};
+	struct binder_txn txn = {
+		.flags = TF_ONE_WAY,
+	};
+	struct binder_conn mgr, client;
+	pthread_t thread;
+	char name[32], dev[PATH_MAX];
+	__u32 result;
+	size_t i;
+	long start;
+
+	for (i = 0; i < sizeof(cases) / sizeof(cases[0]); i++) {
+		snprintf(name, sizeof(name), "binder-flush-%zu", i);
+		ASSERT_EQ(binder_fs_add_device(&self->fs, name, dev, sizeof(dev)), 0);
+		ASSERT_EQ(binder_open(&mgr, dev, BINDER_TEST_MAP_SIZE, 0), 0);
+		ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+		ASSERT_EQ(ioctl(mgr.fd, BINDER_SET_FLUSH_DRAIN_TIMEOUT, &cases[i].timeout), 0);
+		ASSERT_EQ(binder_open(&client, dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+		ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+		ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+		if (cases[i].read) {
+			ASSERT_EQ(pthread_create(&thread, NULL, delayed_reader, &mgr), 0);
+		}
+
+		start = now_ms();
+		EXPECT_EQ(close(dup(mgr.fd)), 0);
+		EXPECT_GE(now_ms() - start, cases[i].min_ms);
+		EXPECT_LT(now_ms() - start, cases[i].max_ms);
+
+		if (cases[i].read) {
+			pthread_join(thread, NULL);
+		}
+		binder_close(&client);
+		binder_close(&mgr);
+	}
+}
+
+/* Removes CAP_SYS_ADMIN from the effective and permitted sets of the caller. */
+static int drop_sys_admin(void)
+{
+	struct __user_cap_header_struct hdr = {
+		.version = _LINUX_CAPABILITY_VERSION_3,
+	};
+	struct __user_cap_data_struct data[2];
+
+	if (syscall(SYS_capget, &hdr, data))
+		return -1;
+	data[CAP_TO_INDEX(CAP_SYS_ADMIN)].effective &= ~CAP_TO_MASK(CAP_SYS_ADMIN);
+	data[CAP_TO_INDEX(CAP_SYS_ADMIN)].permitted &= ~CAP_TO_MASK(CAP_SYS_ADMIN);
+	return syscall(SYS_capset, &hdr, data);
+}
+
+/*
+ * Freezes and unfreezes @pid, or the child itself if it is 0, from a child
+ * process without CAP_SYS_ADMIN. With @open_first, the child opens the device
+ * before dropping it. Returns the error of freezing, or 0 if it succeeded.
+ */
+static int freeze_unprivileged(const char *dev, pid_t pid, bool open_first)
+{
+	struct binder_conn conn;
+	pid_t child;
+	int status;
+
+	child = fork();
+	if (child < 0)
+		return -errno;
+	if (!child) {
+		if (open_first && binder_open(&conn, dev, 0, 0))
+			_exit(255);
+		if (drop_sys_admin())
+			_exit(255);
+		if (!open_first && binder_open(&conn, dev, 0, 0))
+			_exit(255);
+		if (!pid)
+			pid = getpid();
+		status = set_frozen(conn.fd, pid, true);
+		if (status)
+			_exit(-status);
+		_exit(set_frozen(conn.fd, pid, false) ? 255 : 0);
+	}
+
+	if (waitpid(child, &status, 0) != child || !WIFEXITED(status))
+		return -ECHILD;
+	return -WEXITSTATUS(status);
+}
+
+/*
+ * Without strict freezing, any process may freeze another. With it, only
+ * processes that opened the device with CAP_SYS_ADMIN may, while a process
+ * may still freeze itself.
+ */
+TEST_F(binder, strict_freeze)
+{
+	struct binder_conn conn;
+	__u32 enable, pid;
+
+	ASSERT_EQ(binder_open(&conn, self->dev, 0, 0), 0);
+	pid = getpid();
+
+	EXPECT_EQ(freeze_unprivileged(self->dev, pid, false), 0);
+
+	enable = 1;
+	ASSERT_EQ(ioctl(conn.fd, BINDER_SET_STRICT_FREEZE, &enable), 0);
+	EXPECT_EQ(freeze_unprivileged(self->dev, pid, false), -EPERM);
+	EXPECT_EQ(freeze_unprivileged(self->dev, pid, true), 0);
+	EXPECT_EQ(freeze_unprivileged(self->dev, 0, false), 0);
+
+	enable = 0;
+	EXPECT_EQ(ioctl(conn.fd, BINDER_SET_STRICT_FREEZE, &enable), 0);
+	binder_close(&conn);
+}
+
+struct oneway_prio_arg {
+	int count;
+	int fd;
+};
+
+/* Writes the policy and nice value each oneway transaction was handled at to a pipe. */
+static int oneway_prio_server(struct binder_conn *conn, void *arg)
+{
+	struct oneway_prio_arg *a = arg;
+	struct binder_transaction_data tr;
+	int i, prio[2];
+	__u32 cmd;
+
+	for (i = 0; i < a->count; i++) {
+		if (binder_wait(conn, &cmd, &tr, sizeof(tr)) || cmd != BR_TRANSACTION)
+			return -1;
+		prio[0] = sched_getscheduler(0);
+		errno = 0;
+		prio[1] = getpriority(PRIO_PROCESS, 0);
+		if (prio[0] < 0 || errno)
+			return -1;
+		if (binder_free_buffer(conn, tr.data.ptr.buffer))
+			return -1;
+		if (write(a->fd, prio, sizeof(prio)) != sizeof(prio))
+			return -1;
+	}
+	return 0;
+}
+
+/*
+ * A oneway transaction runs at the default priority of the sending process,
+ * which is SCHED_OTHER at nice 0 here. With the cap, the sender running
+ * SCHED_BATCH at nice 10 lowers the level to nice 10 but doesn't change the
+ * policy.
+ */
+TEST_F(binder, async_priority_cap)
+{
+	struct sched_param param = {};
+	struct oneway_prio_arg arg = {
+		.count = 2,
+	};
+	struct binder_txn txn = {
+		.flags = TF_ONE_WAY,
+	};
+	struct binder_conn conn;
+	__u32 enable, result;
+	int pipefd[2], prio[2];
+	pid_t pid;
+
+	ASSERT_EQ(setpriority(PRIO_PROCESS, 0, 0), 0);
+	ASSERT_EQ(pipe(pipefd), 0);
+	arg.fd = pipefd[1];
+	pid = binder_start_server(self->dev, 0, oneway_prio_server, &arg);
+	ASSERT_GT(pid, 0);
+	close(pipefd[1]);
+	ASSERT_EQ(binder_open(&conn, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(sched_setscheduler(0, SCHED_BATCH, &param), 0);
+	ASSERT_EQ(setpriority(PRIO_PROCESS, 0, 10), 0);
+
+	enable = 1;
+	ASSERT_EQ(ioctl(conn.fd, BINDER_SET_ASYNC_PRIORITY_CAP, &enable), 0);
+	ASSERT_EQ(binder_transact(&conn, &txn, &result), 0);
+	ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+	ASSERT_EQ(read(pipefd[0], prio, sizeof(prio)), sizeof(prio));
+	EXPECT_EQ(prio[0], SCHED_OTHER);
+	EXPECT_EQ(prio[1], 10);
+
+	enable = 0;
+	ASSERT_EQ(ioctl(conn.fd, BINDER_SET_ASYNC_PRIORITY_CAP, &enable), 0);
+	ASSERT_EQ(binder_transact(&conn, &txn, &result), 0);
+	ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+	ASSERT_EQ(read(pipefd[0], prio, sizeof(prio)), sizeof(prio));
+	EXPECT_EQ(prio[0], SCHED_OTHER);
+	EXPECT_EQ(prio[1], 0);
+
+	EXPECT_EQ(sched_setscheduler(0, SCHED_OTHER, &param), 0);
+	EXPECT_EQ(setpriority(PRIO_PROCESS, 0, 0), 0);
+	close(pipefd[0]);
+	EXPECT_EQ(binder_wait_server(pid), 0);
+	binder_close(&conn);
+}
+
+/*
+ * The manager watches three nodes of another process that then exits. With a
+ * limit of two delivered deaths, the first one delivered is treated as
+ * acknowledged when the third is, so clearing it completes right away, while
+ * clearing the second one waits for BC_DEAD_BINDER_DONE.
+ */
+TEST_F(binder, max_delivered_deaths)
+{
+	struct flat_binder_object objs[3] = {};
+	binder_size_t offsets[3];
+	struct binder_txn txn = {
+		.flags = TF_ONE_WAY,
+		.data = objs,
+		.data_size = sizeof(objs),
+		.offsets = offsets,
+		.offsets_count = 3,
+	};
+	struct binder_transaction_data tr;
+	struct binder_handle_cookie death;
+	binder_uintptr_t cookies[3];
+	struct binder_conn mgr, owner;
+	__u32 max = 2, result, cmd;
+	const struct flat_binder_object *received;
+	int i, j;
+
+	ASSERT_EQ(binder_open(&mgr, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+	ASSERT_EQ(binder_enter_looper(&mgr), 0);
+	ASSERT_EQ(ioctl(mgr.fd, BINDER_SET_MAX_DELIVERED_DEATHS, &max), 0);
+	ASSERT_EQ(binder_open(&owner, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	for (i = 0; i < 3; i++) {
+		objs[i].hdr.type = BINDER_TYPE_BINDER;
+		objs[i].binder = 0x3000 + i * 0x10;
+		offsets[i] = i * sizeof(objs[i]);
+	}
+	ASSERT_EQ(binder_transact(&owner, &txn, &result), 0);
+	ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+
+	/* Keep the buffer, which holds the references to the nodes. */
+	ASSERT_EQ(binder_wait(&mgr, &cmd, &tr, sizeof(tr)), 0);
+	ASSERT_EQ(cmd, BR_TRANSACTION);
+	received = (const struct flat_binder_object *)tr.data.ptr.buffer;
+	for (i = 0; i < 3; i++) {
+		ASSERT_EQ(received[i].hdr.type, BINDER_TYPE_HANDLE);
+		death.handle = received[i].handle;
+		death.cookie = i;
+		ASSERT_EQ(binder_write_cmd(&mgr, BC_REQUEST_DEATH_NOTIFICATION,
+					   &death, sizeof(death)), 0);
+	}
+
+	binder_close(&owner);
+	for (i = 0; i < 3; i++) {
+		ASSERT_EQ(binder_wait(&mgr, &cmd, &cookies[i], sizeof(cookies[i])), 0);
+		ASSERT_EQ(cmd, BR_DEAD_BINDER);
+	}
+
+	ASSERT_EQ(fcntl(mgr.fd, F_SETFL, O_NONBLOCK), 0);
+	for (i = 0; i < 2; i++) {
+		j = cookies[i];
+		death.handle = received[j].handle;
+		death.cookie = j;
+		ASSERT_EQ(binder_write_cmd(&mgr, BC_CLEAR_DEATH_NOTIFICATION,
+					   &death, sizeof(death)), 0);
+		if (i == 1) {
+			EXPECT_EQ(binder_wait(&mgr, &cmd, NULL, 0), -EAGAIN);
+			ASSERT_EQ(binder_write_cmd(&mgr, BC_DEAD_BINDER_DONE,
+						   &death.cookie, sizeof(death.cookie)), 0);
+		}
+		ASSERT_EQ(binder_wait(&mgr, &cmd, &death.cookie, sizeof(death.cookie)), 0);
+		EXPECT_EQ(cmd, BR_CLEAR_DEATH_NOTIFICATION_DONE);
+		EXPECT_EQ(death.cookie, j);
+	}
+
+	EXPECT_EQ(binder_free_buffer(&mgr, tr.data.ptr.buffer), 0);
+	binder_close(&mgr);
+}
+
+static void ignore_signal(int sig)
+{
+}
+
+/*
+ * Once the async space of the manager is full, a new oneway transaction waits
+ * for space until the timeout, until a buffer is freed, or until a signal.
+ */
+TEST_F(binder, buffer_wait_timeout)
+{
+	struct sigaction sa = {
+		.sa_handler = ignore_signal,
+	};
+	struct binder_txn txn = {
+		.flags = TF_ONE_WAY,
+	};
+	static char data[ONEWAY_SIZE];
+	struct binder_extended_error ee;
+	struct binder_conn mgr, client;
+	pthread_t thread;
+	__u32 timeout, result;
+	long start;
+
+	ASSERT_EQ(binder_open(&mgr, self->dev, 64 * 1024, 0), 0);
+	ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+	ASSERT_EQ(binder_open(&client, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	fill_async_space(_metadata, &client, 64, &result);
+	ASSERT_EQ(result, BR_FAILED_REPLY);
+	txn.data = data;
+	txn.data_size = sizeof(data);
+
+	timeout = 300;
+	ASSERT_EQ(ioctl(client.fd, BINDER_SET_BUFFER_WAIT_TIMEOUT, &timeout), 0);
+	start = now_ms();
+	ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+	EXPECT_EQ(result, BR_FAILED_REPLY);
+	EXPECT_GE(now_ms() - start, 250);
+	ASSERT_EQ(ioctl(client.fd, BINDER_GET_EXTENDED_ERROR, &ee), 0);
+	EXPECT_EQ(ee.command, BR_FAILED_REPLY);
+	EXPECT_EQ(ee.param, -EAGAIN);
+
+	timeout = 5000;
+	ASSERT_EQ(ioctl(client.fd, BINDER_SET_BUFFER_WAIT_TIMEOUT, &timeout), 0);
+	ASSERT_EQ(pthread_create(&thread, NULL, delayed_reader, &mgr), 0);
+	start = now_ms();
+	ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+	EXPECT_EQ(result, BR_TRANSACTION_COMPLETE);
+	EXPECT_GE(now_ms() - start, 250);
+	EXPECT_LT(now_ms() - start, 5000);
+	pthread_join(thread, NULL);
+
+	/* The space is full again, and without SA_RESTART the signal ends the wait. */
+	ASSERT_EQ(sigaction(SIGALRM, &sa, NULL), 0);
+	alarm(1);
+	start = now_ms();
+	ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+	EXPECT_EQ(result, BR_FAILED_REPLY);
+	EXPECT_LT(now_ms() - start, 5000);
+	ASSERT_EQ(ioctl(client.fd, BINDER_GET_EXTENDED_ERROR, &ee), 0);
+	EXPECT_NE(ee.param, -EAGAIN);
+	signal(SIGALRM, SIG_DFL);
+
+	binder_close(&client);
+	binder_close(&mgr);
+}
+
+/* The manager can query its own node with handle 0. */
+TEST_F(binder, manager_node_info)
+{
+	struct binder_node_info_for_ref info = {};
+	struct binder_txn txn = {
+		.flags = TF_ONE_WAY,
+	};
+	struct binder_conn mgr, client;
+	__u32 strong, weak, result;
+
+	ASSERT_EQ(binder_open(&mgr, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+	ASSERT_EQ(binder_open(&client, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	ASSERT_EQ(ioctl(mgr.fd, BINDER_GET_NODE_INFO_FOR_REF, &info), 0);
+	EXPECT_GT(info.strong_count + info.weak_count, 0);
+	strong = info.strong_count;
+	weak = info.weak_count;
+
+	/* Sending to handle 0 takes a strong reference from the client. */
+	ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+	ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+	memset(&info, 0, sizeof(info));
+	ASSERT_EQ(ioctl(mgr.fd, BINDER_GET_NODE_INFO_FOR_REF, &info), 0);
+	EXPECT_GT(info.strong_count, strong);
+	EXPECT_GE(info.weak_count, weak);
+
+	/* Only the manager may query node info. */
+	memset(&info, 0, sizeof(info));
+	EXPECT_EQ(ioctl(client.fd, BINDER_GET_NODE_INFO_FOR_REF, &info), -1);
+	EXPECT_EQ(errno, EPERM);
+
+	binder_close(&client);
+	binder_close(&mgr);
+}
+
+/*
+ * This thread builds a chain of synchronous transactions by sending each one
+ * from the process that is handling the previous one: from the client to the
+ * manager, back to a node of the client, and to the manager again.
+ */
+TEST_F(binder, max_chain_len)
+{
+	struct flat_binder_object obj = {
+		.hdr.type = BINDER_TYPE_BINDER,
+		.binder = 0x3000,
+	};
+	binder_size_t offset = 0;
+	struct binder_txn txn = {
+		.data = &obj,
+		.data_size = sizeof(obj),
+		.offsets = &offset,
+		.offsets_count = 1,
+	};
+	struct binder_transaction_data tr[3], reply;
+	struct binder_extended_error ee;
+	struct binder_conn mgr, client;
+	__u32 max = 2, result, cmd;
+
+	ASSERT_EQ(binder_open(&mgr, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+	ASSERT_EQ(binder_enter_looper(&mgr), 0);
+	ASSERT_EQ(ioctl(mgr.fd, BINDER_SET_MAX_CHAIN_LEN, &max), 0);
+	ASSERT_EQ(binder_open(&client, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+	ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+	ASSERT_EQ(binder_wait(&mgr, &cmd, &tr[0], sizeof(tr[0])), 0);
+	ASSERT_EQ(cmd, BR_TRANSACTION);
+
+	memset(&txn, 0, sizeof(txn));
+	txn.handle = ((const struct flat_binder_object *)tr[0].data.ptr.buffer)->handle;
+	ASSERT_EQ(binder_transact(&mgr, &txn, &result), 0);
+	ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+	ASSERT_EQ(binder_wait(&client, &cmd, &tr[1], sizeof(tr[1])), 0);
+	ASSERT_EQ(cmd, BR_TRANSACTION);
+
+	/* A third transaction in the chain is over the limit. */
+	txn.handle = 0;
+	ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+	EXPECT_EQ(result, BR_FAILED_REPLY);
+	ASSERT_EQ(ioctl(client.fd, BINDER_GET_EXTENDED_ERROR, &ee), 0);
+	EXPECT_EQ(ee.param, -ELOOP);
+
+	max = 3;
+	ASSERT_EQ(ioctl(mgr.fd, BINDER_SET_MAX_CHAIN_LEN, &max), 0);
+	ASSERT_EQ(binder_transact(&client, &txn, &result), 0);
+	ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+	ASSERT_EQ(binder_wait(&mgr, &cmd, &tr[2], sizeof(tr[2])), 0);
+	ASSERT_EQ(cmd, BR_TRANSACTION);
+
+	/* Unwind the chain. */
+	ASSERT_EQ(binder_reply(&mgr, &tr[2], NULL, 0, &result), 0);
+	ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+	ASSERT_EQ(binder_wait(&client, &cmd, &reply, sizeof(reply)), 0);
+	ASSERT_EQ(cmd, BR_REPLY);
+	EXPECT_EQ(binder_free_buffer(&client, reply.data.ptr.buffer), 0);
+
+	ASSERT_EQ(binder_reply(&client, &tr[1], NULL, 0, &result), 0);
+	ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+	ASSERT_EQ(binder_wait(&mgr, &cmd, &reply, sizeof(reply)), 0);
+	ASSERT_EQ(cmd, BR_REPLY);
+	EXPECT_EQ(binder_free_buffer(&mgr, reply.data.ptr.buffer), 0);
+
+	ASSERT_EQ(binder_reply(&mgr, &tr[0], NULL, 0, &result), 0);
+	ASSERT_EQ(result, BR_TRANSACTION_COMPLETE);
+	ASSERT_EQ(binder_wait(&client, &cmd, &reply, sizeof(reply)), 0);
+	ASSERT_EQ(cmd, BR_REPLY);
+	EXPECT_EQ(binder_free_buffer(&client, reply.data.ptr.buffer), 0);
+
+	binder_close(&client);
+	binder_close(&mgr);
+}
+
+TEST_F(binder, context_memory_limit)
+{
+	__u64 limit = BINDER_TEST_MAP_SIZE + 4096;
+	struct binder_conn a, b, c;
+	void *map;
+
+	ASSERT_EQ(binder_open(&a, self->dev, 0, 0), 0);
+	ASSERT_EQ(ioctl(a.fd, BINDER_SET_CONTEXT_MEMORY_LIMIT, &limit), 0);
+	binder_close(&a);
+	ASSERT_EQ(binder_open(&a, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	/* Another process can't map more than what is left. */
+	EXPECT_EQ(binder_open(&c, self->dev, 8192, 0), -ENOMEM);
+
+	/* A failed mmap gives its share of the limit back. */
+	map = mmap(NULL, 4096, PROT_READ, MAP_PRIVATE, a.fd, 0);
+	EXPECT_EQ(map, MAP_FAILED);
+	EXPECT_EQ(errno, EBUSY);
+	ASSERT_EQ(binder_open(&b, self->dev, 4096, 0), 0);
+
+	binder_close(&b);
+	binder_close(&a);
+}
+
+/*
+ * Sends a oneway transaction with @depth buffer objects, where each buffer
+ * after the first is pointed to by the previous one.
+ */
+static int send_nested_buffers(struct binder_conn *conn, int depth, __u32 *result)
+{
+	static __u64 buffers[4];
+	struct binder_buffer_object objs[4] = {};
+	binder_size_t offsets[4];
+	struct binder_txn txn = {
+		.flags = TF_ONE_WAY,
+		.data = objs,
+		.data_size = depth * sizeof(objs[0]),
+		.offsets = offsets,
+		.offsets_count = depth,
+		.buffers_size = depth * sizeof(buffers[0]),
+	};
+	int i;
+
+	for (i = 0; i < depth; i++) {
+		objs[i].hdr.type = BINDER_TYPE_PTR;
+		objs[i].buffer = (binder_uintptr_t)&buffers[i];
+		objs[i].length = sizeof(buffers[i]);
+		if (i) {
+			objs[i].flags = BINDER_BUFFER_FLAG_HAS_PARENT;
+			objs[i].parent = i - 1;
+			objs[i].parent_offset = 0;
+			buffers[i - 1] = (__u64)(uintptr_t)&buffers[i];
+		}
+		offsets[i] = i * sizeof(objs[i]);
+	}
+	return binder_transact(conn, &txn, result);
+}
+
+TEST_F(binder, max_buffer_depth)
+{
+	struct binder_extended_error ee;
+	struct binder_conn mgr, client;
+	__u32 max = 2, result;
+
+	ASSERT_EQ(binder_open(&mgr, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+	ASSERT_EQ(binder_become_manager(&mgr, 0), 0);
+	ASSERT_EQ(ioctl(mgr.fd, BINDER_SET_MAX_BUFFER_DEPTH, &max), 0);
+	ASSERT_EQ(binder_open(&client, self->dev, BINDER_TEST_MAP_SIZE, 0), 0);
+
+	ASSERT_EQ(send_nested_buffers(&client, 2, &result), 0);
+	EXPECT_EQ(result, BR_TRANSACTION_COMPLETE);
+
+	ASSERT_EQ(send_nested_buffers(&client, 3, &result), 0);
+	EXPECT_EQ(result, BR_FAILED_REPLY);
+	ASSERT_EQ(ioctl(client.fd, BINDER_GET_EXTENDED_ERROR, &ee), 0);
+	EXPECT_EQ(ee.param, -EINVAL);
+
+	max = 0;
+	ASSERT_EQ(ioctl(mgr.fd, BINDER_SET_MAX_BUFFER_DEPTH, &max), 0);
+	ASSERT_EQ(send_nested_buffers(&client, 4, &result), 0);
+	EXPECT_EQ(result, BR_TRANSACTION_COMPLETE);
+
+	binder_close(&client);
+	binder_close(&mgr);
+}
+
+TEST_HARNESS_MAIN
@@ -0,0 +1,385 @@
+// SPDX-License-Identifier: GPL-2.0
+
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <sched.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+#include <sys/ioctl.h>
+#include <sys/mman.h>
+#include <sys/mount.h>
+#include <sys/wait.h>
+#include <linux/android/binder.h>
+#include <linux/android/binderfs.h>
+
+#include "binder_util.h"
+
+/* Any payload that can follow a BR_* command. */
+union binder_br_payload {
+	struct binder_transaction_data tr;
+	struct binder_transaction_data_secctx secctx;
+	struct binder_ptr_cookie ref;
+	binder_uintptr_t cookie;
+	__s32 error;
+};
+
+int binder_fs_mount(struct binder_fs *fs)
+{
+	int ret;
+
+	fs->root[0] = '\0';
+	if (unshare(CLONE_NEWNS))
+		return -errno;
+	if (mount(NULL, "/", NULL, MS_REC | MS_PRIVATE, NULL))
+		return -errno;
+
+	strcpy(fs->root, "/tmp/binder_test_XXXXXX");
+	if (!mkdtemp(fs->root)) {
+		fs->root[0] = '\0';
+		return -errno;
+	}
+	if (mount(NULL, fs->root, "binder", 0, NULL)) {
+		ret = -errno;
+		rmdir(fs->root);
+		fs->root[0] = '\0';
+		return ret;
+	}
+	return 0;
+}
+
+void binder_fs_umount(struct binder_fs *fs)
+{
+	if (!fs->root[0])
+		return;
+	umount2(fs->root, MNT_DETACH);
+	rmdir(fs->root);
+	fs->root[0] = '\0';
+}
+
+int binder_fs_add_device(struct binder_fs *fs, const char *name, char *path, size_t size)
+{
+	struct binderfs_device device = {};
+	char control[sizeof(fs->root) + 16];
+	int fd, ret = 0;
+
+	snprintf(control, sizeof(control), "%s/binder-control", fs->root);
+	fd = open(control, O_RDONLY | O_CLOEXEC);
+	if (fd < 0)
+		return -errno;
+
+	strncpy(device.name, name, sizeof(device.name) - 1);
+	if (ioctl(fd, BINDER_CTL_ADD, &device) < 0)
+		ret = -errno;
+	close(fd);
+
+	if (!ret)
+		snprintf(path, size, "%s/%s", fs->root, name);
+	return ret;
+}
+
+/*
+ * The C driver rejects the ioctls of the extensions, so probe for one that
+ * doesn't change anything when given zero.
+ */
+bool binder_has_extensions(const char *path)
+{
+	__u32 threshold = 0;
+	bool ret;
+	int fd;
+
+	fd = open(path, O_RDWR | O_CLOEXEC);
+	if (fd < 0)
+		return false;
+	ret = ioctl(fd, BINDER_SET_BUFFER_PRESSURE_THRESHOLD, &threshold) == 0;
+	close(fd);
+	return ret;
+}
+
+int binder_open(struct binder_conn *conn, const char *path, size_t map_size, int flags)
+{
+	int ret;
+
+	memset(conn, 0, sizeof(*conn));
+	conn->fd = open(path, O_RDWR | O_CLOEXEC | flags);
+	if (conn->fd < 0)
+		return -errno;
+	if (!map_size)
+		return 0;
+
+	conn->map = mmap(NULL, map_size, PROT_READ, MAP_PRIVATE, conn->fd, 0);
+	if (conn->map == MAP_FAILED) {
+		ret = -errno;
+		close(conn->fd);
+		conn->fd = -1;
+		conn->map = NULL;
+		return ret;
+	}
+	conn->map_size = map_size;
+	return 0;
+}
+
+void binder_close(struct binder_conn *conn)
+{
+	if (conn->map)
+		munmap(conn->map, conn->map_size);
+	if (conn->fd >= 0)
+		close(conn->fd);
+	conn->map = NULL;
+	conn->fd = -1;
+}
+
+int binder_become_manager(struct binder_conn *conn, __u32 flags)
+{
+	struct flat_binder_object obj = {
+		.hdr.type = BINDER_TYPE_BINDER,
+		.flags = flags,
+		.binder = BINDER_TEST_MGR_PTR,
+		.cookie = BINDER_TEST_MGR_COOKIE,
+	};
+
+	if (ioctl(conn->fd, BINDER_SET_CONTEXT_MGR_EXT, &obj) < 0)
+		return -errno;
+	return 0;
+}
+
+int binder_write(struct binder_conn *conn, const void *data, size_t size)
+{
+	struct binder_write_read bwr = {
+		.write_size = size,
+		.write_buffer = (binder_uintptr_t)data,
+	};
+
+	if (ioctl(conn->fd, BINDER_WRITE_READ, &bwr) < 0)
+		return -errno;
+	return bwr.write_consumed == size ? 0 : -EIO;
+}
+
+int binder_write_cmd(struct binder_conn *conn, __u32 cmd, const void *arg, size_t size)
+{
+	char buf[sizeof(cmd) + sizeof(struct binder_transaction_data_sg)];
+
+	if (size > sizeof(buf) - sizeof(cmd))
+		return -EINVAL;
+	memcpy(buf, &cmd, sizeof(cmd));
+	if (size)
+		memcpy(buf + sizeof(cmd), arg, size);
+	return binder_write(conn, buf, sizeof(cmd) + size);
+}
+
+int binder_enter_looper(struct binder_conn *conn)
+{
+	return binder_write_cmd(conn, BC_ENTER_LOOPER, NULL, 0);
+}
+
+int binder_free_buffer(struct binder_conn *conn, binder_uintptr_t buffer)
+{
+	return binder_write_cmd(conn, BC_FREE_BUFFER, &buffer, sizeof(buffer));
+}
+
+static int binder_fill(struct binder_conn *conn)
+{
+	struct binder_write_read bwr = {
+		.read_size = sizeof(conn->rbuf),
+		.read_buffer = (binder_uintptr_t)conn->rbuf,
+	};
+
+	while (!bwr.read_consumed) {
+		if (ioctl(conn->fd, BINDER_WRITE_READ, &bwr) < 0)
+			return -errno;
+	}
+	conn->rpos = 0;
+	conn->rlen = bwr.read_consumed;
+	return 0;
+}
+
+/* Returns the next command and its payload, reading from the driver if needed. */
+int binder_read_cmd(struct binder_conn *conn, __u32 *cmd, void *payload, size_t size)
+{
+	size_t len;
+	int ret;
+
+	if (conn->rpos == conn->rlen) {
+		ret = binder_fill(conn);
+		if (ret)
+			return ret;
+	}
+	if (conn->rlen - conn->rpos < sizeof(*cmd))
+		return -EPROTO;
+	memcpy(cmd, conn->rbuf + conn->rpos, sizeof(*cmd));
+	conn->rpos += sizeof(*cmd);
+
+	len = _IOC_SIZE(*cmd);
+	if (conn->rlen - conn->rpos < len)
+		return -EPROTO;
+	if (payload)
+		memcpy(payload, conn->rbuf + conn->rpos, len < size ? len : size);
+	conn->rpos += len;
+	return 0;
+}
+
+/*
+ * Returns the next command that the test may care about. Reference count
+ * requests are acknowledged, and BR_SPAWN_LOOPER is only recorded.
+ */
+int binder_wait(struct binder_conn *conn, __u32 *cmd, void *payload, size_t size)
+{
+	union binder_br_payload p;
+	size_t len;
+	int ret;
+
+	for (;;) {
+		ret = binder_read_cmd(conn, cmd, &p, sizeof(p));
+		if (ret)
+			return ret;
+
+		switch (*cmd) {
+		case BR_NOOP:
+		case BR_RELEASE:
+		case BR_DECREFS:
+			break;
+		case BR_SPAWN_LOOPER:
+			conn->spawn_looper = true;
+			break;
+		case BR_INCREFS:
+			ret = binder_write_cmd(conn, BC_INCREFS_DONE, &p.ref, sizeof(p.ref));
+			if (ret)
+				return ret;
+			break;
+		case BR_ACQUIRE:
+			ret = binder_write_cmd(conn, BC_ACQUIRE_DONE, &p.ref, sizeof(p.ref));
+			if (ret)
+				return ret;
+			break;
+		default:
+			len = _IOC_SIZE(*cmd);
+			if (payload)
+				memcpy(payload, &p, len < size ? len : size);
+			return 0;
+		}
+	}
+}
+
+int binder_send(struct binder_conn *conn, const struct binder_txn *txn, bool reply)
+{
+	struct binder_transaction_data_sg sg = {};
+	struct binder_transaction_data *tr = &sg.transaction_data;
+
+	tr->target.handle = txn->handle;
+	tr->code = txn->code;
+	tr->flags = txn->flags;
+	tr->data_size = txn->data_size;
+	tr->offsets_size = txn->offsets_count * sizeof(binder_size_t);
+	tr->data.ptr.buffer = (binder_uintptr_t)txn->data;
+	tr->data.ptr.offsets = (binder_uintptr_t)txn->offsets;
+	sg.buffers_size = txn->buffers_size;
+
+	if (txn->buffers_size)
+		return binder_write_cmd(conn, reply ? BC_REPLY_SG : BC_TRANSACTION_SG,
+					&sg, sizeof(sg));
+	return binder_write_cmd(conn, reply ? BC_REPLY : BC_TRANSACTION, tr, sizeof(*tr));
+}
+
+/*
+ * Sends a transaction and returns in @result whether it was sent, which is
+ * BR_TRANSACTION_COMPLETE on success.
+ */
+int binder_transact(struct binder_conn *conn, const struct binder_txn *txn, __u32 *result)
+{
+	int ret;
+
+	ret = binder_send(conn, txn, false);
+	if (ret)
+		return ret;
+	return binder_wait(conn, result, NULL, 0);
+}
+
+/*
+ * Sends a synchronous transaction and waits for the reply, which is
+ * BR_REPLY in @result on success. The caller must free the reply buffer.
+ */
+int binder_call(struct binder_conn *conn, const struct binder_txn *txn,
+		struct binder_transaction_data *reply, __u32 *result)
+{
+	int ret;
+
+	ret = binder_transact(conn, txn, result);
+	if (ret || *result != BR_TRANSACTION_COMPLETE)
+		return ret;
+	return binder_wait(conn, result, reply, sizeof(*reply));
+}
+
+/* Frees the buffer of @tr and replies to it with @data. */
+int binder_reply(struct binder_conn *conn, const struct binder_transaction_data *tr,
+		 const void *data, size_t size, __u32 *result)
+{
+	struct binder_txn txn = {
+		.data = data,
+		.data_size = size,
+	};
+	int ret;
+
+	ret = binder_free_buffer(conn, tr->data.ptr.buffer);
+	if (ret)
+		return ret;
+	ret = binder_send(conn, &txn, true);
+	if (ret)
+		return ret;
+	return binder_wait(conn, result, NULL, 0);
+}
+
+/*
+ * Forks a child that becomes the context manager of @path with a looper
+ * thread, and then runs @fn. Returns once the manager is registered.
+ */
+pid_t binder_start_server(const char *path, __u32 node_flags, binder_server_fn fn, void *arg)
+{
+	struct binder_conn conn;
+	int pipefd[2];
+	char c = 0;
+	pid_t pid;
+
+	if (pipe2(pipefd, O_CLOEXEC))
+		return -1;
+
+	pid = fork();
+	if (pid < 0) {
+		close(pipefd[0]);
+		close(pipefd[1]);
+		return -1;
+	}
+	if (!pid) {
+		close(pipefd[0]);
+		if (binder_open(&conn, path, BINDER_TEST_MAP_SIZE, 0) ||
+		    binder_become_manager(&conn, node_flags) ||
+		    binder_enter_looper(&conn))
+			_exit(EXIT_FAILURE);
+		if (write(pipefd[1], &c, 1) != 1)
+			_exit(EXIT_FAILURE);
+		close(pipefd[1]);
+		_exit(fn(&conn, arg) ? EXIT_FAILURE : EXIT_SUCCESS);
+	}
+
+	close(pipefd[1]);
+	if (read(pipefd[0], &c, 1) != 1) {
+		close(pipefd[0]);
+		waitpid(pid, NULL, 0);
+		return -1;
+	}
+	close(pipefd[0]);
+	return pid;
+}
+
+/* Returns the exit status of the server, which is zero if @fn succeeded. */
+int binder_wait_server(pid_t pid)
+{
+	int status;
+
+	if (waitpid(pid, &status, 0) != pid)
+		return -errno;
+	if (!WIFEXITED(status))
+		return -ECHILD;
+	return WEXITSTATUS(status);
+}
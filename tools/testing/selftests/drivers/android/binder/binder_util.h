@@ -0,0 +1,123 @@
+/* SPDX-License-Identifier: GPL-2.0 */
+
+#ifndef __SELFTEST_BINDER_UTIL_H
+#define __SELFTEST_BINDER_UTIL_H
+
+#include <limits.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <sys/ioctl.h>
+#include <sys/types.h>
+#include <linux/android/binder.h>
+
+/*
+ * Extensions of the Rust binder driver. They are not part of the uapi
+ * headers, so they are duplicated here and must be kept in sync with
+ * drivers/android/binder/defs.rs.
+ */
+#define FLAT_BINDER_FLAG_ONEWAY_DROP_OLDEST	0x01000000
+#define FLAT_BINDER_FLAG_SYNC_ONLY		0x02000000
+
+#define TF_NO_PRIO_INHERIT	0x01000000
+#define TF_KEEP_SCHED_POLICY	0x04000000
+
+#define BINDER_SET_BUFFER_PRESSURE_THRESHOLD	_IOW('b', 64, __u32)
+#define BINDER_SET_MAX_PROCS			_IOW('b', 77, __u32)
+#define BINDER_FREEZE_IF_IDLE			_IOW('b', 79, __u32)
+#define BINDER_SET_STRICT_FREEZE		_IOW('b', 80, __u32)
+#define BINDER_SET_DEFAULT_MAX_THREADS		_IOW('b', 83, __u32)
+
+#define BINDER_UID_FILTER_NONE	0
+#define BINDER_UID_FILTER_ALLOW	1
+#define BINDER_UID_FILTER_DENY	2
+
+struct binder_node_uid_filter {
+	__u64 ptr;
+	__u64 cookie;
+	__u32 mode;
+	__u32 count;
+	__u64 uids;
+};
+
+#define BINDER_SET_NODE_UID_FILTER	_IOW('b', 86, struct binder_node_uid_filter)
+#define BINDER_SET_MAX_SYNC_TXNS	_IOW('b', 88, __u32)
+#define BINDER_PAUSE_DELIVERY		_IOW('b', 89, __u32)
+#define BINDER_SET_ALLOWED_NODE_FLAGS	_IOW('b', 99, __u32)
+#define BINDER_SET_MMAP_REQUIRES_MAX_THREADS	_IOW('b', 100, __u32)
+#define BINDER_ENABLE_DEAD_REPLY_ERRORS		_IOW('b', 102, __u32)
+#define BINDER_SET_NODE_CREATION_RATE		_IOW('b', 107, __u32)
+#define BINDER_SET_FLUSH_DRAIN_TIMEOUT		_IOW('b', 109, __u32)
+#define BINDER_SET_ASYNC_PRIORITY_CAP		_IOW('b', 111, __u32)
+#define BINDER_SET_MAX_DELIVERED_DEATHS		_IOW('b', 114, __u32)
+#define BINDER_SET_BUFFER_WAIT_TIMEOUT		_IOW('b', 116, __u32)
+#define BINDER_SET_MAX_CHAIN_LEN		_IOW('b', 121, __u32)
+#define BINDER_SET_CONTEXT_MEMORY_LIMIT		_IOW('b', 128, __u64)
+#define BINDER_SET_MAX_BUFFER_DEPTH		_IOW('b', 129, __u32)
+
+/* The size of the buffer mapping of binder_open() when none is given. */
+#define BINDER_TEST_MAP_SIZE	(128 * 1024)
+
+/* The ptr and cookie of the context manager node. */
+#define BINDER_TEST_MGR_PTR	0x1000
+#define BINDER_TEST_MGR_COOKIE	0x2000
+
+/* A binderfs instance mounted in a private mount namespace. */
+struct binder_fs {
+	char root[PATH_MAX];
+};
+
+/* A binder fd, its buffer mapping and the unconsumed part of the last read. */
+struct binder_conn {
+	int fd;
+	void *map;
+	size_t map_size;
+	char rbuf[1024];
+	size_t rpos;
+	size_t rlen;
+	/* Set once BR_SPAWN_LOOPER has been read. */
+	bool spawn_looper;
+};
+
+/* A transaction or reply to send. */
+struct binder_txn {
+	__u32 handle;
+	__u32 code;
+	__u32 flags;
+	const void *data;
+	size_t data_size;
+	const binder_size_t *offsets;
+	size_t offsets_count;
+	/* Space for BINDER_TYPE_PTR buffers. Sent with BC_TRANSACTION_SG if set. */
+	size_t buffers_size;
+};
+
+typedef int (*binder_server_fn)(struct binder_conn *conn, void *arg);
+
+int binder_fs_mount(struct binder_fs *fs);
+void binder_fs_umount(struct binder_fs *fs);
+int binder_fs_add_device(struct binder_fs *fs, const char *name, char *path, size_t size);
+bool binder_has_extensions(const char *path);
+
+int binder_open(struct binder_conn *conn, const char *path, size_t map_size, int flags);
+void binder_close(struct binder_conn *conn);
+int binder_become_manager(struct binder_conn *conn, __u32 flags);
+
+int binder_write(struct binder_conn *conn, const void *data, size_t size);
+int binder_write_cmd(struct binder_conn *conn, __u32 cmd, const void *arg, size_t size);
+int binder_enter_looper(struct binder_conn *conn);
+int binder_free_buffer(struct binder_conn *conn, binder_uintptr_t buffer);
+
+int binder_read_cmd(struct binder_conn *conn, __u32 *cmd, void *payload, size_t size);
+int binder_wait(struct binder_conn *conn, __u32 *cmd, void *payload, size_t size);
+
+int binder_send(struct binder_conn *conn, const struct binder_txn *txn, bool reply);
+int binder_transact(struct binder_conn *conn, const struct binder_txn *txn, __u32 *result);
+int binder_call(struct binder_conn *conn, const struct binder_txn *txn,
+		struct binder_transaction_data *reply, __u32 *result);
+int binder_reply(struct binder_conn *conn, const struct binder_transaction_data *tr,
+		 const void *data, size_t size, __u32 *result);
+
+pid_t binder_start_server(const char *path, __u32 node_flags, binder_server_fn fn, void *arg);
+int binder_wait_server(pid_t pid);
+
+#endif /* __SELFTEST_BINDER_UTIL_H */